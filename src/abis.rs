@@ -0,0 +1,382 @@
+use candid::CandidType;
+use ethers_core::abi::{
+    decode, Component, Contract, Event, Function, FunctionExt, Log, RawAbi, Token,
+};
+use ethers_core::types::{Address, H256};
+use ethers_core::utils::to_checksum;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+use crate::error::EthRpcError;
+use crate::eth_rpc::{self, CallOptions};
+use crate::events::{anonymous_event, decode_anonymous_log, decode_log};
+use crate::named::{self, DecodedEvent, NamedToken};
+use crate::providers::expected_chain_id;
+use crate::util::JsonOutputFormat;
+
+// Repository of contracts verified by Sourcify
+const SOURCIFY_REPO_URL: &str = "https://repo.sourcify.dev/contracts/full_match";
+// Contract metadata embeds the ABI and compiler settings, but not the sources
+const MAX_ABI_BYTES: u64 = 1024 * 1024;
+
+/// A contract ABI along with the parameter component names which `Contract` discards
+pub struct NamedAbi {
+    pub contract: Contract,
+    outputs: BTreeMap<String, Vec<Component>>,
+    event_inputs: BTreeMap<H256, Vec<Component>>,
+}
+
+impl NamedAbi {
+    /// Parse a JSON ABI
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        let contract = serde_json::from_str::<Contract>(json)?;
+        let mut outputs = BTreeMap::new();
+        let mut event_inputs = BTreeMap::new();
+        for item in serde_json::from_str::<RawAbi>(json)? {
+            // Parse the item on its own to find the signature `Contract` knows it by
+            let single = serde_json::from_value::<Contract>(serde_json::json!([item]))?;
+            if let Some(function) = single.functions().next() {
+                outputs.insert(function.abi_signature(), item.outputs);
+            } else if let Some(event) = single.events().next() {
+                event_inputs.insert(event.signature(), item.inputs);
+            }
+        }
+        Ok(Self {
+            contract,
+            outputs,
+            event_inputs,
+        })
+    }
+
+    fn output_components(&self, function: &Function) -> &[Component] {
+        self.outputs
+            .get(&function.abi_signature())
+            .map_or(&[][..], |outputs| outputs)
+    }
+
+    /// Name a function's decoded outputs, rendering values in the given format
+    pub fn name_outputs(
+        &self,
+        function: &Function,
+        tokens: &[Token],
+        format: JsonOutputFormat,
+    ) -> Vec<NamedToken> {
+        let kinds = function
+            .outputs
+            .iter()
+            .map(|output| output.kind.clone())
+            .collect::<Vec<_>>();
+        named::named_tokens_with(&kinds, self.output_components(function), tokens, format)
+    }
+
+    /// Decode a function's return data into named outputs
+    pub fn decode_output_named(
+        &self,
+        function: &Function,
+        data: &[u8],
+    ) -> Result<Vec<NamedToken>, ethers_core::abi::Error> {
+        named::decode_output_named(function, self.output_components(function), data)
+    }
+
+    // Name the params of a log decoded as an event
+    fn name_log(&self, event: &Event, log: Log) -> DecodedEvent {
+        let inputs = self
+            .event_inputs
+            .get(&event.signature())
+            .map_or(&[][..], |inputs| inputs);
+        let kinds = event
+            .inputs
+            .iter()
+            .map(|input| input.kind.clone())
+            .collect::<Vec<_>>();
+        let tokens = log
+            .params
+            .into_iter()
+            .map(|param| param.value)
+            .collect::<Vec<_>>();
+        DecodedEvent {
+            name: event.name.clone(),
+            params: named::named_tokens(&kinds, inputs, &tokens),
+        }
+    }
+
+    /// Decode a log into an event with named parameters
+    pub fn decode_log_named(
+        &self,
+        topics: Vec<H256>,
+        data: Vec<u8>,
+    ) -> Result<DecodedEvent, ethers_core::abi::Error> {
+        let signature = *topics.first().ok_or(ethers_core::abi::Error::InvalidData)?;
+        let log = decode_log(&self.contract, topics, data)?;
+        let event = self
+            .contract
+            .events()
+            .find(|event| !event.anonymous && event.signature() == signature)
+            .ok_or(ethers_core::abi::Error::InvalidData)?;
+        Ok(self.name_log(event, log))
+    }
+
+    /// Decode a log emitted by the anonymous event with the given name into an event
+    /// with named parameters (see [`decode_anonymous_log`])
+    pub fn decode_anonymous_log_named(
+        &self,
+        event_name: &str,
+        topics: Vec<H256>,
+        data: Vec<u8>,
+    ) -> Result<DecodedEvent, ethers_core::abi::Error> {
+        let event = anonymous_event(&self.contract, event_name, topics.len())?;
+        let log = decode_anonymous_log(&self.contract, event_name, topics, data)?;
+        Ok(self.name_log(event, log))
+    }
+}
+
+// ABIs available to the generic endpoints, by name
+const BUILTIN_ABIS: &[(&str, &str)] = &[
+    ("erc721", include_str!("../abi/erc721.json")),
+    ("erc1155", include_str!("../abi/erc1155.json")),
+    ("weth", include_str!("../abi/weth.json")),
+    ("governor", include_str!("../abi/governor.json")),
+];
+
+thread_local! {
+    static ABIS: BTreeMap<&'static str, Rc<NamedAbi>> = BUILTIN_ABIS
+        .iter()
+        .map(|(name, json)| match NamedAbi::from_json(json) {
+            Ok(abi) => (*name, Rc::new(abi)),
+            Err(err) => panic!("Error loading ABI {:?}: {}", name, err),
+        })
+        .collect();
+}
+
+thread_local! {
+    // ABIs fetched from a public source, by network and contract address
+    static FETCHED_ABIS: RefCell<BTreeMap<(String, Address), Contract>> = RefCell::default();
+}
+
+/// Public source of the ABIs of verified contracts
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum AbiSource {
+    /// Sourcify's repository of fully matched contracts, which needs no API key
+    Sourcify,
+    /// An Etherscan-compatible API (`module=contract&action=getabi`), given by its
+    /// base URL. The URL may contain `{{secret:NAME}}` placeholders, e.g. for the API key.
+    Etherscan { api_url: String },
+}
+
+// Sourcify's `metadata.json`, of which only the ABI is used
+#[derive(Deserialize)]
+struct SourcifyMetadata {
+    output: SourcifyOutput,
+}
+
+#[derive(Deserialize)]
+struct SourcifyOutput {
+    abi: serde_json::Value,
+}
+
+// Etherscan API response; `result` holds the ABI as a JSON string, or an error message
+#[derive(Deserialize)]
+struct EtherscanResponse {
+    status: String,
+    result: String,
+}
+
+/// Fetch the ABI of a verified contract from a public source.
+/// Fetched ABIs are cached for the lifetime of the canister's heap.
+pub async fn fetch_abi(
+    network: &str,
+    address: String,
+    source: AbiSource,
+) -> Result<Contract, EthRpcError> {
+    let address: Address = address
+        .parse()
+        .map_err(|_| EthRpcError::InvalidInput("Invalid contract address".to_string()))?;
+    let key = (network.to_string(), address);
+    if let Some(contract) = FETCHED_ABIS.with(|abis| abis.borrow().get(&key).cloned()) {
+        return Ok(contract);
+    }
+
+    let options = CallOptions::default();
+    let json = match source {
+        AbiSource::Sourcify => {
+            let chain_id = match expected_chain_id(network) {
+                Some(chain_id) => chain_id,
+                None => eth_rpc::chain_id(network).await?,
+            };
+            let url = format!(
+                "{}/{}/{}/metadata.json",
+                SOURCIFY_REPO_URL,
+                chain_id,
+                to_checksum(&address, None)
+            );
+            let body = eth_rpc::http_get(&url, MAX_ABI_BYTES, &options).await?;
+            let metadata = serde_json::from_str::<SourcifyMetadata>(&body).map_err(|_| {
+                EthRpcError::InvalidResponse("Malformed Sourcify metadata".to_string())
+            })?;
+            metadata.output.abi.to_string()
+        }
+        AbiSource::Etherscan { api_url } => {
+            let separator = if api_url.contains('?') { '&' } else { '?' };
+            let url = format!(
+                "{}{}module=contract&action=getabi&address={:?}",
+                api_url, separator, address
+            );
+            let body = eth_rpc::http_get(&url, MAX_ABI_BYTES, &options).await?;
+            let response = serde_json::from_str::<EtherscanResponse>(&body).map_err(|_| {
+                EthRpcError::InvalidResponse("Malformed Etherscan response".to_string())
+            })?;
+            if response.status != "1" {
+                return Err(EthRpcError::InvalidResponse(response.result));
+            }
+            response.result
+        }
+    };
+    let contract = serde_json::from_str::<Contract>(&json)
+        .map_err(|err| EthRpcError::AbiDecode(format!("Invalid ABI: {}", err)))?;
+    FETCHED_ABIS.with(|abis| abis.borrow_mut().insert(key, contract.clone()));
+    Ok(contract)
+}
+
+/// Look up a registered ABI by name
+pub fn registered_abi(name: &str) -> Option<Rc<NamedAbi>> {
+    ABIS.with(|abis| abis.get(name).cloned())
+}
+
+/// Decode the constructor arguments appended to a contract's creation bytecode
+/// in its deployment input (the data of the contract-creation transaction)
+pub fn decode_constructor_args(
+    abi: &Contract,
+    creation_code: &[u8],
+    deployed_code: &[u8],
+) -> Result<Vec<Token>, EthRpcError> {
+    let encoded_args = deployed_code.strip_prefix(creation_code).ok_or_else(|| {
+        EthRpcError::InvalidInput(
+            "Deployment input does not start with the creation code".to_string(),
+        )
+    })?;
+    let kinds = abi
+        .constructor()
+        .map(|constructor| {
+            constructor
+                .inputs
+                .iter()
+                .map(|input| input.kind.clone())
+                .collect::<Vec<_>>()
+        })
+        .unwrap_or_default();
+    if kinds.is_empty() && !encoded_args.is_empty() {
+        return Err(EthRpcError::AbiDecode(format!(
+            "Found {} bytes of arguments for a constructor without inputs",
+            encoded_args.len()
+        )));
+    }
+    decode(&kinds, encoded_args).map_err(|err| EthRpcError::AbiDecode(err.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::from_hex;
+    use serde_json::json;
+
+    #[test]
+    fn builtin_abis_parse() {
+        for (name, json) in BUILTIN_ABIS {
+            assert!(NamedAbi::from_json(json).is_ok(), "{} doesn't parse", name);
+            assert!(registered_abi(name).is_some(), "{} isn't registered", name);
+        }
+        assert!(registered_abi("erc20").is_none());
+    }
+
+    // The `slot0()` getter of a Uniswap V3 pool: several flat named outputs
+    const SLOT0_ABI: &str = r#"[{
+        "type": "function",
+        "name": "slot0",
+        "stateMutability": "view",
+        "inputs": [],
+        "outputs": [
+            {"name": "sqrtPriceX96", "type": "uint160", "internalType": "uint160"},
+            {"name": "tick", "type": "int24", "internalType": "int24"},
+            {"name": "observationIndex", "type": "uint16", "internalType": "uint16"},
+            {"name": "observationCardinality", "type": "uint16", "internalType": "uint16"},
+            {"name": "observationCardinalityNext", "type": "uint16", "internalType": "uint16"},
+            {"name": "feeProtocol", "type": "uint8", "internalType": "uint8"},
+            {"name": "unlocked", "type": "bool", "internalType": "bool"}
+        ]
+    }]"#;
+
+    #[test]
+    fn decodes_slot0_outputs_with_names() {
+        let abi = NamedAbi::from_json(SLOT0_ABI).unwrap();
+        let function = abi.contract.function("slot0").unwrap();
+        let data = from_hex(concat!(
+            "0x",
+            "0000000000000000000000000000000000004a48e3e9f45a65511b5168aa8c4a",
+            "fffffffffffffffffffffffffffffffffffffffffffffffffffffffffffcfd42",
+            "000000000000000000000000000000000000000000000000000000000000001c",
+            "00000000000000000000000000000000000000000000000000000000000002d3",
+            "00000000000000000000000000000000000000000000000000000000000002d3",
+            "0000000000000000000000000000000000000000000000000000000000000000",
+            "0000000000000000000000000000000000000000000000000000000000000001",
+        ))
+        .unwrap();
+
+        let outputs = abi.decode_output_named(function, &data).unwrap();
+        assert_eq!(
+            serde_json::to_value(outputs).unwrap(),
+            json!([
+                {
+                    "name": "sqrtPriceX96",
+                    "type": "uint160",
+                    "value": "1506673274302120988651364689808458",
+                },
+                {"name": "tick", "type": "int24", "value": "-197310"},
+                {"name": "observationIndex", "type": "uint16", "value": "28"},
+                {"name": "observationCardinality", "type": "uint16", "value": "723"},
+                {"name": "observationCardinalityNext", "type": "uint16", "value": "723"},
+                {"name": "feeProtocol", "type": "uint8", "value": "0"},
+                {"name": "unlocked", "type": "bool", "value": true},
+            ])
+        );
+    }
+
+    #[test]
+    fn names_anonymous_event_params() {
+        let abi = NamedAbi::from_json(
+            r#"[{
+                "type": "event",
+                "name": "LogNote",
+                "anonymous": true,
+                "inputs": [
+                    {"name": "guy", "type": "address", "indexed": true},
+                    {"name": "wad", "type": "uint256", "indexed": false}
+                ]
+            }]"#,
+        )
+        .unwrap();
+        let topics = vec![H256::from(ethers_core::types::Address::repeat_byte(0x11))];
+        let data =
+            from_hex("0x0000000000000000000000000000000000000000000000000000000000000005").unwrap();
+
+        let event = abi
+            .decode_anonymous_log_named("LogNote", topics.clone(), data.clone())
+            .unwrap();
+        assert_eq!(
+            serde_json::to_value(event).unwrap(),
+            json!({
+                "name": "LogNote",
+                "params": [
+                    {
+                        "name": "guy",
+                        "type": "address",
+                        "value": "0x1111111111111111111111111111111111111111",
+                    },
+                    {"name": "wad", "type": "uint256", "value": "5"},
+                ],
+            })
+        );
+        assert!(abi.decode_log_named(topics, data).is_err());
+    }
+}
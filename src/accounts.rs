@@ -0,0 +1,227 @@
+use candid::{CandidType, Principal};
+use ethers_core::types::Address;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::clock;
+use crate::ecdsa::derive_address;
+use crate::eth_rpc::{self, BlockTag};
+use crate::util::to_hex;
+
+/// How an account's key is derived from the canister's threshold ECDSA key
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum Derivation {
+    /// The canister's root key
+    Root,
+    /// A key derived from the creating caller's principal
+    Caller,
+    /// A key derived from an explicit derivation path
+    Custom(Vec<Vec<u8>>),
+}
+
+impl Derivation {
+    fn derivation_path(&self, caller: Principal) -> Vec<Vec<u8>> {
+        match self {
+            Derivation::Root => vec![],
+            Derivation::Caller => vec![caller.as_slice().to_vec()],
+            Derivation::Custom(path) => path.clone(),
+        }
+    }
+}
+
+/// An Ethereum account controlled by the canister on a specific network
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct EthAccount {
+    pub network: String,
+    pub chain_id: u64,
+    pub derivation_path: Vec<Vec<u8>>,
+    pub address: String,
+    pub nonce: Option<u64>,
+    pub balance_wei: Option<String>,
+    pub balance_updated_at: Option<u64>,
+    pub pending_transactions: Vec<String>,
+}
+
+/// Account details, as returned by `get_account`
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AccountSummary {
+    pub label: String,
+    pub network: String,
+    pub chain_id: u64,
+    pub address: String,
+    pub nonce: Option<u64>,
+    pub balance_wei: Option<String>,
+    pub balance_age_ns: Option<u64>,
+    pub pending_transactions: u64,
+}
+
+// Accounts by label
+thread_local! {
+    static ACCOUNTS: RefCell<BTreeMap<String, EthAccount>> = RefCell::default();
+}
+
+/// Look up an account by label
+pub fn account(label: &str) -> Result<EthAccount, String> {
+    ACCOUNTS
+        .with(|accounts| accounts.borrow().get(label).cloned())
+        .ok_or_else(|| format!("Account not found: {}", label))
+}
+
+/// Create an account, deriving its address and fetching the network's chain id
+pub async fn create_account(
+    label: String,
+    network: String,
+    derivation: Derivation,
+) -> Result<AccountSummary, String> {
+    if ACCOUNTS.with(|accounts| accounts.borrow().contains_key(&label)) {
+        return Err(format!("Account already exists: {}", label));
+    }
+    let derivation_path = derivation.derivation_path(ic_cdk::caller());
+    let address = derive_address(derivation_path.clone())
+        .await
+        .map_err(|err| err.to_string())?;
+    let chain_id = eth_rpc::chain_id(&network)
+        .await
+        .map_err(|err| err.to_string())?;
+    let account = EthAccount {
+        network,
+        chain_id,
+        derivation_path,
+        address: to_hex(address.as_bytes()),
+        nonce: None,
+        balance_wei: None,
+        balance_updated_at: None,
+        pending_transactions: vec![],
+    };
+    ACCOUNTS.with(|accounts| {
+        let mut accounts = accounts.borrow_mut();
+        // Another call may have created the account while awaiting
+        if accounts.contains_key(&label) {
+            return Err(format!("Account already exists: {}", label));
+        }
+        accounts.insert(label.clone(), account.clone());
+        Ok(())
+    })?;
+    Ok(summary(label, account))
+}
+
+/// Refresh an account's nonce and balance from the network
+pub async fn refresh_account(label: String) -> Result<AccountSummary, String> {
+    let account = account(&label)?;
+    let address: Address = account.address.parse().map_err(|_| "Invalid address")?;
+    let nonce = eth_rpc::get_transaction_count(&account.network, address, BlockTag::Latest)
+        .await
+        .map_err(|err| err.to_string())?;
+    let balance = eth_rpc::get_balance(&account.network, address, BlockTag::Latest)
+        .await
+        .map_err(|err| err.to_string())?;
+    ACCOUNTS.with(|accounts| {
+        let mut accounts = accounts.borrow_mut();
+        let account = accounts
+            .get_mut(&label)
+            .ok_or_else(|| format!("Account not found: {}", label))?;
+        account.nonce = Some(nonce);
+        account.balance_wei = Some(balance.to_string());
+        account.balance_updated_at = Some(clock::now());
+        Ok(summary(label.clone(), account.clone()))
+    })
+}
+
+/// Reserve `count` consecutive nonces for an account, starting at the later of
+/// the account's next known nonce and the network's pending transaction count
+pub async fn reserve_nonces(label: &str, count: u64) -> Result<u64, String> {
+    let account = account(label)?;
+    let address: Address = account.address.parse().map_err(|_| "Invalid address")?;
+    let pending = eth_rpc::get_transaction_count(&account.network, address, BlockTag::Pending)
+        .await
+        .map_err(|err| err.to_string())?;
+    ACCOUNTS.with(|accounts| {
+        let mut accounts = accounts.borrow_mut();
+        let account = accounts
+            .get_mut(label)
+            .ok_or_else(|| format!("Account not found: {}", label))?;
+        let first = account.nonce.unwrap_or_default().max(pending);
+        account.nonce = Some(first + count);
+        Ok(first)
+    })
+}
+
+/// Release reserved nonces `from..to` which will not be used, if no later nonce
+/// has been reserved since (otherwise the gap must be filled by a later transaction)
+pub fn release_nonces(label: &str, from: u64, to: u64) {
+    ACCOUNTS.with(|accounts| {
+        if let Some(account) = accounts.borrow_mut().get_mut(label) {
+            if account.nonce == Some(to) {
+                account.nonce = Some(from);
+            }
+        }
+    })
+}
+
+/// Record a broadcast transaction of an account as pending
+pub fn add_pending_transaction(label: &str, tx_hash: String) {
+    ACCOUNTS.with(|accounts| {
+        if let Some(account) = accounts.borrow_mut().get_mut(label) {
+            account.pending_transactions.push(tx_hash);
+        }
+    })
+}
+
+/// Remove a transaction of an account from its pending transactions
+pub fn remove_pending_transaction(label: &str, tx_hash: &str) {
+    ACCOUNTS.with(|accounts| {
+        if let Some(account) = accounts.borrow_mut().get_mut(label) {
+            account
+                .pending_transactions
+                .retain(|pending| pending != tx_hash);
+        }
+    })
+}
+
+/// Summary of an account by label
+pub fn get_account(label: String) -> Option<AccountSummary> {
+    account(&label).ok().map(|account| summary(label, account))
+}
+
+/// Delete an account which has no pending transactions
+pub fn delete_account(label: &str) -> Result<(), String> {
+    ACCOUNTS.with(|accounts| {
+        let mut accounts = accounts.borrow_mut();
+        let account = accounts
+            .get(label)
+            .ok_or_else(|| format!("Account not found: {}", label))?;
+        if !account.pending_transactions.is_empty() {
+            return Err(format!(
+                "Account {} has pending transactions: {}",
+                label,
+                account.pending_transactions.join(", ")
+            ));
+        }
+        accounts.remove(label);
+        Ok(())
+    })
+}
+
+fn summary(label: String, account: EthAccount) -> AccountSummary {
+    AccountSummary {
+        label,
+        network: account.network,
+        chain_id: account.chain_id,
+        address: account.address,
+        nonce: account.nonce,
+        balance_wei: account.balance_wei,
+        balance_age_ns: account
+            .balance_updated_at
+            .map(|updated_at| clock::now().saturating_sub(updated_at)),
+        pending_transactions: account.pending_transactions.len() as u64,
+    }
+}
+
+pub fn snapshot() -> BTreeMap<String, EthAccount> {
+    ACCOUNTS.with(|accounts| accounts.borrow().clone())
+}
+
+pub fn restore(state: BTreeMap<String, EthAccount>) {
+    ACCOUNTS.with(|accounts| *accounts.borrow_mut() = state);
+}
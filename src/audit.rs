@@ -0,0 +1,142 @@
+use candid::CandidType;
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::H256;
+use ethers_core::utils::{keccak256, rlp::Rlp};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+
+use crate::clock;
+use crate::error::EthRpcError;
+use crate::util::to_hex;
+
+// Number of transactions kept in the audit log by default
+const DEFAULT_CAPACITY: u32 = 1000;
+
+/// Configuration of the transaction audit log
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct AuditConfig {
+    /// Record each transaction the canister sends
+    pub enabled: bool,
+    /// Number of records kept; the oldest are dropped beyond it
+    pub capacity: u32,
+}
+
+impl Default for AuditConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            capacity: DEFAULT_CAPACITY,
+        }
+    }
+}
+
+/// A transaction sent by the canister, as recorded in the audit log
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct AuditRecord {
+    pub timestamp: u64,
+    pub network: String,
+    pub from: Option<String>,
+    /// Recipient (none for contract creations)
+    pub to: Option<String>,
+    pub nonce: Option<String>,
+    pub value_wei: Option<String>,
+    /// Keccak-256 hash of the calldata
+    pub calldata_hash: String,
+    pub tx_hash: String,
+    /// Error with which the transaction was rejected, if it was
+    pub error: Option<String>,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct AuditState {
+    config: AuditConfig,
+    log: VecDeque<AuditRecord>,
+}
+
+thread_local! {
+    static AUDIT: RefCell<AuditState> = RefCell::default();
+}
+
+/// Configure the audit log, dropping the oldest records beyond the new capacity
+pub fn set_config(config: AuditConfig) {
+    AUDIT.with(|audit| {
+        let mut audit = audit.borrow_mut();
+        audit.config = config;
+        truncate(&mut audit);
+    })
+}
+
+pub fn config() -> AuditConfig {
+    AUDIT.with(|audit| audit.borrow().config.clone())
+}
+
+fn truncate(audit: &mut AuditState) {
+    while audit.log.len() > audit.config.capacity as usize {
+        audit.log.pop_front();
+    }
+}
+
+/// Record a signed transaction sent to a network, if the audit log is enabled
+pub fn record(network: &str, raw: &[u8], result: &Result<H256, EthRpcError>) {
+    if !config().enabled {
+        return;
+    }
+    let decoded = TypedTransaction::decode_signed(&Rlp::new(raw))
+        .ok()
+        .map(|(tx, _)| tx);
+    let data = decoded
+        .as_ref()
+        .and_then(|tx| tx.data().cloned())
+        .unwrap_or_default();
+    let record = AuditRecord {
+        timestamp: clock::now(),
+        network: network.to_string(),
+        from: decoded
+            .as_ref()
+            .and_then(|tx| tx.from())
+            .map(|from| to_hex(from.as_bytes())),
+        to: decoded
+            .as_ref()
+            .and_then(|tx| tx.to_addr())
+            .map(|to| to_hex(to.as_bytes())),
+        nonce: decoded
+            .as_ref()
+            .and_then(|tx| tx.nonce())
+            .map(|nonce| nonce.to_string()),
+        value_wei: decoded
+            .as_ref()
+            .and_then(|tx| tx.value())
+            .map(|value| value.to_string()),
+        calldata_hash: to_hex(&keccak256(&data)),
+        tx_hash: to_hex(&keccak256(raw)),
+        error: result.as_ref().err().map(|err| err.to_string()),
+    };
+    AUDIT.with(|audit| {
+        let mut audit = audit.borrow_mut();
+        audit.log.push_back(record);
+        truncate(&mut audit);
+    })
+}
+
+/// The most recent transactions in the audit log, newest first
+pub fn transaction_history(limit: usize) -> Vec<AuditRecord> {
+    AUDIT.with(|audit| {
+        audit
+            .borrow()
+            .log
+            .iter()
+            .rev()
+            .take(limit)
+            .cloned()
+            .collect()
+    })
+}
+
+pub fn snapshot() -> AuditState {
+    AUDIT.with(|audit| audit.borrow().clone())
+}
+
+pub fn restore(state: AuditState) {
+    AUDIT.with(|audit| *audit.borrow_mut() = state);
+}
@@ -0,0 +1,88 @@
+use ethers_core::types::{Address, U256};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::clock;
+use crate::error::EthRpcError;
+use crate::eth_rpc::{simulate_call, BlockTag, CallOptions};
+
+// Upper bound on cached call results; the oldest result is evicted beyond it
+const MAX_ENTRIES: usize = 1000;
+
+// (network, contract, calldata, block)
+type CacheKey = (String, Address, Vec<u8>, BlockTag);
+
+struct CachedResult {
+    data: Vec<u8>,
+    fetched_at: u64,
+}
+
+thread_local! {
+    // Results of read-only contract calls, kept in heap memory only
+    static RESULTS: RefCell<BTreeMap<CacheKey, CachedResult>> = RefCell::default();
+}
+
+/// Result of a read-only call from the cache, if it was fetched at most
+/// `max_staleness_ns` ago
+pub fn lookup(
+    network: &str,
+    to: Address,
+    calldata: &[u8],
+    block: BlockTag,
+    max_staleness_ns: u64,
+) -> Option<Vec<u8>> {
+    let key = (network.to_string(), to, calldata.to_vec(), block);
+    let now = clock::now();
+    RESULTS.with(|results| {
+        results
+            .borrow()
+            .get(&key)
+            .filter(|cached| now.saturating_sub(cached.fetched_at) <= max_staleness_ns)
+            .map(|cached| cached.data.clone())
+    })
+}
+
+/// Cache the result of a read-only call, evicting the oldest result if the cache is full
+pub fn store(network: &str, to: Address, calldata: &[u8], block: BlockTag, data: Vec<u8>) {
+    let key = (network.to_string(), to, calldata.to_vec(), block);
+    RESULTS.with(|results| {
+        let mut results = results.borrow_mut();
+        if results.len() >= MAX_ENTRIES && !results.contains_key(&key) {
+            let oldest = results
+                .iter()
+                .min_by_key(|(_, cached)| cached.fetched_at)
+                .map(|(key, _)| key.clone());
+            if let Some(oldest) = oldest {
+                results.remove(&oldest);
+            }
+        }
+        results.insert(
+            key,
+            CachedResult {
+                data,
+                fetched_at: clock::now(),
+            },
+        );
+    });
+}
+
+/// Return the result of a read-only call from the cache if it was fetched at most
+/// `max_staleness_ns` ago, otherwise perform the call and refresh the cache
+pub async fn get_or_fetch(
+    network: &str,
+    to: Address,
+    calldata: &[u8],
+    block: BlockTag,
+    max_staleness_ns: u64,
+) -> Result<Vec<u8>, EthRpcError> {
+    if let Some(data) = lookup(network, to, calldata, block, max_staleness_ns) {
+        return Ok(data);
+    }
+    let options = CallOptions {
+        block,
+        ..Default::default()
+    };
+    let data = simulate_call(network, to, calldata, U256::zero(), &options).await?;
+    store(network, to, calldata, block, data.clone());
+    Ok(data)
+}
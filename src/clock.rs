@@ -0,0 +1,22 @@
+// Current time in nanoseconds since the epoch. Outside a canister (in tests) the
+// system time is unavailable, so tests use a clock which they set themselves.
+
+#[cfg(not(test))]
+pub fn now() -> u64 {
+    ic_cdk::api::time()
+}
+
+#[cfg(test)]
+thread_local! {
+    static NOW: std::cell::Cell<u64> = const { std::cell::Cell::new(0) };
+}
+
+#[cfg(test)]
+pub fn now() -> u64 {
+    NOW.with(|now| now.get())
+}
+
+#[cfg(test)]
+pub fn set_now(time: u64) {
+    NOW.with(|now| now.set(time));
+}
@@ -0,0 +1,138 @@
+use ethers_core::abi::Token;
+use ethers_core::types::{Address, U256};
+
+use crate::error::EthRpcError;
+
+/// Typed access to a decoded token, erroring clearly on a type mismatch.
+///
+/// Arrays (including nested ones like `address[][]`) can be traversed with
+/// [`DecodedValue::as_array`] and [`DecodedValue::as_fixed_array`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DecodedValue<'a>(pub &'a Token);
+
+// Name of a token's kind, for type mismatch errors
+fn kind(token: &Token) -> &'static str {
+    match token {
+        Token::Address(_) => "address",
+        Token::FixedBytes(_) => "fixed bytes",
+        Token::Bytes(_) => "bytes",
+        Token::Int(_) => "int",
+        Token::Uint(_) => "uint",
+        Token::Bool(_) => "bool",
+        Token::String(_) => "string",
+        Token::FixedArray(_) => "fixed array",
+        Token::Array(_) => "array",
+        Token::Tuple(_) => "tuple",
+    }
+}
+
+impl<'a> DecodedValue<'a> {
+    fn mismatch(&self, expected: &str) -> EthRpcError {
+        EthRpcError::AbiDecode(format!("Expected {}, found {}", expected, kind(self.0)))
+    }
+
+    /// Elements of a dynamic array (`T[]`)
+    pub fn as_array(&self) -> Result<impl Iterator<Item = DecodedValue<'a>>, EthRpcError> {
+        match self.0 {
+            Token::Array(tokens) => Ok(tokens.iter().map(DecodedValue)),
+            _ => Err(self.mismatch("array")),
+        }
+    }
+
+    /// Elements of a fixed-size array (`T[N]`)
+    pub fn as_fixed_array(&self) -> Result<impl Iterator<Item = DecodedValue<'a>>, EthRpcError> {
+        match self.0 {
+            Token::FixedArray(tokens) => Ok(tokens.iter().map(DecodedValue)),
+            _ => Err(self.mismatch("fixed array")),
+        }
+    }
+
+    /// Components of a tuple (struct)
+    pub fn as_tuple(&self) -> Result<impl Iterator<Item = DecodedValue<'a>>, EthRpcError> {
+        match self.0 {
+            Token::Tuple(tokens) => Ok(tokens.iter().map(DecodedValue)),
+            _ => Err(self.mismatch("tuple")),
+        }
+    }
+
+    pub fn as_uint(&self) -> Result<U256, EthRpcError> {
+        match self.0 {
+            Token::Uint(n) => Ok(*n),
+            _ => Err(self.mismatch("uint")),
+        }
+    }
+
+    pub fn as_address(&self) -> Result<Address, EthRpcError> {
+        match self.0 {
+            Token::Address(address) => Ok(*address),
+            _ => Err(self.mismatch("address")),
+        }
+    }
+
+    pub fn as_string(&self) -> Result<&'a str, EthRpcError> {
+        match self.0 {
+            Token::String(s) => Ok(s),
+            _ => Err(self.mismatch("string")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::abi::{self, ParamType};
+
+    #[test]
+    fn traverses_nested_arrays() {
+        let a = Address::repeat_byte(0xaa);
+        let b = Address::repeat_byte(0xbb);
+        let c = Address::repeat_byte(0xcc);
+        // Round trip through the ABI encoding, as a call result would arrive
+        let nested = Token::Array(vec![
+            Token::Array(vec![Token::Address(a), Token::Address(b)]),
+            Token::Array(vec![]),
+            Token::Array(vec![Token::Address(c)]),
+        ]);
+        let param_type = ParamType::Array(Box::new(ParamType::Array(Box::new(ParamType::Address))));
+        let tokens = abi::decode(&[param_type], &abi::encode(&[nested])).unwrap();
+
+        let addresses = DecodedValue(&tokens[0])
+            .as_array()
+            .unwrap()
+            .map(|inner| {
+                inner
+                    .as_array()?
+                    .map(|address| address.as_address())
+                    .collect::<Result<Vec<_>, _>>()
+            })
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(addresses, vec![vec![a, b], vec![], vec![c]]);
+    }
+
+    #[test]
+    fn reports_type_mismatches() {
+        let tokens = Token::Array(vec![Token::Array(vec![Token::Address(Address::zero())])]);
+        let inner = DecodedValue(&tokens).as_array().unwrap().next().unwrap();
+        let address = inner.as_array().unwrap().next().unwrap();
+
+        assert_eq!(
+            address.as_array().err(),
+            Some(EthRpcError::AbiDecode(
+                "Expected array, found address".to_string()
+            ))
+        );
+        assert_eq!(
+            inner.as_fixed_array().err(),
+            Some(EthRpcError::AbiDecode(
+                "Expected fixed array, found array".to_string()
+            ))
+        );
+        assert_eq!(
+            address.as_uint(),
+            Err(EthRpcError::AbiDecode(
+                "Expected uint, found address".to_string()
+            ))
+        );
+    }
+}
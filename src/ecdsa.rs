@@ -0,0 +1,97 @@
+use ethers_core::k256::ecdsa::VerifyingKey;
+use ethers_core::types::{Address, RecoveryMessage, Signature, H256, U256};
+use ethers_core::utils::public_key_to_address;
+use ic_cdk::api::management_canister::ecdsa::{
+    ecdsa_public_key, sign_with_ecdsa, EcdsaCurve, EcdsaKeyId, EcdsaPublicKeyArgument,
+    SignWithEcdsaArgument,
+};
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::error::EthRpcError;
+
+// Name of the threshold ECDSA key controlled by the canister
+const ECDSA_KEY_NAME: &str = "key_1";
+
+/// Cycles charged for a `sign_with_ecdsa` call (attached by `ic_cdk`)
+pub const SIGN_WITH_ECDSA_CYCLES: u128 = 26_153_846_153;
+
+/// Threshold ECDSA key used for addresses and signatures
+pub fn key_id() -> EcdsaKeyId {
+    EcdsaKeyId {
+        curve: EcdsaCurve::Secp256k1,
+        name: ECDSA_KEY_NAME.to_string(),
+    }
+}
+
+// (key name, derivation path)
+type AddressKey = (String, Vec<Vec<u8>>);
+
+thread_local! {
+    // Derived addresses, which never change for a canister
+    static ADDRESSES: RefCell<BTreeMap<AddressKey, Address>> = RefCell::default();
+}
+
+/// Ethereum address controlled by the canister for a derivation path and key.
+/// The address is derived once and cached.
+pub async fn canister_eth_address(
+    derivation_path: Vec<Vec<u8>>,
+    key_id: EcdsaKeyId,
+) -> Result<Address, EthRpcError> {
+    let cache_key = (key_id.name.clone(), derivation_path.clone());
+    if let Some(address) = ADDRESSES.with(|addresses| addresses.borrow().get(&cache_key).copied()) {
+        return Ok(address);
+    }
+    let (response,) = ecdsa_public_key(EcdsaPublicKeyArgument {
+        canister_id: None,
+        derivation_path,
+        key_id,
+    })
+    .await
+    .map_err(|(code, msg)| EthRpcError::Ecdsa(format!("{:?} {}", code, msg)))?;
+    let public_key = VerifyingKey::from_sec1_bytes(&response.public_key)
+        .map_err(|_| EthRpcError::Ecdsa("Invalid public key".to_string()))?;
+    let address = public_key_to_address(&public_key);
+    ADDRESSES.with(|addresses| addresses.borrow_mut().insert(cache_key, address));
+    Ok(address)
+}
+
+/// Derive the Ethereum address controlled by the canister for a derivation path
+pub async fn derive_address(derivation_path: Vec<Vec<u8>>) -> Result<Address, EthRpcError> {
+    canister_eth_address(derivation_path, key_id()).await
+}
+
+/// Sign a 32-byte digest with the key for a derivation path, returning an
+/// Ethereum signature (with the recovery id as `v` = 27 or 28)
+pub async fn sign_digest(
+    derivation_path: Vec<Vec<u8>>,
+    digest: [u8; 32],
+) -> Result<Signature, EthRpcError> {
+    let address = derive_address(derivation_path.clone()).await?;
+    let (response,) = sign_with_ecdsa(SignWithEcdsaArgument {
+        message_hash: digest.to_vec(),
+        derivation_path,
+        key_id: key_id(),
+    })
+    .await
+    .map_err(|(code, msg)| EthRpcError::Ecdsa(format!("{:?} {}", code, msg)))?;
+    if response.signature.len() != 64 {
+        return Err(EthRpcError::Ecdsa("Invalid signature length".to_string()));
+    }
+    // The management canister doesn't return the recovery id, so find the one
+    // which recovers the signing address
+    [27, 28]
+        .into_iter()
+        .map(|v| Signature {
+            r: U256::from_big_endian(&response.signature[..32]),
+            s: U256::from_big_endian(&response.signature[32..]),
+            v,
+        })
+        .find(|signature| {
+            signature
+                .recover(RecoveryMessage::Hash(H256(digest)))
+                .is_ok_and(|recovered| recovered == address)
+        })
+        .ok_or_else(|| EthRpcError::Ecdsa("Signature does not recover the address".to_string()))
+}
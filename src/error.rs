@@ -0,0 +1,179 @@
+use candid::CandidType;
+use serde::Deserialize;
+use std::fmt;
+
+/// Errors returned by JSON-RPC calls to Ethereum providers
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum EthRpcError {
+    /// No usable provider is configured for the network
+    UnsupportedNetwork(String),
+    /// The provider configuration could not be turned into a request
+    ProviderConfig(String),
+    /// The HTTP outcall was rejected
+    HttpOutcall { code: String, message: String },
+    /// The provider's response could not be parsed
+    InvalidResponse(String),
+    /// The provider returned a JSON-RPC error
+    JsonRpc { code: i64, message: String },
+    /// The threshold ECDSA key could not be used
+    Ecdsa(String),
+    /// A single batch entry exceeds the maximum request size
+    BatchTooLarge { size: usize, limit: usize },
+    /// A variable could not be read using a storage layout
+    StorageLayout(String),
+    /// The call would revert, with the decoded revert reason
+    Reverted(String),
+    /// The provider no longer has the state of the requested block (e.g. it was pruned)
+    StateUnavailable(String),
+    /// Attaching the outcall's cycles would leave the canister below its minimum balance
+    InsufficientCycles { balance: u128, required: u128 },
+    /// A transaction was not sent because its fee quote no longer holds
+    QuoteRejected(String),
+    /// A function or event name matches several overloads; use a full signature instead
+    AmbiguousOverload {
+        name: String,
+        overloads: Vec<Overload>,
+    },
+    /// A caller-provided argument is malformed or too large
+    InvalidInput(String),
+    /// Data could not be decoded using an ABI
+    AbiDecode(String),
+    /// The provider redirected the request, which outcalls don't follow;
+    /// the provider URL should be updated to the redirect's destination
+    Redirect { location: String },
+    /// The provider doesn't implement the JSON-RPC method (e.g. a newer method on an older node)
+    MethodNotSupported(String),
+    /// The response exceeded the outcall's maximum response size
+    ResponseTooLarge { limit: u64 },
+    /// A batch response has no response with the id of one of its requests
+    MissingBatchResponse { id: u64 },
+    /// A request body exceeds the maximum request size, so it was not sent
+    RequestTooLarge { size: usize, limit: usize },
+    /// Every outcall slot available to the call's priority is in use; retry later
+    OutcallLimitReached { in_flight: u32, limit: u32 },
+}
+
+/// One of the overloads of an ambiguous function or event name
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Overload {
+    /// Full signature, e.g. `transfer(address,uint256)`
+    pub signature: String,
+    /// Hex-encoded identifier as it appears on chain: the 4-byte selector of a
+    /// function, or the signature topic of an event
+    pub selector: String,
+}
+
+impl EthRpcError {
+    /// Short name of the error class, used for provider health statistics
+    pub fn class(&self) -> &'static str {
+        match self {
+            EthRpcError::UnsupportedNetwork(_) => "unsupported_network",
+            EthRpcError::ProviderConfig(_) => "provider_config",
+            EthRpcError::HttpOutcall { .. } => "http_outcall",
+            EthRpcError::InvalidResponse(_) => "invalid_response",
+            EthRpcError::JsonRpc { .. } => "json_rpc",
+            EthRpcError::Ecdsa(_) => "ecdsa",
+            EthRpcError::BatchTooLarge { .. } => "batch_too_large",
+            EthRpcError::StorageLayout(_) => "storage_layout",
+            EthRpcError::Reverted(_) => "reverted",
+            EthRpcError::StateUnavailable(_) => "state_unavailable",
+            EthRpcError::InsufficientCycles { .. } => "insufficient_cycles",
+            EthRpcError::QuoteRejected(_) => "quote_rejected",
+            EthRpcError::AmbiguousOverload { .. } => "ambiguous_overload",
+            EthRpcError::InvalidInput(_) => "invalid_input",
+            EthRpcError::AbiDecode(_) => "abi_decode",
+            EthRpcError::Redirect { .. } => "redirect",
+            EthRpcError::MethodNotSupported(_) => "method_not_supported",
+            EthRpcError::ResponseTooLarge { .. } => "response_too_large",
+            EthRpcError::MissingBatchResponse { .. } => "missing_batch_response",
+            EthRpcError::RequestTooLarge { .. } => "request_too_large",
+            EthRpcError::OutcallLimitReached { .. } => "outcall_limit_reached",
+        }
+    }
+
+    /// Whether the error indicates a faulty provider (as opposed to a failed call)
+    pub fn is_provider_failure(&self) -> bool {
+        matches!(
+            self,
+            EthRpcError::ProviderConfig(_)
+                | EthRpcError::HttpOutcall { .. }
+                | EthRpcError::InvalidResponse(_)
+                | EthRpcError::Redirect { .. }
+                | EthRpcError::MissingBatchResponse { .. }
+        )
+    }
+}
+
+impl fmt::Display for EthRpcError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            EthRpcError::UnsupportedNetwork(network) => {
+                write!(f, "Unsupported network: {}", network)
+            }
+            EthRpcError::ProviderConfig(message) => {
+                write!(f, "Provider configuration error: {}", message)
+            }
+            EthRpcError::HttpOutcall { code, message } => {
+                write!(f, "HTTP outcall rejected ({}): {}", code, message)
+            }
+            EthRpcError::InvalidResponse(message) => write!(f, "Invalid response: {}", message),
+            EthRpcError::JsonRpc { code, message } => {
+                write!(f, "JSON-RPC error code {}: {}", code, message)
+            }
+            EthRpcError::Ecdsa(message) => write!(f, "Threshold ECDSA error: {}", message),
+            EthRpcError::BatchTooLarge { size, limit } => write!(
+                f,
+                "Batch request of {} bytes exceeds the limit of {} bytes",
+                size, limit
+            ),
+            EthRpcError::StorageLayout(message) => write!(f, "Storage layout error: {}", message),
+            EthRpcError::Reverted(reason) => write!(f, "Execution reverted: {}", reason),
+            EthRpcError::StateUnavailable(message) => {
+                write!(f, "State unavailable at the requested block: {}", message)
+            }
+            EthRpcError::InsufficientCycles { balance, required } => write!(
+                f,
+                "Cycles balance of {} is below the {} required for the outcall",
+                balance, required
+            ),
+            EthRpcError::QuoteRejected(message) => write!(f, "Quote rejected: {}", message),
+            EthRpcError::AmbiguousOverload { name, overloads } => write!(
+                f,
+                "Found {} overloads of {}. Use one of: {}",
+                overloads.len(),
+                name,
+                overloads
+                    .iter()
+                    .map(|overload| format!("{:?} ({})", overload.signature, overload.selector))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+            EthRpcError::InvalidInput(message) => write!(f, "Invalid input: {}", message),
+            EthRpcError::AbiDecode(message) => write!(f, "ABI decoding error: {}", message),
+            EthRpcError::Redirect { location } => {
+                write!(f, "Provider redirected the request to {:?}", location)
+            }
+            EthRpcError::MethodNotSupported(message) => {
+                write!(f, "Method not supported by the provider: {}", message)
+            }
+            EthRpcError::ResponseTooLarge { limit } => {
+                write!(f, "Response exceeds the limit of {} bytes", limit)
+            }
+            EthRpcError::MissingBatchResponse { id } => {
+                write!(f, "Missing batch response for id {}", id)
+            }
+            EthRpcError::RequestTooLarge { size, limit } => write!(
+                f,
+                "Request of {} bytes exceeds the limit of {} bytes",
+                size, limit
+            ),
+            EthRpcError::OutcallLimitReached { in_flight, limit } => write!(
+                f,
+                "{} of {} outcall slots are in use; retry later",
+                in_flight, limit
+            ),
+        }
+    }
+}
+
+impl std::error::Error for EthRpcError {}
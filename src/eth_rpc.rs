@@ -1,42 +1,411 @@
-use ethers_core::abi::{Contract, FunctionExt, Token};
+use candid::CandidType;
+use ethers_core::abi::{Contract, Function, FunctionExt, ParamType, Token};
+use ethers_core::types::transaction::eip2930::AccessList;
+use ethers_core::types::{
+    Address, Block, Bytes, Filter, Log, Transaction, TransactionReceipt, TransactionRequest, H256,
+    U256, U64,
+};
 use ic_cdk::api::management_canister::http_request::{
-    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
-    TransformContext,
+    CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformArgs,
 };
-use serde::{Deserialize, Serialize};
-use std::cell::RefCell;
+use serde::{de::DeserializeOwned, Deserialize, Deserializer, Serialize, Serializer};
+use serde_json::Value;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::future::Future;
 
-use crate::util::{from_hex, to_hex};
+use crate::audit;
+use crate::cache;
+use crate::clock;
+use crate::decoded::DecodedValue;
+use crate::ecdsa::{canister_eth_address, key_id};
+use crate::error::{EthRpcError, Overload};
+use crate::health::{record_failure, record_success, select_providers};
+use crate::limiter::{self, Priority};
+use crate::providers::{archive_support, is_archive};
+use crate::secrets::{expand_secrets, redact};
+use crate::transport::{transform_context, transport};
+use crate::util::{from_hex, function_selector, to_hex};
 
 // Constants for HTTP call configuration
 const CYCLES_COST: u128 = 100_000_000;
 const MAX_BYTES: u64 = 2048;
+// Contract bytecode is up to 24KB, i.e. about 48KB of hex
+const MAX_CODE_BYTES: u64 = 64 * 1024;
+// Receipts include the transaction's logs
+const MAX_RECEIPT_BYTES: u64 = 64 * 1024;
+// Blocks (with transaction hashes only) list up to a few thousand transactions
+const MAX_BLOCK_BYTES: u64 = 256 * 1024;
+// Access lists hold an entry per touched account and storage slot
+const MAX_ACCESS_LIST_BYTES: u64 = 64 * 1024;
+const TRANSFORM_METHOD: &str = "handle_transform";
+const DEFAULT_MAX_REQUEST_BYTES: usize = 2_000_000;
+// Largest response an HTTP outcall may return
+const MAX_RESPONSE_BYTES: u64 = 2_000_000;
+
+// Constants for the HTTP outcall cost formula (13-node application subnet)
+const SUBNET_SIZE: u128 = 13;
+const OUTCALL_BASE_FEE: u128 = 3_000_000;
+const OUTCALL_PER_NODE_FEE: u128 = 60_000;
+const OUTCALL_REQUEST_BYTE_FEE: u128 = 400;
+const OUTCALL_RESPONSE_BYTE_FEE: u128 = 800;
+
+/// Options controlling how an outcall is made
+#[derive(Clone, Debug, Default)]
+pub struct CallOptions {
+    /// Attach a size-based cycles estimate instead of the flat `CYCLES_COST`
+    pub estimate_cycles: bool,
+    /// Maximum size of a request body (defaults to `DEFAULT_MAX_REQUEST_BYTES`, the system limit)
+    pub max_request_bytes: Option<usize>,
+    /// Priority used when taking an outcall slot
+    pub priority: Priority,
+    /// Block at which contract calls are executed
+    pub block: BlockTag,
+    /// What to do when a contract call's block state was pruned by the provider
+    pub state_fallback: StateFallback,
+    /// Cycles balance the canister must keep after paying for the outcall
+    pub min_cycles_balance: Option<u128>,
+    /// Make contract calls from the canister's address for this derivation path,
+    /// so that `msg.sender` matches transactions signed by the canister
+    pub from_derivation_path: Option<Vec<Vec<u8>>>,
+    /// Make contract calls from this address (e.g. an account's, which is known
+    /// without deriving it again), instead of `from_derivation_path`
+    pub from: Option<Address>,
+    /// Response size allowed for each entry of a batch (defaults to `MAX_BYTES`)
+    pub max_entry_response_bytes: Option<u64>,
+    /// Round a quantity result up in the transform, so that replicas which observe
+    /// slightly different values still reach consensus (see [`Rounding`])
+    pub rounding: Option<Rounding>,
+    /// Produce an authentication header from the serialized request body (e.g. an HMAC
+    /// signature expected by an RPC gateway), which is appended to the outcall's headers
+    pub sign_request: Option<fn(&[u8]) -> HttpHeader>,
+    /// Serve verbose contract calls from the call cache if the cached result was
+    /// fetched at most this long ago (in nanoseconds), and cache fresh results
+    /// (other calls use [`cache::get_or_fetch`])
+    pub max_cache_staleness_ns: Option<u64>,
+}
+
+/// Rounding of a quantity result, applied in the transform.
+///
+/// Replicas query providers at slightly different moments, so methods whose result
+/// changes from one block (or mempool update) to the next may return different values
+/// to each replica, and the outcall then fails consensus. Those methods need rounding:
+///
+/// - `eth_gasPrice` and `eth_maxPriorityFeePerGas`, whose suggestions follow the
+///   mempool. [`gas_price`] and [`max_priority_fee_per_gas`] round to
+///   [`FEE_SIGNIFICANT_DIGITS`] significant digits.
+/// - `eth_estimateGas` against the pending block, whose result depends on pending state.
+/// - `eth_getBalance` and `eth_getTransactionCount` against `latest` or `pending` are
+///   only stable if the account isn't active; query a fixed block where possible.
+///
+/// Results at a fixed block number (e.g. `eth_call` or `eth_getStorageAt`) and
+/// constants such as `eth_chainId` don't need rounding. Rounding is always upwards, so
+/// a rounded fee never underpays.
+///
+/// Single requests are rounded with [`CallOptions::rounding`], and each entry of a batch
+/// with its own [`BatchRequest::rounding`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Round up to a multiple of a fixed value
+    Multiple(u64),
+    /// Round up to this many significant decimal digits, so the rounding is relative
+    /// to the value: 1 gwei fees and 0.001 gwei fees (as on L2s) are both rounded
+    /// by at most one part in 10^(digits - 1)
+    SignificantDigits(u8),
+}
+
+// Transform context tags of each kind of rounding, and of the roundings of
+// batch entries by id
+const ROUNDING_MULTIPLE_TAG: u8 = 1;
+const ROUNDING_DIGITS_TAG: u8 = 2;
+const BATCH_ROUNDING_TAG: u8 = 3;
+
+impl Rounding {
+    /// Round a quantity up
+    pub fn apply(self, quantity: U256) -> U256 {
+        let unit = match self {
+            Rounding::Multiple(multiple) => U256::from(multiple.max(1)),
+            Rounding::SignificantDigits(digits) => {
+                let length = quantity.to_string().len();
+                match length.checked_sub(usize::from(digits.max(1))) {
+                    Some(excess) if excess > 0 => U256::exp10(excess),
+                    _ => return quantity,
+                }
+            }
+        };
+        let remainder = quantity % unit;
+        if remainder.is_zero() {
+            quantity
+        } else {
+            quantity.saturating_add(unit - remainder)
+        }
+    }
+
+    // Encoding of the rounding as the transform context
+    fn to_context(self) -> Vec<u8> {
+        match self {
+            Rounding::Multiple(multiple) => {
+                let mut context = vec![ROUNDING_MULTIPLE_TAG];
+                context.extend_from_slice(&multiple.to_be_bytes());
+                context
+            }
+            Rounding::SignificantDigits(digits) => vec![ROUNDING_DIGITS_TAG, digits],
+        }
+    }
+
+    fn from_context(context: &[u8]) -> Option<Self> {
+        match context.split_first()? {
+            (&ROUNDING_MULTIPLE_TAG, multiple) => Some(Rounding::Multiple(u64::from_be_bytes(
+                multiple.try_into().ok()?,
+            ))),
+            (&ROUNDING_DIGITS_TAG, &[digits]) => Some(Rounding::SignificantDigits(digits)),
+            _ => None,
+        }
+    }
+}
+
+// Transform context rounding the results of batch entries: the tag, followed by
+// each entry's id and the length and context of its rounding
+fn batch_rounding_context(entries: &[BatchEntry]) -> Vec<u8> {
+    let mut context = vec![BATCH_ROUNDING_TAG];
+    for entry in entries {
+        if let Some(rounding) = entry.rounding {
+            let rounding = rounding.to_context();
+            context.extend_from_slice(&entry.id.to_be_bytes());
+            context.push(rounding.len() as u8);
+            context.extend_from_slice(&rounding);
+        }
+    }
+    context
+}
+
+fn batch_rounding_from_context(mut context: &[u8]) -> Option<BTreeMap<u64, Rounding>> {
+    let mut roundings = BTreeMap::new();
+    while !context.is_empty() {
+        let id = u64::from_be_bytes(context.get(..8)?.try_into().ok()?);
+        let len = usize::from(*context.get(8)?);
+        let rounding = Rounding::from_context(context.get(9..9 + len)?)?;
+        roundings.insert(id, rounding);
+        context = &context[9 + len..];
+    }
+    Some(roundings)
+}
+
+// Transform context for an outcall made with the given options
+fn options_context(options: &CallOptions) -> Vec<u8> {
+    options
+        .rounding
+        .map(Rounding::to_context)
+        .unwrap_or_default()
+}
+
+/// Significant digits to which fee suggestions are rounded up, so they overpay by less than 1%
+pub const FEE_SIGNIFICANT_DIGITS: u8 = 3;
+
+/// Fallback policy for contract calls whose block state is unavailable
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub enum StateFallback {
+    /// Return the `StateUnavailable` error
+    #[default]
+    None,
+    /// Retry against the network's archive providers
+    Archive,
+    /// Retry at the finalized block, then at the latest block
+    Degrade,
+}
+
+/// Decoded outputs of a contract call, along with the block it was executed at
+/// (which differs from the requested block if the call was degraded)
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallOutput {
+    pub tokens: Vec<Token>,
+    /// Return data of the call, as returned by `eth_call`
+    pub raw: Vec<u8>,
+    pub block: BlockTag,
+}
+
+/// Measurements of a single HTTP outcall
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutcallTrace {
+    /// Provider URL template (secrets are never expanded)
+    pub provider: String,
+    pub response_bytes: usize,
+    pub latency_ns: u64,
+    /// Cycles attached to the outcall
+    pub cycles: u128,
+}
+
+/// Options of the `call_contract` endpoint
+#[derive(Clone, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ContractCallOptions {
+    /// Wrap the outputs in an envelope with metadata about the outcall
+    pub verbose: Option<bool>,
+    /// Block at which the call is executed (`Latest` by default)
+    pub block: Option<BlockTag>,
+    /// Serve the call from the call cache if the cached result was fetched at most
+    /// this long ago (in nanoseconds), and cache fresh results
+    pub max_cache_staleness_ns: Option<u64>,
+    /// What to do when the block's state was pruned by the provider (not applied
+    /// to verbose or cached calls)
+    pub state_fallback: Option<StateFallback>,
+    /// Render unsigned integers as hex quantities instead of decimal strings
+    pub uint_as_hex: Option<bool>,
+}
+
+/// Decoded outputs of a contract call, with metadata about where and when they were obtained
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallResultMeta {
+    pub tokens: Vec<Token>,
+    /// Raw return data the outputs were decoded from
+    pub raw: Vec<u8>,
+    /// Block the call was executed at
+    pub block: BlockTag,
+    /// Head block number reported alongside the call, if the provider returned it
+    pub head_block: Option<u64>,
+    /// The outcall which served the call, unless it was served from the cache
+    pub trace: Option<OutcallTrace>,
+    /// Number of providers which failed before the call succeeded
+    pub retries: u32,
+    pub from_cache: bool,
+}
+
+/// A single request within a JSON-RPC batch
+#[derive(Clone, Debug)]
+pub struct BatchRequest {
+    pub method: String,
+    pub params: Value,
+    /// Rounding of the request's quantity result, applied in the transform
+    /// (see [`Rounding`])
+    pub rounding: Option<Rounding>,
+}
+
+// A serialized batch request with its id
+struct BatchEntry {
+    id: u64,
+    json: String,
+    rounding: Option<Rounding>,
+}
+
+/// Outcome of a transaction, as reported by its receipt
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum TxStatus {
+    /// Not yet included in a block
+    Pending,
+    Success,
+    Failed,
+}
+
+/// Block at which a read is performed
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, CandidType, Deserialize)]
+pub enum BlockTag {
+    #[default]
+    Latest,
+    Safe,
+    Finalized,
+    Earliest,
+    Pending,
+    Number(u64),
+}
+
+impl BlockTag {
+    /// Whether the block may be old enough for non-archive providers to have pruned its state
+    pub fn is_historical(&self) -> bool {
+        matches!(self, BlockTag::Number(_) | BlockTag::Earliest)
+    }
+}
+
+impl Serialize for BlockTag {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            BlockTag::Latest => serializer.serialize_str("latest"),
+            BlockTag::Safe => serializer.serialize_str("safe"),
+            BlockTag::Finalized => serializer.serialize_str("finalized"),
+            BlockTag::Earliest => serializer.serialize_str("earliest"),
+            BlockTag::Pending => serializer.serialize_str("pending"),
+            BlockTag::Number(n) => serializer.serialize_str(&format!("{:#x}", n)),
+        }
+    }
+}
 
 // Structs to define JSON-RPC requests and responses
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct RpcRequest {
+struct RpcRequest<P> {
+    #[serde(rename = "id")]
     request_id: u64,
+    #[serde(rename = "jsonrpc")]
     version: String,
+    #[serde(rename = "method")]
     action: String,
-    parameters: (EthCallData, String),
+    #[serde(rename = "params")]
+    parameters: P,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct EthCallData {
+    #[serde(rename = "from", skip_serializing_if = "Option::is_none")]
+    sender: Option<String>,
+    #[serde(rename = "to")]
     recipient: String,
+    #[serde(rename = "data")]
     payload: String,
+    #[serde(rename = "value", skip_serializing_if = "Option::is_none")]
+    amount: Option<U256>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-struct RpcResponse {
-    outcome: Option<String>,
+#[serde(bound(deserialize = "R: Deserialize<'de>"))]
+struct RpcResponse<R> {
+    #[serde(rename = "id", default)]
+    request_id: Option<u64>,
+    // A `null` result is still a result (e.g. an unknown transaction's receipt)
+    #[serde(rename = "result", default, deserialize_with = "deserialize_present")]
+    outcome: Option<R>,
+    #[serde(rename = "error")]
     rpc_error: Option<RpcErrorDetail>,
 }
 
+fn deserialize_present<'de, D, R>(deserializer: D) -> Result<Option<R>, D::Error>
+where
+    D: Deserializer<'de>,
+    R: Deserialize<'de>,
+{
+    R::deserialize(deserializer).map(Some)
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccessListResult {
+    access_list: AccessList,
+    gas_used: U256,
+    #[serde(default)]
+    error: Option<String>,
+}
+
+// The part of a block needed to tell whether the network implements EIP-1559
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BaseFeeBlock {
+    #[serde(default)]
+    base_fee_per_gas: Option<U256>,
+}
+
+// Body of a batch response: an array of responses in any order, or a single
+// response object (which some servers return for a batch of one request)
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum BatchBody {
+    Many(Vec<RpcResponse<Value>>),
+    One(RpcResponse<Value>),
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize)]
 struct RpcErrorDetail {
-    error_code: isize,
+    #[serde(rename = "code")]
+    error_code: i64,
+    #[serde(rename = "message")]
     error_message: String,
+    #[serde(rename = "data", default)]
+    error_data: Option<Value>,
 }
 
 // Macro to include ABI JSON files
@@ -50,26 +419,1291 @@ macro_rules! load_abi {
     }};
 }
 
-// Generate a unique ID for requests
+// Sort object keys recursively, regardless of the map type they were serialized
+// from (e.g. a `HashMap`) or whether `serde_json` preserves insertion order
+fn canonicalize(value: Value) -> Value {
+    match value {
+        Value::Object(map) => {
+            let mut entries = map.into_iter().collect::<Vec<_>>();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(key, value)| (key, canonicalize(value)))
+                    .collect(),
+            )
+        }
+        Value::Array(values) => Value::Array(values.into_iter().map(canonicalize).collect()),
+        value => value,
+    }
+}
+
+// Serialize a request body with a deterministic key order, since every replica
+// must send byte-identical HTTP outcall requests to reach consensus
+fn to_canonical_json<T: Serialize>(request: &T) -> Result<String, serde_json::Error> {
+    serde_json::to_string(&canonicalize(serde_json::to_value(request)?))
+}
+
+// Generate a unique ID for requests.
+// A `Cell` is used rather than a `RefCell` so that no borrow can ever be held
+// across an await point by concurrent requests.
 fn generate_request_id() -> u64 {
     thread_local! {
-        static REQUEST_ID: RefCell<u64> = RefCell::default();
+        static REQUEST_ID: Cell<u64> = Cell::default();
     }
     REQUEST_ID.with(|id| {
-        let mut id = id.borrow_mut();
-        let current_id = *id;
-        *id = id.wrapping_add(1);
+        let current_id = id.get();
+        id.set(current_id.wrapping_add(1));
         current_id
     })
 }
 
-// Function to get the RPC endpoint URL based on network name
-fn determine_rpc_url(network: &str) -> &'static str {
-    match network {
-        "mainnet" | "ethereum" => "https://cloudflare-eth.com/v1/mainnet",
-        "goerli" => "https://ethereum-goerli.publicnode.com",
-        "sepolia" => "https://rpc.sepolia.org",
-        _ => panic!("Unsupported network: {}", network),
+/// Estimate the cycles needed for an HTTP outcall of the given request size
+/// and response limit, following the IC's documented outcall pricing.
+pub fn estimate_outcall_cost(request_bytes: usize, max_response_bytes: u64) -> u128 {
+    let base_fee = (OUTCALL_BASE_FEE + OUTCALL_PER_NODE_FEE * SUBNET_SIZE) * SUBNET_SIZE;
+    let request_fee = OUTCALL_REQUEST_BYTE_FEE * SUBNET_SIZE * request_bytes as u128;
+    let response_fee = OUTCALL_RESPONSE_BYTE_FEE * SUBNET_SIZE * max_response_bytes as u128;
+    base_fee + request_fee + response_fee
+}
+
+// Size of an outcall request as counted by the cost formula
+fn request_size(request: &CanisterHttpRequestArgument) -> usize {
+    let headers_size: usize = request
+        .headers
+        .iter()
+        .map(|header| header.name.len() + header.value.len())
+        .sum();
+    let transform_size = request
+        .transform
+        .as_ref()
+        .map(|transform| transform.function.0.method.len() + transform.context.len())
+        .unwrap_or_default();
+    request.url.len() + headers_size + request.body.as_ref().map_or(0, Vec::len) + transform_size
+}
+
+// Send a JSON-RPC payload to a specific provider and return the response body
+async fn post_to(
+    provider: &str,
+    rpc_payload: String,
+    max_response_bytes: u64,
+    options: &CallOptions,
+) -> Result<String, EthRpcError> {
+    let (body, _) = post_traced(
+        provider,
+        rpc_payload,
+        max_response_bytes,
+        options,
+        options_context(options),
+    )
+    .await?;
+    Ok(body)
+}
+
+// Send a JSON-RPC payload to a specific provider and return the response body,
+// along with a trace of the outcall
+async fn post_traced(
+    provider: &str,
+    rpc_payload: String,
+    max_response_bytes: u64,
+    options: &CallOptions,
+    context: Vec<u8>,
+) -> Result<(String, OutcallTrace), EthRpcError> {
+    let (response, trace) = outcall_traced(
+        provider,
+        HttpMethod::POST,
+        Some(rpc_payload),
+        max_response_bytes,
+        options,
+        context,
+    )
+    .await?;
+    let body = String::from_utf8(response.body)
+        .map_err(|_| EthRpcError::InvalidResponse("Invalid UTF-8".to_string()))?;
+    Ok((body, trace))
+}
+
+/// Fetch a URL (which may contain `{{secret:NAME}}` placeholders) with a GET request
+/// and return the response body. Responses with a non-2xx status are an error.
+pub async fn http_get(
+    url: &str,
+    max_response_bytes: u64,
+    options: &CallOptions,
+) -> Result<String, EthRpcError> {
+    let (response, _) = outcall_traced(
+        url,
+        HttpMethod::GET,
+        None,
+        max_response_bytes,
+        options,
+        options_context(options),
+    )
+    .await?;
+    if response.status < 200u64 || response.status >= 300u64 {
+        return Err(EthRpcError::InvalidResponse(format!(
+            "HTTP status {}",
+            response.status
+        )));
+    }
+    String::from_utf8(response.body)
+        .map_err(|_| EthRpcError::InvalidResponse("Invalid UTF-8".to_string()))
+}
+
+// Send an HTTP request to a URL and return the response, along with a trace of the outcall.
+// The context is passed to the transform.
+async fn outcall_traced(
+    provider: &str,
+    method: HttpMethod,
+    body: Option<String>,
+    max_response_bytes: u64,
+    options: &CallOptions,
+    context: Vec<u8>,
+) -> Result<(HttpResponse, OutcallTrace), EthRpcError> {
+    // Refuse bodies the system would reject, reporting their actual size
+    let limit = options
+        .max_request_bytes
+        .unwrap_or(DEFAULT_MAX_REQUEST_BYTES);
+    let size = body.as_ref().map_or(0, String::len);
+    if size > limit {
+        return Err(EthRpcError::RequestTooLarge { size, limit });
+    }
+
+    // Parse service URL (with secrets expanded) and set headers
+    let rpc_url = expand_secrets(provider).map_err(EthRpcError::ProviderConfig)?;
+    let url_parts = url::Url::parse(&rpc_url)
+        .map_err(|_| EthRpcError::ProviderConfig("Error parsing service URL".to_string()))?;
+    let host_header = url_parts
+        .host_str()
+        .ok_or_else(|| EthRpcError::ProviderConfig("Invalid service URL host".to_string()))?;
+
+    let mut headers = vec![HttpHeader {
+        name: "Host".to_string(),
+        value: host_header.to_string(),
+    }];
+    if body.is_some() {
+        headers.insert(
+            0,
+            HttpHeader {
+                name: "Content-Type".to_string(),
+                value: "application/json".to_string(),
+            },
+        );
+    }
+
+    if let Some(sign_request) = options.sign_request {
+        headers.push(sign_request(body.as_deref().unwrap_or_default().as_bytes()));
+    }
+
+    // Prepare the HTTP request
+    let http_request_data = CanisterHttpRequestArgument {
+        url: rpc_url,
+        max_response_bytes: Some(max_response_bytes),
+        method,
+        headers,
+        body: body.map(String::into_bytes),
+        transform: Some(transform_context(TRANSFORM_METHOD, context)),
+    };
+
+    // Perform the HTTP request once an outcall slot is available
+    let cycles = if options.estimate_cycles {
+        estimate_outcall_cost(request_size(&http_request_data), max_response_bytes)
+    } else {
+        CYCLES_COST
+    };
+    if let Some(min_cycles_balance) = options.min_cycles_balance {
+        let balance = ic_cdk::api::canister_balance128();
+        let required = min_cycles_balance + cycles;
+        if balance < required {
+            return Err(EthRpcError::InsufficientCycles { balance, required });
+        }
+    }
+    let _permit = limiter::try_acquire(options.priority)?;
+    let start = clock::now();
+    let response = match transport().http_request(http_request_data, cycles).await {
+        Ok(res) => res,
+        Err((_, msg)) if msg.contains(RESPONSE_SIZE_LIMIT_MESSAGE) => {
+            return Err(EthRpcError::ResponseTooLarge {
+                limit: max_response_bytes,
+            })
+        }
+        Err((code, msg)) => {
+            return Err(EthRpcError::HttpOutcall {
+                code: format!("{:?}", code),
+                message: redact(&msg),
+            })
+        }
+    };
+    let trace = OutcallTrace {
+        provider: provider.to_string(),
+        response_bytes: response.body.len(),
+        latency_ns: clock::now() - start,
+        cycles,
+    };
+    // Outcalls don't follow redirects, so report the destination instead
+    if response.status >= 300u64 && response.status < 400u64 {
+        let location = response
+            .headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case("Location"))
+            .map(|header| redact(&header.value))
+            .unwrap_or_default();
+        return Err(EthRpcError::Redirect { location });
+    }
+    Ok((response, trace))
+}
+
+// Decode the JSON-RPC response body
+fn parse_body<T: DeserializeOwned>(body: &str) -> Result<T, EthRpcError> {
+    serde_json::from_str(body)
+        .map_err(|_| EthRpcError::InvalidResponse("Malformed JSON response".to_string()))
+}
+
+// Error with which the system rejects responses above `max_response_bytes`
+const RESPONSE_SIZE_LIMIT_MESSAGE: &str = "body exceeds size limit";
+
+// Error code and messages with which providers refuse log queries with too many results
+const LOG_LIMIT_CODE: i64 = -32005;
+const LOG_LIMIT_MESSAGES: &[&str] = &[
+    "query returned more than",
+    "log response size exceeded",
+    "response size exceeded",
+    "too many results",
+];
+
+// JSON-RPC error code for methods the provider doesn't implement
+const METHOD_NOT_FOUND_CODE: i64 = -32601;
+
+// Error messages with which providers report pruned or missing historical state
+const STATE_UNAVAILABLE_MESSAGES: &[&str] = &[
+    "missing trie node",
+    "header not found",
+    "state not available",
+    "state is not available",
+    "historical state",
+    "pruned",
+];
+
+// Selectors of the `Error(string)` and `Panic(uint256)` revert payloads
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+// Decode the reason a call reverted, from the revert data if it is a
+// standard payload and otherwise from the provider's error message
+fn revert_reason(err: &RpcErrorDetail) -> String {
+    let data = err
+        .error_data
+        .as_ref()
+        .and_then(Value::as_str)
+        .and_then(|data| hex::decode(data.trim_start_matches("0x")).ok())
+        .unwrap_or_default();
+    if let Some(payload) = data.strip_prefix(&ERROR_SELECTOR[..]) {
+        if let Ok(reason) = ethers_core::abi::decode(&[ParamType::String], payload) {
+            if let Some(Ok(reason)) = reason.first().map(|token| DecodedValue(token).as_string()) {
+                return reason.to_string();
+            }
+        }
+    }
+    if let Some(payload) = data.strip_prefix(&PANIC_SELECTOR[..]) {
+        if payload.len() == 32 {
+            return format!("panic code {:#x}", U256::from_big_endian(payload));
+        }
+    }
+    match err.error_message.split_once(": ") {
+        Some((_, reason)) => reason.to_string(),
+        None => err.error_message.clone(),
+    }
+}
+
+// Extract the result of a JSON-RPC response
+fn parse_response<R>(rpc_result: RpcResponse<R>) -> Result<R, EthRpcError> {
+    if let Some(err) = rpc_result.rpc_error {
+        let message = err.error_message.to_lowercase();
+        if err.error_code == 3 || message.starts_with("execution reverted") {
+            return Err(EthRpcError::Reverted(redact(&revert_reason(&err))));
+        }
+        if STATE_UNAVAILABLE_MESSAGES
+            .iter()
+            .any(|pattern| message.contains(pattern))
+        {
+            return Err(EthRpcError::StateUnavailable(redact(&err.error_message)));
+        }
+        if err.error_code == METHOD_NOT_FOUND_CODE {
+            return Err(EthRpcError::MethodNotSupported(redact(&err.error_message)));
+        }
+        return Err(EthRpcError::JsonRpc {
+            code: err.error_code,
+            message: redact(&err.error_message),
+        });
+    }
+    rpc_result
+        .outcome
+        .ok_or_else(|| EthRpcError::InvalidResponse("Unexpected JSON response".to_string()))
+}
+
+// Send a JSON-RPC request to a specific provider
+async fn rpc_request_to<P: Serialize, R: DeserializeOwned>(
+    provider: &str,
+    method: &str,
+    params: P,
+    max_response_bytes: u64,
+    options: &CallOptions,
+) -> Result<R, EthRpcError> {
+    // Prepare JSON-RPC payload
+    let rpc_payload = to_canonical_json(&RpcRequest {
+        request_id: generate_request_id(),
+        version: "2.0".to_string(),
+        action: method.to_string(),
+        parameters: params,
+    })
+    .expect("Error encoding JSON-RPC request");
+
+    let body = post_to(provider, rpc_payload, max_response_bytes, options).await?;
+    parse_response(parse_body(&body)?)
+}
+
+// Default time for which a network's head block is reused (about one block time)
+const DEFAULT_HEAD_BLOCK_TTL_NS: u64 = 12_000_000_000;
+
+// Highest head block seen on a network, and when it was seen
+#[derive(Clone, Copy, Debug)]
+struct HeadBlock {
+    number: u64,
+    observed_at: u64,
+}
+
+thread_local! {
+    // Whether each probed network supports EIP-1559 transactions
+    static EIP1559_SUPPORT: RefCell<BTreeMap<String, bool>> = RefCell::default();
+}
+
+thread_local! {
+    // Used to reuse block number lookups and to judge how deep a historical read is
+    static HEAD_BLOCKS: RefCell<BTreeMap<String, HeadBlock>> = RefCell::default();
+    static HEAD_BLOCK_TTL_NS: Cell<u64> = const { Cell::new(DEFAULT_HEAD_BLOCK_TTL_NS) };
+}
+
+/// Record a head block number reported by one of a network's providers
+pub fn observe_head_block(network: &str, block: u64) {
+    HEAD_BLOCKS.with(|heads| {
+        let mut heads = heads.borrow_mut();
+        let head = heads.entry(network.to_string()).or_insert(HeadBlock {
+            number: 0,
+            observed_at: 0,
+        });
+        if block >= head.number {
+            *head = HeadBlock {
+                number: block,
+                observed_at: clock::now(),
+            };
+        }
+    })
+}
+
+/// The most recent head block number observed for a network
+pub fn known_head_block(network: &str) -> Option<u64> {
+    HEAD_BLOCKS.with(|heads| heads.borrow().get(network).map(|head| head.number))
+}
+
+/// Set how long a network's head block number is reused before it is fetched again
+pub fn set_head_block_ttl(ttl_ns: u64) {
+    HEAD_BLOCK_TTL_NS.with(|ttl| ttl.set(ttl_ns))
+}
+
+/// Time for which a network's head block number is reused
+pub fn head_block_ttl() -> u64 {
+    HEAD_BLOCK_TTL_NS.with(Cell::get)
+}
+
+/// Forget a network's head block, so the next `block_number` fetches it again
+pub fn invalidate_head_block(network: &str) {
+    HEAD_BLOCKS.with(|heads| heads.borrow_mut().remove(network));
+}
+
+/// Get the number of the network's head block. A head seen within the
+/// head block TTL is reused instead of making an outcall.
+pub async fn block_number(network: &str) -> Result<u64, EthRpcError> {
+    let now = clock::now();
+    let cached = HEAD_BLOCKS.with(|heads| {
+        heads
+            .borrow()
+            .get(network)
+            .filter(|head| now.saturating_sub(head.observed_at) < head_block_ttl())
+            .map(|head| head.number)
+    });
+    if let Some(number) = cached {
+        return Ok(number);
+    }
+    let number: U64 = rpc_request(
+        network,
+        "eth_blockNumber",
+        (),
+        MAX_BYTES,
+        &CallOptions::default(),
+    )
+    .await?;
+    observe_head_block(network, number.as_u64());
+    Ok(number.as_u64())
+}
+
+// The network's providers in order of health. For historical blocks, providers
+// which can't serve the block's depth are left out, since they would have pruned
+// its state; if none can, they are all tried anyway. When the head block isn't
+// known yet, archive providers go first instead.
+fn candidate_providers(network: &str, block: BlockTag) -> Vec<String> {
+    let mut providers = select_providers(network);
+    let depth = match block {
+        BlockTag::Number(number) => match known_head_block(network) {
+            Some(head) => Some(head.saturating_sub(number)),
+            None => {
+                providers.sort_by_key(|provider| !is_archive(network, provider));
+                return providers;
+            }
+        },
+        BlockTag::Earliest => None,
+        _ => return providers,
+    };
+    let (capable, pruned): (Vec<_>, Vec<_>) = providers
+        .into_iter()
+        .partition(|provider| archive_support(network, provider).can_serve(depth));
+    if capable.is_empty() {
+        pruned
+    } else {
+        capable
+    }
+}
+
+// Make an attempt against each provider in turn, until one succeeds
+// or fails for a reason other than a faulty provider
+async fn with_failover<R, F, Fut>(
+    network: &str,
+    providers: Vec<String>,
+    attempt: F,
+) -> Result<R, EthRpcError>
+where
+    F: Fn(String) -> Fut,
+    Fut: Future<Output = Result<R, EthRpcError>>,
+{
+    let mut result = Err(EthRpcError::UnsupportedNetwork(network.to_string()));
+    for provider in providers {
+        let start = clock::now();
+        result = attempt(provider.clone()).await;
+        match &result {
+            Err(err) if err.is_provider_failure() => record_failure(&provider, err),
+            // Nothing was sent, so the provider's health is unknown
+            Err(EthRpcError::OutcallLimitReached { .. }) => break,
+            _ => {
+                record_success(&provider, clock::now() - start);
+                break;
+            }
+        }
+    }
+    result
+}
+
+// Send a JSON-RPC request, failing over across the network's providers by health
+async fn rpc_request<P: Serialize, R: DeserializeOwned>(
+    network: &str,
+    method: &str,
+    params: P,
+    max_response_bytes: u64,
+    options: &CallOptions,
+) -> Result<R, EthRpcError> {
+    let providers = candidate_providers(network, options.block);
+    rpc_request_via(
+        network,
+        providers,
+        method,
+        params,
+        max_response_bytes,
+        options,
+    )
+    .await
+}
+
+// Send a JSON-RPC request, failing over across the given providers
+async fn rpc_request_via<P: Serialize, R: DeserializeOwned>(
+    network: &str,
+    providers: Vec<String>,
+    method: &str,
+    params: P,
+    max_response_bytes: u64,
+    options: &CallOptions,
+) -> Result<R, EthRpcError> {
+    let params = &params;
+    with_failover(network, providers, |provider| async move {
+        rpc_request_to(&provider, method, params, max_response_bytes, options).await
+    })
+    .await
+}
+
+// Split serialized batch entries into consecutive chunks whose batch payload
+// (`[entry,entry,...]`) stays within the byte limit
+fn split_batch(entries: &[BatchEntry], limit: usize) -> Result<Vec<&[BatchEntry]>, EthRpcError> {
+    let mut chunks = vec![];
+    let mut start = 0;
+    let mut size = 0;
+    for (i, entry) in entries.iter().enumerate() {
+        let len = entry.json.len();
+        if len + 2 > limit {
+            return Err(EthRpcError::BatchTooLarge {
+                size: len + 2,
+                limit,
+            });
+        }
+        if i > start && size + len + 1 > limit {
+            chunks.push(&entries[start..i]);
+            start = i;
+        }
+        size = if i == start { len + 2 } else { size + len + 1 };
+    }
+    if start < entries.len() {
+        chunks.push(&entries[start..]);
+    }
+    Ok(chunks)
+}
+
+// Serialize each batch request with its own id
+fn batch_entries(requests: &[BatchRequest]) -> Vec<BatchEntry> {
+    requests
+        .iter()
+        .map(|request| {
+            let id = generate_request_id();
+            let json = to_canonical_json(&RpcRequest {
+                request_id: id,
+                version: "2.0".to_string(),
+                action: request.method.clone(),
+                parameters: &request.params,
+            })
+            .expect("Error encoding JSON-RPC request");
+            BatchEntry {
+                id,
+                json,
+                rounding: request.rounding,
+            }
+        })
+        .collect()
+}
+
+// Send a chunk of serialized batch entries to a specific provider
+async fn batch_request_to(
+    provider: &str,
+    chunk: &[BatchEntry],
+    options: &CallOptions,
+) -> Result<Vec<RpcResponse<Value>>, EthRpcError> {
+    let (responses, _) = batch_request_traced(provider, chunk, options).await?;
+    Ok(responses)
+}
+
+// Send a chunk of serialized batch entries to a specific provider,
+// returning a trace of the outcall along with the responses
+async fn batch_request_traced(
+    provider: &str,
+    chunk: &[BatchEntry],
+    options: &CallOptions,
+) -> Result<(Vec<RpcResponse<Value>>, OutcallTrace), EthRpcError> {
+    let rpc_payload = format!(
+        "[{}]",
+        chunk
+            .iter()
+            .map(|entry| entry.json.as_str())
+            .collect::<Vec<_>>()
+            .join(",")
+    );
+    let max_response_bytes = (options.max_entry_response_bytes.unwrap_or(MAX_BYTES)
+        * chunk.len() as u64)
+        .min(MAX_RESPONSE_BYTES);
+    let context = if chunk.iter().any(|entry| entry.rounding.is_some()) {
+        batch_rounding_context(chunk)
+    } else {
+        options_context(options)
+    };
+    let (body, trace) =
+        post_traced(provider, rpc_payload, max_response_bytes, options, context).await?;
+    let responses = match parse_body(&body)? {
+        BatchBody::Many(responses) => responses,
+        // Without an id, the response is an error with the batch as a whole
+        BatchBody::One(response) if response.request_id.is_none() => {
+            parse_response(response)?;
+            return Err(EthRpcError::InvalidResponse(
+                "Batch response without an id".to_string(),
+            ));
+        }
+        BatchBody::One(response) => vec![response],
+    };
+    Ok((responses, trace))
+}
+
+// Match batch responses to their requests by id, in request order
+fn batch_results(
+    entries: &[BatchEntry],
+    responses: Vec<RpcResponse<Value>>,
+) -> Result<Vec<Result<Value, EthRpcError>>, EthRpcError> {
+    let mut results = responses
+        .into_iter()
+        .filter_map(|response| Some((response.request_id?, parse_response(response))))
+        .collect::<BTreeMap<_, _>>();
+    entries
+        .iter()
+        .map(|entry| {
+            results
+                .remove(&entry.id)
+                .ok_or(EthRpcError::MissingBatchResponse { id: entry.id })
+        })
+        .collect()
+}
+
+/// Send several JSON-RPC requests as batches, returning each request's result in order.
+/// Batches whose payload would exceed `max_request_bytes` are split across multiple outcalls.
+pub async fn batch_call(
+    network: &str,
+    requests: &[BatchRequest],
+    options: &CallOptions,
+) -> Result<Vec<Result<Value, EthRpcError>>, EthRpcError> {
+    let limit = options
+        .max_request_bytes
+        .unwrap_or(DEFAULT_MAX_REQUEST_BYTES);
+    let entries = batch_entries(requests);
+    let mut responses = vec![];
+    for chunk in split_batch(&entries, limit)? {
+        responses.extend(
+            with_failover(
+                network,
+                candidate_providers(network, options.block),
+                |provider| async move { batch_request_to(&provider, chunk, options).await },
+            )
+            .await?,
+        );
+    }
+    batch_results(&entries, responses)
+}
+
+/// Send several JSON-RPC requests as a single batch to a specific provider,
+/// bypassing provider rotation
+pub async fn batch_call_to(
+    provider: &str,
+    requests: &[BatchRequest],
+    options: &CallOptions,
+) -> Result<Vec<Result<Value, EthRpcError>>, EthRpcError> {
+    let entries = batch_entries(requests);
+    let responses = batch_request_to(provider, &entries, options).await?;
+    batch_results(&entries, responses)
+}
+
+/// Send a cheap request (eth_chainId) to check whether a provider responds
+pub async fn probe_provider(provider: &str) -> Result<String, EthRpcError> {
+    let options = CallOptions {
+        priority: Priority::Background,
+        ..Default::default()
+    };
+    rpc_request_to(provider, "eth_chainId", (), MAX_BYTES, &options).await
+}
+
+/// Get the chain id reported by a network's providers
+pub async fn chain_id(network: &str) -> Result<u64, EthRpcError> {
+    let chain_id: U64 = rpc_request(
+        network,
+        "eth_chainId",
+        (),
+        MAX_BYTES,
+        &CallOptions::default(),
+    )
+    .await?;
+    Ok(chain_id.as_u64())
+}
+
+/// Get the balance (in wei) of an address
+pub async fn get_balance(
+    network: &str,
+    address: Address,
+    block: BlockTag,
+) -> Result<U256, EthRpcError> {
+    rpc_request(
+        network,
+        "eth_getBalance",
+        (address, block),
+        MAX_BYTES,
+        &CallOptions {
+            block,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Get the number of transactions sent from an address (i.e. its next nonce)
+pub async fn get_transaction_count(
+    network: &str,
+    address: Address,
+    block: BlockTag,
+) -> Result<u64, EthRpcError> {
+    let count: U64 = rpc_request(
+        network,
+        "eth_getTransactionCount",
+        (address, block),
+        MAX_BYTES,
+        &CallOptions {
+            block,
+            ..Default::default()
+        },
+    )
+    .await?;
+    Ok(count.as_u64())
+}
+
+/// Get the deployed bytecode of a contract
+pub async fn get_code(
+    network: &str,
+    address: Address,
+    block: BlockTag,
+) -> Result<Vec<u8>, EthRpcError> {
+    let code: Bytes = rpc_request(
+        network,
+        "eth_getCode",
+        (address, block),
+        MAX_CODE_BYTES,
+        &CallOptions {
+            block,
+            ..Default::default()
+        },
+    )
+    .await?;
+    Ok(code.to_vec())
+}
+
+/// Read a 32-byte word from a contract's storage
+pub async fn get_storage_at(
+    network: &str,
+    address: Address,
+    slot: U256,
+    block: BlockTag,
+) -> Result<H256, EthRpcError> {
+    rpc_request(
+        network,
+        "eth_getStorageAt",
+        (address, slot, block),
+        MAX_BYTES,
+        &CallOptions {
+            block,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Read several 32-byte words from a contract's storage in a single batch,
+/// returned in slot order
+pub async fn get_storage_slots(
+    network: &str,
+    address: Address,
+    slots: &[U256],
+    block: BlockTag,
+) -> Result<Vec<H256>, EthRpcError> {
+    let requests = slots
+        .iter()
+        .map(|slot| BatchRequest {
+            method: "eth_getStorageAt".to_string(),
+            params: serde_json::json!([address, slot, block]),
+            rounding: None,
+        })
+        .collect::<Vec<_>>();
+    let options = CallOptions {
+        block,
+        ..Default::default()
+    };
+    batch_call(network, &requests, &options)
+        .await?
+        .into_iter()
+        .map(|word| {
+            serde_json::from_value(word?)
+                .map_err(|_| EthRpcError::InvalidResponse("Malformed storage word".to_string()))
+        })
+        .collect()
+}
+
+/// Get the current gas price (in wei), rounded up to [`FEE_SIGNIFICANT_DIGITS`]
+/// significant digits
+pub async fn gas_price(network: &str) -> Result<U256, EthRpcError> {
+    rpc_request(
+        network,
+        "eth_gasPrice",
+        (),
+        MAX_BYTES,
+        &CallOptions {
+            rounding: Some(Rounding::SignificantDigits(FEE_SIGNIFICANT_DIGITS)),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Get the suggested priority fee (tip) of EIP-1559 transactions (in wei),
+/// rounded up to [`FEE_SIGNIFICANT_DIGITS`] significant digits
+pub async fn max_priority_fee_per_gas(network: &str) -> Result<U256, EthRpcError> {
+    rpc_request(
+        network,
+        "eth_maxPriorityFeePerGas",
+        (),
+        MAX_BYTES,
+        &CallOptions {
+            rounding: Some(Rounding::SignificantDigits(FEE_SIGNIFICANT_DIGITS)),
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Get the base fee of the latest block (in wei), or `None` if the network
+/// doesn't implement EIP-1559
+pub async fn latest_base_fee(network: &str) -> Result<Option<U256>, EthRpcError> {
+    let block: Option<BaseFeeBlock> = rpc_request(
+        network,
+        "eth_getBlockByNumber",
+        (BlockTag::Latest, false),
+        MAX_BLOCK_BYTES,
+        &CallOptions::default(),
+    )
+    .await?;
+    let block =
+        block.ok_or_else(|| EthRpcError::InvalidResponse("Latest block not found".to_string()))?;
+    let base_fee = block.base_fee_per_gas;
+    EIP1559_SUPPORT.with(|support| {
+        support
+            .borrow_mut()
+            .insert(network.to_string(), base_fee.is_some())
+    });
+    Ok(base_fee)
+}
+
+/// Whether a network supports EIP-1559 (type 2) transactions, i.e. whether its
+/// blocks have a base fee. The network is only probed the first time.
+pub async fn supports_eip1559(network: &str) -> Result<bool, EthRpcError> {
+    if let Some(supported) = EIP1559_SUPPORT.with(|support| support.borrow().get(network).copied())
+    {
+        return Ok(supported);
+    }
+    Ok(latest_base_fee(network).await?.is_some())
+}
+
+/// Broadcast a signed transaction, returning its hash
+pub async fn send_raw_transaction(network: &str, raw: &[u8]) -> Result<H256, EthRpcError> {
+    let result = rpc_request(
+        network,
+        "eth_sendRawTransaction",
+        (Bytes::from(raw.to_vec()),),
+        MAX_BYTES,
+        &CallOptions::default(),
+    )
+    .await;
+    audit::record(network, raw, &result);
+    result
+}
+
+/// Accounting helpers for transaction receipts
+pub trait ReceiptExt {
+    /// Fee paid by the transaction (in wei): gas used times the effective gas price.
+    /// `None` if the receipt lacks either field (e.g. receipts from before EIP-1559).
+    fn total_fee(&self) -> Option<U256>;
+}
+
+impl ReceiptExt for TransactionReceipt {
+    fn total_fee(&self) -> Option<U256> {
+        Some(self.gas_used? * self.effective_gas_price?)
+    }
+}
+
+/// Get a transaction (pending or mined) by hash, or `None` if the provider doesn't know it
+pub async fn get_transaction_by_hash(
+    network: &str,
+    tx_hash: H256,
+) -> Result<Option<Transaction>, EthRpcError> {
+    rpc_request(
+        network,
+        "eth_getTransactionByHash",
+        (tx_hash,),
+        MAX_BLOCK_BYTES,
+        &CallOptions::default(),
+    )
+    .await
+}
+
+/// Get the receipt of a transaction, or `None` if it is not yet included in a block
+pub async fn get_transaction_receipt(
+    network: &str,
+    tx_hash: H256,
+) -> Result<Option<TransactionReceipt>, EthRpcError> {
+    rpc_request(
+        network,
+        "eth_getTransactionReceipt",
+        (tx_hash,),
+        MAX_RECEIPT_BYTES,
+        &CallOptions::default(),
+    )
+    .await
+}
+
+/// Get the receipts of every transaction in a block with `eth_getBlockReceipts`.
+/// Providers which don't implement the method fail with `MethodNotSupported`.
+pub async fn get_block_receipts(
+    network: &str,
+    block: BlockTag,
+) -> Result<Vec<TransactionReceipt>, EthRpcError> {
+    let receipts: Option<Vec<TransactionReceipt>> = rpc_request(
+        network,
+        "eth_getBlockReceipts",
+        (block,),
+        MAX_RESPONSE_BYTES,
+        &CallOptions {
+            block,
+            ..Default::default()
+        },
+    )
+    .await?;
+    receipts.ok_or_else(|| EthRpcError::InvalidInput(format!("Block not found: {:?}", block)))
+}
+
+/// Get the logs matching a filter with `eth_getLogs`
+pub async fn get_logs(
+    network: &str,
+    filter: &Filter,
+    options: &CallOptions,
+) -> Result<Vec<Log>, EthRpcError> {
+    rpc_request(
+        network,
+        "eth_getLogs",
+        (filter,),
+        MAX_RESPONSE_BYTES,
+        options,
+    )
+    .await
+}
+
+// Whether a log query failed because its results don't fit in one response
+fn is_log_limit(err: &EthRpcError) -> bool {
+    match err {
+        EthRpcError::ResponseTooLarge { .. } => true,
+        EthRpcError::JsonRpc { code, message } => {
+            let message = message.to_lowercase();
+            *code == LOG_LIMIT_CODE
+                || LOG_LIMIT_MESSAGES
+                    .iter()
+                    .any(|pattern| message.contains(pattern))
+        }
+        _ => false,
+    }
+}
+
+/// Get the logs matching a filter between two blocks (inclusive), querying pages
+/// of at most `page_size` blocks. Pages whose logs don't fit in one response are
+/// bisected, down to single blocks, so dense ranges are still fetched completely.
+/// Scans are Background work unless a priority is given, so a long backfill
+/// doesn't take the slots of interactive reads.
+pub async fn get_logs_paged(
+    network: &str,
+    filter: &Filter,
+    from_block: u64,
+    to_block: u64,
+    page_size: u64,
+    priority: Option<Priority>,
+) -> Result<Vec<Log>, EthRpcError> {
+    let options = CallOptions {
+        priority: priority.unwrap_or(Priority::Background),
+        ..Default::default()
+    };
+    let page_size = page_size.max(1);
+    // Ranges still to fetch, with the earliest last
+    let mut ranges = vec![];
+    let mut start = from_block;
+    while start <= to_block {
+        let end = start.saturating_add(page_size - 1).min(to_block);
+        ranges.push((start, end));
+        if end == u64::MAX {
+            break;
+        }
+        start = end + 1;
+    }
+    ranges.reverse();
+
+    let mut logs = vec![];
+    while let Some((start, end)) = ranges.pop() {
+        let page = filter.clone().from_block(start).to_block(end);
+        match get_logs(network, &page, &options).await {
+            Ok(page_logs) => logs.extend(page_logs),
+            Err(err) if is_log_limit(&err) && start < end => {
+                let middle = start + (end - start) / 2;
+                ranges.push((middle + 1, end));
+                ranges.push((start, middle));
+            }
+            Err(err) => return Err(err),
+        }
+    }
+    Ok(logs)
+}
+
+/// Get the traces of every transaction in a block, including internal calls and
+/// value transfers which receipts don't show. Uses Parity/Erigon's `trace_block`,
+/// falling back to Geth's `debug_traceBlockByNumber` with the `callTracer` on providers
+/// without it. Either requires a provider exposing the trace or debug namespace
+/// (typically an archive node for older blocks); others fail with `MethodNotSupported`.
+/// Responses may be up to the outcall response limit.
+pub async fn trace_block(network: &str, block: BlockTag) -> Result<Vec<Value>, EthRpcError> {
+    let options = CallOptions {
+        block,
+        ..Default::default()
+    };
+    match rpc_request(
+        network,
+        "trace_block",
+        (block,),
+        MAX_RESPONSE_BYTES,
+        &options,
+    )
+    .await
+    {
+        Err(EthRpcError::MethodNotSupported(_)) => {
+            rpc_request(
+                network,
+                "debug_traceBlockByNumber",
+                (block, serde_json::json!({ "tracer": "callTracer" })),
+                MAX_RESPONSE_BYTES,
+                &options,
+            )
+            .await
+        }
+        result => result,
+    }
+}
+
+/// Get the receipts of several transactions in one batch, in input order.
+/// Transactions which are not yet mined have no receipt.
+pub async fn get_transaction_receipts(
+    network: &str,
+    hashes: &[H256],
+) -> Result<Vec<Option<TransactionReceipt>>, EthRpcError> {
+    let requests = hashes
+        .iter()
+        .map(|hash| BatchRequest {
+            method: "eth_getTransactionReceipt".to_string(),
+            params: serde_json::json!([hash]),
+            rounding: None,
+        })
+        .collect::<Vec<_>>();
+    let options = CallOptions {
+        max_entry_response_bytes: Some(MAX_RECEIPT_BYTES),
+        ..Default::default()
+    };
+    batch_call(network, &requests, &options)
+        .await?
+        .into_iter()
+        .map(|receipt| {
+            serde_json::from_value(receipt?)
+                .map_err(|_| EthRpcError::InvalidResponse("Malformed receipt".to_string()))
+        })
+        .collect()
+}
+
+/// Resolve whether a transaction is pending, succeeded or failed
+pub async fn transaction_status(
+    network: &str,
+    tx_hash: H256,
+    options: &CallOptions,
+) -> Result<TxStatus, EthRpcError> {
+    let receipt: Option<TransactionReceipt> = rpc_request(
+        network,
+        "eth_getTransactionReceipt",
+        (tx_hash,),
+        MAX_RECEIPT_BYTES,
+        options,
+    )
+    .await?;
+    let receipt = match receipt {
+        Some(receipt) => receipt,
+        None => return Ok(TxStatus::Pending),
+    };
+    match receipt.status.map(|status| status.as_u64()) {
+        Some(1) => Ok(TxStatus::Success),
+        Some(0) => Ok(TxStatus::Failed),
+        _ => Err(EthRpcError::InvalidResponse(
+            "Receipt has no status field".to_string(),
+        )),
+    }
+}
+
+/// Get a block (with transaction hashes only), or `None` if it doesn't exist yet
+pub async fn get_block_by_number(
+    network: &str,
+    block: BlockTag,
+) -> Result<Option<Block<H256>>, EthRpcError> {
+    rpc_request(
+        network,
+        "eth_getBlockByNumber",
+        (block, false),
+        MAX_BLOCK_BYTES,
+        &CallOptions::default(),
+    )
+    .await
+}
+
+/// Get the receipt of a transaction once it has at least `confirmations` confirmations
+/// (the including block counts as the first), or `None` until then.
+///
+/// Once enough confirmations appear, the receipt's block is checked to still be the
+/// canonical block at its height. If it was reorged out, the receipt is fetched again
+/// and its confirmations counted from its new block.
+pub async fn wait_for_receipt(
+    network: &str,
+    tx_hash: H256,
+    confirmations: u64,
+) -> Result<Option<TransactionReceipt>, EthRpcError> {
+    // A reorged receipt is re-fetched once; a provider still serving the stale
+    // receipt is treated as not yet confirmed
+    for _ in 0..2 {
+        let receipt = match get_transaction_receipt(network, tx_hash).await? {
+            Some(receipt) => receipt,
+            None => return Ok(None),
+        };
+        let (number, hash) = match (receipt.block_number, receipt.block_hash) {
+            (Some(number), Some(hash)) => (number.as_u64(), hash),
+            _ => return Ok(None),
+        };
+        let head = block_number(network).await?;
+        if head + 1 < number + confirmations {
+            return Ok(None);
+        }
+        let canonical = get_block_by_number(network, BlockTag::Number(number))
+            .await?
+            .and_then(|block| block.hash);
+        if canonical == Some(hash) {
+            return Ok(Some(receipt));
+        }
+    }
+    Ok(None)
+}
+
+/// Estimate the gas used by a transaction. If the call object sets `gas`,
+/// the estimate is made within that upper bound.
+pub async fn estimate_gas(
+    network: &str,
+    call: &TransactionRequest,
+    block: BlockTag,
+) -> Result<U256, EthRpcError> {
+    rpc_request(
+        network,
+        "eth_estimateGas",
+        (call, block),
+        MAX_BYTES,
+        &CallOptions {
+            block,
+            ..Default::default()
+        },
+    )
+    .await
+}
+
+/// Compute the access list of the storage a transaction would touch, along with
+/// the gas it uses with that access list, with `eth_createAccessList`
+pub async fn create_access_list(
+    network: &str,
+    call: &TransactionRequest,
+    block: BlockTag,
+) -> Result<(AccessList, U256), EthRpcError> {
+    let result: AccessListResult = rpc_request(
+        network,
+        "eth_createAccessList",
+        (call, block),
+        MAX_ACCESS_LIST_BYTES,
+        &CallOptions {
+            block,
+            ..Default::default()
+        },
+    )
+    .await?;
+    // Some providers report a failing call in the result rather than as an error
+    if let Some(error) = result.error {
+        return Err(EthRpcError::Reverted(redact(&error)));
+    }
+    Ok((result.access_list, result.gas_used))
+}
+
+/// Simulate a call (optionally sending value) with `eth_call`, returning the raw return data
+pub async fn simulate_call(
+    network: &str,
+    address: Address,
+    data: &[u8],
+    value: U256,
+    options: &CallOptions,
+) -> Result<Vec<u8>, EthRpcError> {
+    let call_data = EthCallData {
+        sender: call_sender(options).await?,
+        recipient: to_hex(address.as_bytes()),
+        payload: to_hex(data),
+        amount: Some(value).filter(|value| !value.is_zero()),
+    };
+    let outcome: Bytes = rpc_request(
+        network,
+        "eth_call",
+        (call_data, options.block),
+        MAX_BYTES,
+        options,
+    )
+    .await?;
+    Ok(outcome.to_vec())
+}
+
+/// Whether a contract reports support for an interface through ERC-165's
+/// `supportsInterface(bytes4)`. Contracts which don't implement ERC-165 (the call
+/// reverts or returns no data) don't support the interface.
+pub async fn supports_interface(
+    network: &str,
+    address: String,
+    interface_id: [u8; 4],
+    block: BlockTag,
+) -> Result<bool, EthRpcError> {
+    let address = address
+        .parse::<Address>()
+        .map_err(|_| EthRpcError::InvalidInput(format!("Invalid address: {}", address)))?;
+    let mut data = function_selector("supportsInterface(bytes4)").to_vec();
+    data.extend_from_slice(&interface_id);
+    data.resize(4 + 32, 0);
+    let options = CallOptions {
+        block,
+        ..Default::default()
+    };
+    match simulate_call(network, address, &data, U256::zero(), &options).await {
+        Ok(outcome) => {
+            Ok(outcome.len() >= 32 && U256::from_big_endian(&outcome[..32]) == U256::one())
+        }
+        Err(EthRpcError::Reverted(_)) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Resolve a function in an ABI by name, full signature or 4-byte selector (`0x...`)
+pub fn resolve_function<'a>(
+    contract_abi: &'a Contract,
+    method_name: &str,
+) -> Result<&'a Function, EthRpcError> {
+    match contract_abi.functions_by_name(method_name).map(|v| &v[..]) {
+        Ok([func]) => Ok(func),
+        Ok(overloads) => Err(EthRpcError::AmbiguousOverload {
+            name: method_name.to_string(),
+            overloads: overloads
+                .iter()
+                .map(|func| {
+                    let signature = func.abi_signature();
+                    Overload {
+                        selector: to_hex(&function_selector(&signature)),
+                        signature,
+                    }
+                })
+                .collect(),
+        }),
+        Err(_) => contract_abi
+            .functions()
+            .find(|func| {
+                method_name == func.abi_signature()
+                    || method_name.eq_ignore_ascii_case(&to_hex(&func.short_signature()))
+            })
+            .ok_or_else(|| EthRpcError::InvalidInput("Function not found".to_string())),
     }
 }
 
@@ -81,99 +1715,1128 @@ pub async fn execute_contract_call(
     method_name: &str,
     arguments: &[Token],
 ) -> Vec<Token> {
-    // Find the function to call from the ABI
-    let function = match contract_abi.functions_by_name(method_name).map(|v| &v[..]) {
-        Ok([func]) => func,
-        Ok(overloads) => panic!(
-            "Found {} function overloads. Use one of: {}",
-            overloads.len(),
-            overloads
-                .iter()
-                .map(|func| format!("{:?}", func.abi_signature()))
-                .collect::<Vec<_>>()
-                .join(", ")
-        ),
-        Err(_) => contract_abi
-            .functions()
-            .find(|func| method_name == func.abi_signature())
-            .expect("Function not found"),
-    };
+    execute_contract_call_with_options(
+        network,
+        address,
+        contract_abi,
+        method_name,
+        arguments,
+        &CallOptions::default(),
+    )
+    .await
+}
+
+/// Perform a call to an Ethereum smart contract with the given call options
+pub async fn execute_contract_call_with_options(
+    network: &str,
+    address: String,
+    contract_abi: &Contract,
+    method_name: &str,
+    arguments: &[Token],
+    options: &CallOptions,
+) -> Vec<Token> {
+    try_execute_contract_call(
+        network,
+        address,
+        contract_abi,
+        method_name,
+        arguments,
+        options,
+    )
+    .await
+    .map(|output| output.tokens)
+    .unwrap_or_else(|err| panic!("{}", err))
+}
+
+// Sender of a contract call, if the options make calls from the canister's address
+async fn call_sender(options: &CallOptions) -> Result<Option<String>, EthRpcError> {
+    if let Some(from) = options.from {
+        return Ok(Some(to_hex(from.as_bytes())));
+    }
+    match &options.from_derivation_path {
+        Some(derivation_path) => {
+            let address = canister_eth_address(derivation_path.clone(), key_id()).await?;
+            Ok(Some(to_hex(address.as_bytes())))
+        }
+        None => Ok(None),
+    }
+}
+
+/// Perform a call to an Ethereum smart contract, applying the options' fallback
+/// policy if the requested block's state is unavailable
+pub async fn try_execute_contract_call(
+    network: &str,
+    address: String,
+    contract_abi: &Contract,
+    method_name: &str,
+    arguments: &[Token],
+    options: &CallOptions,
+) -> Result<CallOutput, EthRpcError> {
+    let function = resolve_function(contract_abi, method_name)?;
     let encoded_data = function
         .encode_input(arguments)
-        .expect("Error encoding input arguments");
+        .map_err(|err| EthRpcError::InvalidInput(err.to_string()))?;
 
-    // Prepare JSON-RPC payload
-    let rpc_payload = serde_json::to_string(&RpcRequest {
-        request_id: generate_request_id(),
-        version: "2.0".to_string(),
-        action: "eth_call".to_string(),
-        parameters: (
-            EthCallData {
-                recipient: address,
-                payload: to_hex(&encoded_data),
-            },
-            "latest".to_string(),
-        ),
-    })
-    .expect("Error encoding JSON-RPC request");
+    let call_data = &EthCallData {
+        sender: call_sender(options).await?,
+        recipient: address,
+        payload: to_hex(&encoded_data),
+        amount: None,
+    };
+    let call = |block: BlockTag, providers: Vec<String>| async move {
+        let outcome: String = rpc_request_via(
+            network,
+            providers,
+            "eth_call",
+            (call_data, block),
+            MAX_BYTES,
+            options,
+        )
+        .await?;
+        Ok::<_, EthRpcError>((outcome, block))
+    };
+    let mut result = call(options.block, candidate_providers(network, options.block)).await;
+    if let Err(EthRpcError::StateUnavailable(_)) = result {
+        match options.state_fallback {
+            StateFallback::None => {}
+            StateFallback::Archive => {
+                let mut archive = candidate_providers(network, options.block);
+                archive.retain(|provider| is_archive(network, provider));
+                if !archive.is_empty() {
+                    result = call(options.block, archive).await;
+                }
+            }
+            StateFallback::Degrade => {
+                for block in [BlockTag::Finalized, BlockTag::Latest] {
+                    result = call(block, candidate_providers(network, block)).await;
+                    if !matches!(result, Err(EthRpcError::StateUnavailable(_))) {
+                        break;
+                    }
+                }
+            }
+        }
+    }
+    let (outcome, block) = result?;
 
-    // Parse service URL and set headers
-    let rpc_url = determine_rpc_url(network).to_string();
-    let url_parts = url::Url::parse(&rpc_url).expect("Error parsing service URL");
-    let host_header = url_parts.host_str().expect("Invalid service URL host");
+    let raw = from_hex(&outcome)
+        .map_err(|_| EthRpcError::InvalidResponse("Malformed call result".to_string()))?;
+    let tokens = function
+        .decode_output(&raw)
+        .map_err(|err| EthRpcError::AbiDecode(err.to_string()))?;
+    Ok(CallOutput { tokens, raw, block })
+}
 
-    let headers = vec![
-        HttpHeader {
-            name: "Content-Type".to_string(),
-            value: "application/json".to_string(),
+/// Perform a call to an Ethereum smart contract, returning metadata about the
+/// outcall which served it. The head block number is fetched in the same batch.
+pub async fn execute_contract_call_verbose(
+    network: &str,
+    address: String,
+    contract_abi: &Contract,
+    method_name: &str,
+    arguments: &[Token],
+    options: &CallOptions,
+) -> Result<CallResultMeta, EthRpcError> {
+    let function = resolve_function(contract_abi, method_name)?;
+    let encoded_data = function
+        .encode_input(arguments)
+        .map_err(|err| EthRpcError::InvalidInput(err.to_string()))?;
+    let decode = |data: &[u8]| {
+        function
+            .decode_output(data)
+            .map_err(|err| EthRpcError::AbiDecode(err.to_string()))
+    };
+    let contract: Address = address
+        .parse()
+        .map_err(|_| EthRpcError::InvalidInput("Invalid contract address".to_string()))?;
+    if let Some(max_staleness_ns) = options.max_cache_staleness_ns {
+        let cached = cache::lookup(
+            network,
+            contract,
+            &encoded_data,
+            options.block,
+            max_staleness_ns,
+        );
+        if let Some(data) = cached {
+            return Ok(CallResultMeta {
+                tokens: decode(&data)?,
+                raw: data,
+                block: options.block,
+                head_block: known_head_block(network),
+                trace: None,
+                retries: 0,
+                from_cache: true,
+            });
+        }
+    }
+
+    let call_data = EthCallData {
+        sender: call_sender(options).await?,
+        recipient: address,
+        payload: to_hex(&encoded_data),
+        amount: None,
+    };
+    let requests = [
+        BatchRequest {
+            method: "eth_call".to_string(),
+            params: serde_json::json!([call_data, options.block]),
+            rounding: None,
         },
-        HttpHeader {
-            name: "Host".to_string(),
-            value: host_header.to_string(),
+        BatchRequest {
+            method: "eth_blockNumber".to_string(),
+            params: serde_json::json!([]),
+            rounding: None,
         },
     ];
+    let entries = &batch_entries(&requests);
+    let attempts = Cell::new(0);
+    let (responses, trace) = with_failover(
+        network,
+        candidate_providers(network, options.block),
+        |provider| {
+            attempts.set(attempts.get() + 1);
+            async move { batch_request_traced(&provider, entries, options).await }
+        },
+    )
+    .await?;
+    let mut results = batch_results(entries, responses)?.into_iter();
+    let outcome: Bytes = results
+        .next()
+        .ok_or_else(|| EthRpcError::InvalidResponse("Missing eth_call result".to_string()))?
+        .and_then(|outcome| {
+            serde_json::from_value(outcome)
+                .map_err(|_| EthRpcError::InvalidResponse("Malformed call result".to_string()))
+        })?;
+    let head_block = results
+        .next()
+        .and_then(Result::ok)
+        .and_then(|head| serde_json::from_value::<U64>(head).ok())
+        .map(|head| head.as_u64());
+    if let Some(head) = head_block {
+        observe_head_block(network, head);
+    }
+    let tokens = decode(&outcome)?;
+    if options.max_cache_staleness_ns.is_some() {
+        cache::store(
+            network,
+            contract,
+            &encoded_data,
+            options.block,
+            outcome.to_vec(),
+        );
+    }
+    Ok(CallResultMeta {
+        tokens,
+        raw: outcome.to_vec(),
+        block: options.block,
+        head_block,
+        trace: Some(trace),
+        retries: attempts.get() - 1,
+        from_cache: false,
+    })
+}
 
-    // Prepare the HTTP request
-    let http_request_data = CanisterHttpRequestArgument {
-        url: rpc_url,
-        max_response_bytes: Some(MAX_BYTES),
-        method: HttpMethod::POST,
-        headers,
-        body: Some(rpc_payload.as_bytes().to_vec()),
-        transform: Some(TransformContext::from_name(
-            "handle_transform".to_string(),
-            vec![],
-        )),
+/// Perform a call to an Ethereum smart contract together with an `eth_estimateGas`
+/// of the same calldata in one batch, returning the outputs and the gas the call uses
+pub async fn call_with_gas(
+    network: &str,
+    address: Address,
+    contract_abi: &Contract,
+    method_name: &str,
+    arguments: &[Token],
+    block: BlockTag,
+) -> Result<(Vec<Token>, U256), EthRpcError> {
+    let function = resolve_function(contract_abi, method_name)?;
+    let encoded_data = function
+        .encode_input(arguments)
+        .map_err(|err| EthRpcError::InvalidInput(err.to_string()))?;
+    let call_data = EthCallData {
+        sender: None,
+        recipient: to_hex(address.as_bytes()),
+        payload: to_hex(&encoded_data),
+        amount: None,
+    };
+    let requests = ["eth_call", "eth_estimateGas"].map(|method| BatchRequest {
+        method: method.to_string(),
+        params: serde_json::json!([call_data, block]),
+        rounding: None,
+    });
+    let options = CallOptions {
+        block,
+        ..Default::default()
     };
+    let [outcome, gas]: [Result<Value, EthRpcError>; 2] = batch_call(network, &requests, &options)
+        .await?
+        .try_into()
+        .map_err(|_| EthRpcError::InvalidResponse("Unexpected batch response".to_string()))?;
+    let outcome: Bytes = serde_json::from_value(outcome?)
+        .map_err(|_| EthRpcError::InvalidResponse("Malformed call result".to_string()))?;
+    let gas: U256 = serde_json::from_value(gas?)
+        .map_err(|_| EthRpcError::InvalidResponse("Malformed gas estimate".to_string()))?;
+    let tokens = function
+        .decode_output(&outcome)
+        .map_err(|err| EthRpcError::AbiDecode(err.to_string()))?;
+    Ok((tokens, gas))
+}
 
-    // Perform the HTTP request
-    let response = match http_request(http_request_data, CYCLES_COST).await {
-        Ok((res,)) => res,
-        Err((res, msg)) => panic!("{:?} {:?}", res, msg),
+/// Perform the same call to an Ethereum smart contract at two blocks in one batch,
+/// returning the outputs at each block and whether they differ
+pub async fn diff_call(
+    network: &str,
+    address: Address,
+    contract_abi: &Contract,
+    method_name: &str,
+    arguments: &[Token],
+    block_a: BlockTag,
+    block_b: BlockTag,
+) -> Result<(Vec<Token>, Vec<Token>, bool), EthRpcError> {
+    let function = resolve_function(contract_abi, method_name)?;
+    let encoded_data = function
+        .encode_input(arguments)
+        .map_err(|err| EthRpcError::InvalidInput(err.to_string()))?;
+    let call_data = EthCallData {
+        sender: None,
+        recipient: to_hex(address.as_bytes()),
+        payload: to_hex(&encoded_data),
+        amount: None,
+    };
+    let requests = [block_a, block_b].map(|block| BatchRequest {
+        method: "eth_call".to_string(),
+        params: serde_json::json!([call_data, block]),
+        rounding: None,
+    });
+    // Route the batch by the block with the oldest state
+    let oldest_block = match (block_a, block_b) {
+        (BlockTag::Number(a), BlockTag::Number(b)) => BlockTag::Number(a.min(b)),
+        (BlockTag::Earliest, _) | (_, BlockTag::Earliest) => BlockTag::Earliest,
+        (block, other) if block.is_historical() || !other.is_historical() => block,
+        (_, other) => other,
     };
+    let options = CallOptions {
+        block: oldest_block,
+        ..Default::default()
+    };
+    let outputs = batch_call(network, &requests, &options)
+        .await?
+        .into_iter()
+        .map(|outcome| {
+            let outcome: Bytes = serde_json::from_value(outcome?)
+                .map_err(|_| EthRpcError::InvalidResponse("Malformed call result".to_string()))?;
+            function
+                .decode_output(&outcome)
+                .map_err(|err| EthRpcError::AbiDecode(err.to_string()))
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let [output_a, output_b]: [Vec<Token>; 2] = outputs
+        .try_into()
+        .map_err(|_| EthRpcError::InvalidResponse("Unexpected batch response".to_string()))?;
+    let changed = output_a != output_b;
+    Ok((output_a, output_b, changed))
+}
 
-    // Decode the JSON-RPC response
-    let rpc_result: RpcResponse =
-        serde_json::from_str(std::str::from_utf8(&response.body).expect("Invalid UTF-8"))
-            .expect("Malformed JSON response");
-    if let Some(err) = rpc_result.rpc_error {
-        panic!(
-            "JSON-RPC error code {}: {}",
-            err.error_code, err.error_message
-        );
+// Round the quantity result of a JSON-RPC response up.
+// Responses of any other shape are left unchanged.
+fn round_quantity_result(response: &mut Value, rounding: Rounding) {
+    let quantity = response
+        .get("result")
+        .and_then(Value::as_str)
+        .and_then(|result| result.strip_prefix("0x"))
+        .and_then(|digits| U256::from_str_radix(digits, 16).ok());
+    if let Some(quantity) = quantity {
+        response["result"] = Value::String(format!("{:#x}", rounding.apply(quantity)));
     }
-    let decoded_result = from_hex(&rpc_result.outcome.expect("Unexpected JSON response")).unwrap();
-    function
-        .decode_output(&decoded_result)
-        .expect("Error decoding output")
+}
+
+// Round quantity results of a response body as the transform context requires:
+// the result of a single response, or the results of batch responses by id
+fn round_response_body(body: Vec<u8>, context: &[u8]) -> Vec<u8> {
+    let mut response = match serde_json::from_slice::<Value>(&body) {
+        Ok(response) => response,
+        Err(_) => return body,
+    };
+    match context.split_first() {
+        Some((&BATCH_ROUNDING_TAG, roundings)) => {
+            let roundings = batch_rounding_from_context(roundings).unwrap_or_default();
+            let Some(entries) = response.as_array_mut() else {
+                return body;
+            };
+            for entry in entries {
+                let rounding = entry
+                    .get("id")
+                    .and_then(Value::as_u64)
+                    .and_then(|id| roundings.get(&id));
+                if let Some(rounding) = rounding {
+                    round_quantity_result(entry, *rounding);
+                }
+            }
+        }
+        _ => match Rounding::from_context(context) {
+            Some(rounding) => round_quantity_result(&mut response, rounding),
+            None => return body,
+        },
+    }
+    serde_json::to_vec(&response).unwrap_or(body)
 }
 
 #[ic_cdk_macros::query(name = "handle_transform")]
 pub fn handle_transform(args: TransformArgs) -> HttpResponse {
+    // Remove headers that can differ and affect consensus,
+    // except for the destination of a redirect
+    let is_redirect = args.response.status >= 300u64 && args.response.status < 400u64;
+    // The context holds the rounding of quantity results, if any
+    let body = round_response_body(args.response.body, &args.context);
     HttpResponse {
         status: args.response.status.clone(),
-        body: args.response.body,
-        // Remove headers that can differ and affect consensus
-        headers: Vec::new(),
+        body,
+        headers: args
+            .response
+            .headers
+            .into_iter()
+            .filter(|header| is_redirect && header.name.eq_ignore_ascii_case("Location"))
+            .collect(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::abis::registered_abi;
+    use crate::transport::{set_transport, MockTransport};
+    use futures::executor::block_on;
+    use std::rc::Rc;
+
+    const WETH: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+
+    // Install a transport answering every JSON-RPC request with a result
+    fn mock_result(result: Value) -> Rc<MockTransport> {
+        let transport = Rc::new(MockTransport::new(move |request| {
+            let body: Value = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            Ok(HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(&serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": body["id"],
+                    "result": result,
+                }))
+                .unwrap(),
+            })
+        }));
+        set_transport(transport.clone());
+        transport
+    }
+
+    #[test]
+    fn contract_call_decodes_outputs() {
+        let transport = mock_result(format!("0x{:064x}", 42).into());
+        let abi = registered_abi("weth").unwrap();
+        let output = block_on(try_execute_contract_call(
+            "mainnet",
+            WETH.to_string(),
+            &abi.contract,
+            "totalSupply",
+            &[],
+            &CallOptions::default(),
+        ))
+        .unwrap();
+        assert_eq!(output.tokens, vec![Token::Uint(42.into())]);
+        assert_eq!(transport.requests().len(), 1);
+    }
+
+    // Stand-in for a gateway's HMAC: a keccak256 digest of the body
+    fn sign_body(body: &[u8]) -> HttpHeader {
+        HttpHeader {
+            name: "X-Signature".to_string(),
+            value: format!("keccak256={}", to_hex(&ethers_core::utils::keccak256(body))),
+        }
+    }
+
+    #[test]
+    fn signs_the_body_sent() {
+        let transport = mock_result(format!("0x{:064x}", 42).into());
+        let abi = registered_abi("weth").unwrap();
+        let options = CallOptions {
+            sign_request: Some(sign_body),
+            ..Default::default()
+        };
+        for _ in 0..2 {
+            block_on(try_execute_contract_call(
+                "mainnet",
+                WETH.to_string(),
+                &abi.contract,
+                "totalSupply",
+                &[],
+                &options,
+            ))
+            .unwrap();
+        }
+
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        for request in &requests {
+            let body = request.body.as_ref().unwrap();
+            let signatures = request
+                .headers
+                .iter()
+                .filter(|header| header.name == "X-Signature")
+                .collect::<Vec<_>>();
+            // Appended once, after the standard headers, and over the body with its request id
+            assert_eq!(signatures, vec![&sign_body(body)]);
+            assert_eq!(request.headers.last(), Some(&sign_body(body)));
+            assert!(serde_json::from_slice::<Value>(body).unwrap()["id"].is_number());
+        }
+        // Each request's id differs, and so does its signature
+        assert_ne!(requests[0].headers, requests[1].headers);
+
+        let unsigned = mock_result(format!("0x{:064x}", 42).into());
+        block_on(try_execute_contract_call(
+            "mainnet",
+            WETH.to_string(),
+            &abi.contract,
+            "totalSupply",
+            &[],
+            &CallOptions::default(),
+        ))
+        .unwrap();
+        assert!(unsigned.requests()[0]
+            .headers
+            .iter()
+            .all(|header| header.name != "X-Signature"));
+    }
+
+    #[test]
+    fn contract_call_rejects_unknown_method_and_bad_arguments() {
+        let transport = mock_result(Value::Null);
+        let abi = registered_abi("weth").unwrap();
+        let call = |method: &'static str, arguments: Vec<Token>| {
+            block_on(try_execute_contract_call(
+                "mainnet",
+                WETH.to_string(),
+                &abi.contract,
+                method,
+                &arguments,
+                &CallOptions::default(),
+            ))
+        };
+        assert_eq!(
+            call("mint", vec![]),
+            Err(EthRpcError::InvalidInput("Function not found".to_string()))
+        );
+        assert!(matches!(
+            call("balanceOf", vec![Token::Bool(true)]),
+            Err(EthRpcError::InvalidInput(_))
+        ));
+        assert!(transport.requests().is_empty());
+    }
+
+    #[test]
+    fn raw_contract_call_returns_return_data() {
+        mock_result(format!("0x{:064x}", 42).into());
+        let abi = registered_abi("weth").unwrap();
+        let call = |method: &'static str| {
+            block_on(try_execute_contract_call(
+                "mainnet",
+                WETH.to_string(),
+                &abi.contract,
+                method,
+                &[],
+                &CallOptions::default(),
+            ))
+        };
+        let output = call("totalSupply").unwrap();
+        assert_eq!(output.tokens, vec![Token::Uint(42.into())]);
+        assert_eq!(
+            output.raw,
+            ethers_core::abi::encode(&[Token::Uint(42.into())])
+        );
+        assert_eq!(
+            call("mint").err(),
+            Some(EthRpcError::InvalidInput("Function not found".to_string()))
+        );
+    }
+
+    // Install a transport on which full nodes report missing state for the given
+    // blocks (as geth does for pruned state), and which otherwise answers calls with 42
+    fn mock_pruned_state(pruned: &'static [&'static str]) -> Rc<MockTransport> {
+        let transport = Rc::new(MockTransport::new(move |request| {
+            let body: Value = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            let block = body["params"][1].as_str().unwrap();
+            let response = if request.url.contains("full") && pruned.contains(&block) {
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": body["id"],
+                    "error": {
+                        "code": -32000,
+                        "message": "missing trie node 6d3bc8e37b5f2c1a (path )",
+                    },
+                })
+            } else {
+                serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": body["id"],
+                    "result": format!("0x{:064x}", 42),
+                })
+            };
+            Ok(HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(&response).unwrap(),
+            })
+        }));
+        set_transport(transport.clone());
+        transport
+    }
+
+    fn total_supply_with_fallback(
+        block: BlockTag,
+        state_fallback: StateFallback,
+    ) -> Result<CallOutput, EthRpcError> {
+        let abi = registered_abi("weth").unwrap();
+        block_on(try_execute_contract_call(
+            "mainnet",
+            WETH.to_string(),
+            &abi.contract,
+            "totalSupply",
+            &[],
+            &CallOptions {
+                block,
+                state_fallback,
+                ..CallOptions::default()
+            },
+        ))
+    }
+
+    // The block tag each request of a transport was made at, with its provider
+    fn requested_blocks(transport: &MockTransport) -> Vec<(String, String)> {
+        transport
+            .requests()
+            .iter()
+            .map(|request| {
+                let body: Value = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+                (
+                    request.url.clone(),
+                    body["params"][1].as_str().unwrap().to_string(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn archive_fallback_retries_only_on_archive_providers() {
+        let full = "https://full.example.com".to_string();
+        let archive = "https://archive.example.com".to_string();
+        crate::providers::restore(
+            [(
+                "mainnet".to_string(),
+                vec![
+                    full.clone(),
+                    "https://full-2.example.com".to_string(),
+                    archive.clone(),
+                ],
+            )]
+            .into_iter()
+            .collect(),
+        );
+        crate::providers::restore_archive(
+            [(
+                "mainnet".to_string(),
+                [(archive.clone(), crate::providers::ArchiveSupport::Archive)]
+                    .into_iter()
+                    .collect(),
+            )]
+            .into_iter()
+            .collect(),
+        );
+
+        let transport = mock_pruned_state(&["latest"]);
+        let output = total_supply_with_fallback(BlockTag::Latest, StateFallback::Archive).unwrap();
+        assert_eq!(output.tokens, vec![Token::Uint(42.into())]);
+        assert_eq!(output.block, BlockTag::Latest);
+        // The second full node isn't tried, and the block is kept
+        assert_eq!(
+            requested_blocks(&transport),
+            vec![
+                (full.clone(), "latest".to_string()),
+                (archive, "latest".to_string()),
+            ]
+        );
+
+        // Without a fallback, the error is returned
+        let transport = mock_pruned_state(&["latest"]);
+        assert!(matches!(
+            total_supply_with_fallback(BlockTag::Latest, StateFallback::None),
+            Err(EthRpcError::StateUnavailable(_))
+        ));
+        assert_eq!(transport.requests().len(), 1);
+
+        // Nor is there a retry without archive providers
+        crate::providers::restore_archive(BTreeMap::new());
+        let transport = mock_pruned_state(&["latest"]);
+        assert!(matches!(
+            total_supply_with_fallback(BlockTag::Latest, StateFallback::Archive),
+            Err(EthRpcError::StateUnavailable(_))
+        ));
+        assert_eq!(
+            requested_blocks(&transport),
+            vec![(full, "latest".to_string())]
+        );
+    }
+
+    #[test]
+    fn degraded_call_reports_the_block_used() {
+        let full = "https://full.example.com".to_string();
+        crate::providers::restore(
+            [("mainnet".to_string(), vec![full.clone()])]
+                .into_iter()
+                .collect(),
+        );
+
+        let transport = mock_pruned_state(&["0x10"]);
+        let output =
+            total_supply_with_fallback(BlockTag::Number(0x10), StateFallback::Degrade).unwrap();
+        assert_eq!(output.tokens, vec![Token::Uint(42.into())]);
+        assert_eq!(output.block, BlockTag::Finalized);
+        assert_eq!(
+            requested_blocks(&transport),
+            vec![
+                (full.clone(), "0x10".to_string()),
+                (full.clone(), "finalized".to_string()),
+            ]
+        );
+
+        // Failing the finalized block too, the call falls back to the latest
+        let transport = mock_pruned_state(&["0x10", "finalized"]);
+        let output =
+            total_supply_with_fallback(BlockTag::Number(0x10), StateFallback::Degrade).unwrap();
+        assert_eq!(output.block, BlockTag::Latest);
+        assert_eq!(transport.requests().len(), 3);
+
+        // The error is returned once every block has been tried
+        let transport = mock_pruned_state(&["0x10", "finalized", "latest"]);
+        assert!(matches!(
+            total_supply_with_fallback(BlockTag::Number(0x10), StateFallback::Degrade),
+            Err(EthRpcError::StateUnavailable(_))
+        ));
+        assert_eq!(transport.requests().len(), 3);
+    }
+
+    // Install a transport answering batches of `eth_call` and `eth_blockNumber`,
+    // where requests to providers whose URL contains `failing` are rejected
+    fn mock_batch_provider() -> Rc<MockTransport> {
+        let transport = Rc::new(MockTransport::new(|request| {
+            if request.url.contains("failing") {
+                return Err((
+                    ic_cdk::api::call::RejectionCode::SysTransient,
+                    "Timeout".to_string(),
+                ));
+            }
+            let body: Vec<Value> = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            let responses = body
+                .iter()
+                .map(|entry| {
+                    let result = match entry["method"].as_str().unwrap() {
+                        "eth_call" => format!("0x{:064x}", 42),
+                        "eth_blockNumber" => "0x10".to_string(),
+                        method => panic!("Unexpected method {}", method),
+                    };
+                    serde_json::json!({"jsonrpc": "2.0", "id": entry["id"], "result": result})
+                })
+                .collect::<Vec<_>>();
+            Ok(HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(&responses).unwrap(),
+            })
+        }));
+        set_transport(transport.clone());
+        transport
+    }
+
+    fn verbose_total_supply(options: &CallOptions) -> CallResultMeta {
+        let abi = registered_abi("weth").unwrap();
+        block_on(execute_contract_call_verbose(
+            "mainnet",
+            WETH.to_string(),
+            &abi.contract,
+            "totalSupply",
+            &[],
+            options,
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn verbose_call_counts_retries() {
+        crate::providers::restore(
+            [(
+                "mainnet".to_string(),
+                vec![
+                    "https://failing.example.com".to_string(),
+                    "https://backup.example.com".to_string(),
+                ],
+            )]
+            .into_iter()
+            .collect(),
+        );
+        let transport = mock_batch_provider();
+        let result = verbose_total_supply(&CallOptions::default());
+        assert_eq!(result.tokens, vec![Token::Uint(42.into())]);
+        assert_eq!(result.raw, ethers_core::abi::encode(&result.tokens));
+        assert_eq!(result.retries, 1);
+        assert!(!result.from_cache);
+        assert_eq!(result.head_block, Some(16));
+        assert_eq!(result.trace.unwrap().provider, "https://backup.example.com");
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[test]
+    fn verbose_call_reports_cache_hits() {
+        let transport = mock_batch_provider();
+        let options = CallOptions {
+            max_cache_staleness_ns: Some(60_000_000_000),
+            ..Default::default()
+        };
+        let fetched = verbose_total_supply(&options);
+        assert!(!fetched.from_cache);
+        assert_eq!(fetched.retries, 0);
+
+        let cached = verbose_total_supply(&options);
+        assert!(cached.from_cache);
+        assert_eq!(cached.tokens, fetched.tokens);
+        assert_eq!(cached.raw, fetched.raw);
+        assert_eq!(cached.trace, None);
+        assert_eq!(cached.head_block, Some(16));
+        assert_eq!(transport.requests().len(), 1);
+
+        // Results older than the staleness bound are fetched again
+        crate::clock::set_now(crate::clock::now() + 61_000_000_000);
+        assert!(!verbose_total_supply(&options).from_cache);
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    // Transport which yields once before answering, so that concurrent calls interleave
+    struct YieldingTransport(Rc<MockTransport>);
+
+    impl crate::transport::Transport for YieldingTransport {
+        fn http_request(
+            &self,
+            request: CanisterHttpRequestArgument,
+            cycles: u128,
+        ) -> futures::future::LocalBoxFuture<'static, ic_cdk::api::call::CallResult<HttpResponse>>
+        {
+            let response = self.0.http_request(request, cycles);
+            Box::pin(async move {
+                let mut yielded = false;
+                futures::future::poll_fn(|cx| {
+                    if yielded {
+                        std::task::Poll::Ready(())
+                    } else {
+                        yielded = true;
+                        cx.waker().wake_by_ref();
+                        std::task::Poll::Pending
+                    }
+                })
+                .await;
+                response.await
+            })
+        }
+    }
+
+    #[test]
+    fn concurrent_calls_use_distinct_request_ids() {
+        let transport = mock_result(format!("0x{:064x}", 42).into());
+        set_transport(Rc::new(YieldingTransport(transport.clone())));
+        let abi = registered_abi("weth").unwrap();
+        let call = || {
+            execute_contract_call(
+                "mainnet",
+                WETH.to_string(),
+                &abi.contract,
+                "totalSupply",
+                &[],
+            )
+        };
+        let (first, second) = block_on(futures::future::join(call(), call()));
+        assert_eq!(first, vec![Token::Uint(42.into())]);
+        assert_eq!(second, first);
+        let ids = transport
+            .requests()
+            .iter()
+            .map(|request| {
+                serde_json::from_slice::<Value>(request.body.as_ref().unwrap()).unwrap()["id"]
+                    .clone()
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(ids.len(), 2);
+        assert_ne!(ids[0], ids[1]);
+    }
+
+    #[test]
+    fn request_bodies_are_byte_identical() {
+        use crate::util::{token_to_json_with, HexOrDecimal, JsonOutputFormat};
+        use std::collections::HashMap;
+
+        let tokens = Token::Tuple(vec![
+            Token::Address(WETH.parse().unwrap()),
+            Token::Uint(255.into()),
+            Token::Int(ethers_core::types::I256::from(-1).into_raw()),
+            Token::Bytes(vec![0xde, 0xad]),
+            Token::Array(vec![Token::Bool(true), Token::String("x".into())]),
+        ]);
+        let hex = JsonOutputFormat {
+            uint_as: HexOrDecimal::Hex,
+        };
+        // Insert the same keys in different orders, so the maps' iteration order
+        // is unlikely to match
+        let body = |keys: &[&str]| {
+            let params = keys
+                .iter()
+                .map(|key| (key.to_string(), token_to_json_with(&tokens, hex)))
+                .collect::<HashMap<_, _>>();
+            to_canonical_json(&RpcRequest {
+                request_id: 7,
+                version: "2.0".to_string(),
+                action: "eth_call".to_string(),
+                parameters: vec![params],
+            })
+            .unwrap()
+        };
+        let expected = concat!(
+            r#"{"id":7,"jsonrpc":"2.0","method":"eth_call","params":[{"#,
+            r#""a":["0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2","0xff","-1","0xdead",[true,"x"]],"#,
+            r#""b":["0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2","0xff","-1","0xdead",[true,"x"]],"#,
+            r#""c":["0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2","0xff","-1","0xdead",[true,"x"]]}]}"#,
+        );
+        assert_eq!(body(&["a", "b", "c"]), expected);
+        assert_eq!(body(&["c", "b", "a"]), expected);
+        assert_eq!(body(&["b", "c", "a"]), expected);
+    }
+
+    #[test]
+    fn block_tags_serialize_as_json_rpc_tags() {
+        let cases = [
+            (BlockTag::Latest, "latest"),
+            (BlockTag::Pending, "pending"),
+            (BlockTag::Safe, "safe"),
+            (BlockTag::Finalized, "finalized"),
+            (BlockTag::Earliest, "earliest"),
+            (BlockTag::Number(0), "0x0"),
+            (BlockTag::Number(255), "0xff"),
+            (BlockTag::Number(19_000_000), "0x121eac0"),
+            (BlockTag::Number(u64::MAX), "0xffffffffffffffff"),
+        ];
+        for (tag, expected) in cases {
+            assert_eq!(serde_json::to_value(tag).unwrap(), Value::from(expected));
+        }
+    }
+
+    #[test]
+    fn expanded_secrets_never_appear_in_traces() {
+        use crate::transport::Recorder;
+
+        const SECRET: &str = "s3cr3t-api-key";
+        crate::secrets::restore([("alchemy".to_string(), SECRET.to_string())].into());
+        crate::providers::restore(
+            [(
+                "mainnet".to_string(),
+                vec![
+                    "https://failing.example.com/v2/{{secret:alchemy}}".to_string(),
+                    "https://eth.example.com/v2/{{secret:alchemy}}".to_string(),
+                ],
+            )]
+            .into_iter()
+            .collect(),
+        );
+        // Providers echo the URL (and so the secret) in errors and responses
+        let inner = Rc::new(MockTransport::new(|request| {
+            if request.url.contains("failing") {
+                return Err((
+                    ic_cdk::api::call::RejectionCode::SysTransient,
+                    format!("Timeout connecting to {}", request.url),
+                ));
+            }
+            let body: Value = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            let response = match body {
+                Value::Array(entries) => Value::Array(
+                    entries
+                        .iter()
+                        .map(|entry| {
+                            let result = match entry["method"].as_str().unwrap() {
+                                "eth_call" => format!("0x{:064x}", 42),
+                                _ => "0x10".to_string(),
+                            };
+                            serde_json::json!({"jsonrpc": "2.0", "id": entry["id"], "result": result})
+                        })
+                        .collect(),
+                ),
+                body => serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": body["id"],
+                    "error": {"code": -32000, "message": format!("rate limited: {}", request.url)},
+                }),
+            };
+            Ok(HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(&response).unwrap(),
+            })
+        }));
+        let recorder = Rc::new(Recorder::new(inner.clone()));
+        set_transport(recorder.clone());
+
+        let result = verbose_total_supply(&CallOptions::default());
+        assert_eq!(result.retries, 1);
+        assert_eq!(
+            result.trace.as_ref().unwrap().provider,
+            "https://eth.example.com/v2/{{secret:alchemy}}"
+        );
+        assert!(!format!("{:?}", result).contains(SECRET));
+
+        let error = block_on(chain_id("mainnet")).unwrap_err();
+        assert!(matches!(error, EthRpcError::JsonRpc { .. }));
+        assert!(!format!("{:?}", error).contains(SECRET));
+        assert!(!error.to_string().contains(SECRET));
+        assert!(error.to_string().contains("{{secret:alchemy}}"));
+
+        // The secret was sent, but isn't in the recorded fixture
+        assert!(inner
+            .requests()
+            .iter()
+            .all(|request| request.url.contains(SECRET)));
+        assert!(!recorder.interactions().is_empty());
+        assert!(!recorder.to_json().contains(SECRET));
+
+        crate::providers::restore(
+            [(
+                "mainnet".to_string(),
+                vec!["https://failing.example.com/v2/{{secret:alchemy}}".to_string()],
+            )]
+            .into_iter()
+            .collect(),
+        );
+        let error = block_on(chain_id("mainnet")).unwrap_err();
+        assert_eq!(
+            error,
+            EthRpcError::HttpOutcall {
+                code: "SysTransient".to_string(),
+                message: "Timeout connecting to https://failing.example.com/v2/{{secret:alchemy}}"
+                    .to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn calls_over_the_outcall_limit_are_refused() {
+        let transport = mock_result("0x1".into());
+        limiter::set_config(limiter::LimiterConfig {
+            max_in_flight: 1,
+            reserved_interactive: 0,
+            ..Default::default()
+        });
+        let permit = limiter::try_acquire(Priority::Interactive).unwrap();
+        assert_eq!(
+            block_on(chain_id("mainnet")),
+            Err(EthRpcError::OutcallLimitReached {
+                in_flight: 1,
+                limit: 1
+            })
+        );
+        // Refused before any provider was tried, so no health was recorded
+        assert!(transport.requests().is_empty());
+        assert!(crate::health::snapshot().is_empty());
+
+        drop(permit);
+        assert_eq!(block_on(chain_id("mainnet")), Ok(1));
+    }
+
+    #[test]
+    fn rounds_quantities_up() {
+        let digits = Rounding::SignificantDigits(3);
+        // Sub-gwei fees (as on L2s) are rounded relative to their size
+        assert_eq!(digits.apply(1_234_567u64.into()), 1_240_000u64.into());
+        assert_eq!(digits.apply(1_230_000u64.into()), 1_230_000u64.into());
+        assert_eq!(
+            digits.apply(12_345_678_901u64.into()),
+            12_400_000_000u64.into()
+        );
+        assert_eq!(digits.apply(999u64.into()), 999u64.into());
+        assert_eq!(digits.apply(U256::zero()), U256::zero());
+        assert_eq!(digits.apply(U256::MAX), U256::MAX);
+
+        let multiple = Rounding::Multiple(1_000_000_000);
+        assert_eq!(multiple.apply(1_234_567u64.into()), 1_000_000_000u64.into());
+        assert_eq!(
+            multiple.apply(2_000_000_000u64.into()),
+            2_000_000_000u64.into()
+        );
+
+        for rounding in [digits, multiple] {
+            assert_eq!(
+                Rounding::from_context(&rounding.to_context()),
+                Some(rounding)
+            );
+        }
+        assert_eq!(Rounding::from_context(&[]), None);
+    }
+
+    #[test]
+    fn transform_rounds_quantity_results() {
+        let transform = |result: &str, rounding: Option<Rounding>| {
+            let response = handle_transform(TransformArgs {
+                response: HttpResponse {
+                    status: 200u64.into(),
+                    headers: vec![HttpHeader {
+                        name: "Date".to_string(),
+                        value: "Thu, 15 Oct 2026 08:00:00 GMT".to_string(),
+                    }],
+                    body: serde_json::to_vec(
+                        &serde_json::json!({"jsonrpc": "2.0", "id": 1, "result": result}),
+                    )
+                    .unwrap(),
+                },
+                context: rounding.map(Rounding::to_context).unwrap_or_default(),
+            });
+            assert!(response.headers.is_empty());
+            serde_json::from_slice::<Value>(&response.body).unwrap()["result"].clone()
+        };
+        let digits = Some(Rounding::SignificantDigits(FEE_SIGNIFICANT_DIGITS));
+        // Replicas seeing slightly different 0.0012 gwei gas prices agree
+        assert_eq!(transform("0x12d687", digits), "0x12ebc0");
+        assert_eq!(transform("0x12e000", digits), "0x12ebc0");
+        assert_eq!(transform("0x12d687", None), "0x12d687");
+        // Non-quantity results are left alone
+        assert_eq!(transform("latest", digits), "latest");
+    }
+
+    #[test]
+    fn transform_rounds_batch_results_by_id() {
+        let digits = Some(Rounding::SignificantDigits(FEE_SIGNIFICANT_DIGITS));
+        let entries = batch_entries(&[
+            BatchRequest {
+                method: "eth_gasPrice".to_string(),
+                params: serde_json::json!([]),
+                rounding: digits,
+            },
+            BatchRequest {
+                method: "eth_getBalance".to_string(),
+                params: serde_json::json!([WETH, "latest"]),
+                rounding: None,
+            },
+        ]);
+        // Responses may come back in any order
+        let body = serde_json::json!([
+            {"jsonrpc": "2.0", "id": entries[1].id, "result": "0x12d687"},
+            {"jsonrpc": "2.0", "id": entries[0].id, "result": "0x12d687"},
+        ]);
+        let response = handle_transform(TransformArgs {
+            response: HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(&body).unwrap(),
+            },
+            context: batch_rounding_context(&entries),
+        });
+        let body: Value = serde_json::from_slice(&response.body).unwrap();
+        assert_eq!(body[0]["result"], "0x12d687");
+        assert_eq!(body[1]["result"], "0x12ebc0");
     }
 }
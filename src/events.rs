@@ -0,0 +1,170 @@
+use ethers_core::abi::{Contract, Error, Event, EventExt, Log, RawLog};
+use ethers_core::types::H256;
+
+use crate::error::{EthRpcError, Overload};
+use crate::util::to_hex;
+
+/// Compute the signature topic (topic0) of an event, for use in log filters.
+///
+/// Overloaded events must be given by their full signature (e.g. `Transfer(address,address,uint256)`).
+pub fn event_topic(abi: &Contract, event_name: &str) -> Result<H256, EthRpcError> {
+    match abi.events_by_name(event_name).map(|v| &v[..]) {
+        Ok([event]) => Ok(event.signature()),
+        Ok(overloads) => Err(EthRpcError::AmbiguousOverload {
+            name: event_name.to_string(),
+            overloads: overloads
+                .iter()
+                .map(|event| Overload {
+                    signature: event.abi_signature(),
+                    selector: to_hex(event.signature().as_bytes()),
+                })
+                .collect(),
+        }),
+        Err(_) => abi
+            .events()
+            .find(|event| event_name == event.abi_signature())
+            .map(|event| event.signature())
+            .ok_or_else(|| EthRpcError::InvalidInput(format!("Event not found: {}", event_name))),
+    }
+}
+
+/// Decode a log emitted by a contract, matching the event by its signature topic (topic0).
+///
+/// Anonymous events don't emit a signature topic, so they are never matched here;
+/// use [`decode_anonymous_log`] and name the event explicitly instead.
+pub fn decode_log(abi: &Contract, topics: Vec<H256>, data: Vec<u8>) -> Result<Log, Error> {
+    let signature = *topics.first().ok_or(Error::InvalidData)?;
+    let event = abi
+        .events()
+        .find(|event| !event.anonymous && event.signature() == signature)
+        .ok_or_else(|| Error::InvalidName(format!("{:?}", signature)))?;
+    event.parse_log(RawLog { topics, data })
+}
+
+/// Find the anonymous event with the given name which a log with `topic_count` topics
+/// was emitted by. Overloads are distinguished by their number of indexed params.
+pub fn anonymous_event<'a>(
+    abi: &'a Contract,
+    event_name: &str,
+    topic_count: usize,
+) -> Result<&'a Event, Error> {
+    abi.events_by_name(event_name)?
+        .iter()
+        .filter(|event| event.anonymous)
+        .find(|event| event.inputs.iter().filter(|input| input.indexed).count() == topic_count)
+        .ok_or_else(|| Error::InvalidName(format!("No matching anonymous event: {}", event_name)))
+}
+
+/// Decode a log emitted by an anonymous event with the given name.
+///
+/// Every topic is decoded as an indexed parameter, since anonymous events have no
+/// signature topic to skip. Overloads are distinguished by their number of indexed params.
+pub fn decode_anonymous_log(
+    abi: &Contract,
+    event_name: &str,
+    topics: Vec<H256>,
+    data: Vec<u8>,
+) -> Result<Log, Error> {
+    let event = anonymous_event(abi, event_name, topics.len())?;
+    event.parse_log(RawLog { topics, data })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::abi::{LogParam, Token};
+    use ethers_core::types::{Address, U256};
+
+    // DSNote's anonymous `LogNote`, plus an overload with a single indexed param
+    const NOTE_ABI: &str = r#"[
+        {
+            "type": "event",
+            "name": "LogNote",
+            "anonymous": true,
+            "inputs": [
+                {"name": "sig", "type": "bytes4", "indexed": true},
+                {"name": "guy", "type": "address", "indexed": true},
+                {"name": "foo", "type": "bytes32", "indexed": true},
+                {"name": "bar", "type": "bytes32", "indexed": true},
+                {"name": "wad", "type": "uint256", "indexed": false},
+                {"name": "fax", "type": "bytes", "indexed": false}
+            ]
+        },
+        {
+            "type": "event",
+            "name": "LogNote",
+            "anonymous": true,
+            "inputs": [
+                {"name": "guy", "type": "address", "indexed": true},
+                {"name": "wad", "type": "uint256", "indexed": false}
+            ]
+        }
+    ]"#;
+
+    fn word(value: impl Into<U256>) -> Vec<u8> {
+        let mut word = [0; 32];
+        value.into().to_big_endian(&mut word);
+        word.to_vec()
+    }
+
+    fn params(log: Log) -> Vec<(String, Token)> {
+        log.params
+            .into_iter()
+            .map(|LogParam { name, value }| (name, value))
+            .collect()
+    }
+
+    #[test]
+    fn decodes_anonymous_overloads_by_topic_count() {
+        let abi = serde_json::from_str::<Contract>(NOTE_ABI).unwrap();
+        let guy = Address::repeat_byte(0x11);
+        let fax = vec![0xa9, 0x05, 0x9c, 0xbb];
+        let mut sig = [0; 32];
+        sig[..4].copy_from_slice(&fax);
+
+        // Every topic is an indexed param: there is no signature topic to skip
+        let topics = vec![
+            H256(sig),
+            H256::from(guy),
+            H256::repeat_byte(0xaa),
+            H256::repeat_byte(0xbb),
+        ];
+        let mut data = [word(0), word(0x40), word(4), word(0)].concat();
+        data[96..100].copy_from_slice(&fax);
+        assert_eq!(
+            params(decode_anonymous_log(&abi, "LogNote", topics, data).unwrap()),
+            vec![
+                ("sig".to_string(), Token::FixedBytes(fax.clone())),
+                ("guy".to_string(), Token::Address(guy)),
+                ("foo".to_string(), Token::FixedBytes(vec![0xaa; 32])),
+                ("bar".to_string(), Token::FixedBytes(vec![0xbb; 32])),
+                ("wad".to_string(), Token::Uint(0.into())),
+                ("fax".to_string(), Token::Bytes(fax)),
+            ]
+        );
+
+        let event = anonymous_event(&abi, "LogNote", 1).unwrap();
+        assert_eq!(event.inputs.len(), 2);
+        assert_eq!(
+            params(decode_anonymous_log(&abi, "LogNote", vec![H256::from(guy)], word(5)).unwrap()),
+            vec![
+                ("guy".to_string(), Token::Address(guy)),
+                ("wad".to_string(), Token::Uint(5.into())),
+            ]
+        );
+
+        // No overload has two indexed params
+        assert!(anonymous_event(&abi, "LogNote", 2).is_err());
+        assert!(decode_anonymous_log(&abi, "Transfer", vec![], vec![]).is_err());
+    }
+
+    #[test]
+    fn decode_log_never_matches_anonymous_events() {
+        let abi = serde_json::from_str::<Contract>(NOTE_ABI).unwrap();
+        let guy = Address::repeat_byte(0x11);
+        // Even a log whose first topic happens to be the event's signature hash
+        let event = anonymous_event(&abi, "LogNote", 1).unwrap();
+        let topics = vec![event.signature(), H256::from(guy)];
+        assert!(decode_log(&abi, topics, word(5)).is_err());
+    }
+}
@@ -0,0 +1,491 @@
+use candid::CandidType;
+use ethers_core::abi::{self, Token};
+use ethers_core::types::{Address, TransactionRequest, U256};
+use ethers_core::utils::keccak256;
+use serde::Deserialize;
+
+use crate::abis::registered_abi;
+use crate::accounts::{self, EthAccount};
+use crate::decoded::DecodedValue;
+use crate::error::EthRpcError;
+use crate::eth_rpc::{try_execute_contract_call, CallOptions};
+use crate::sequences;
+use crate::transactions::prepare_contract_tx;
+use crate::util::to_hex;
+
+/// State of a proposal, as returned by an OpenZeppelin Governor's `state(proposalId)`
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum ProposalState {
+    Pending,
+    Active,
+    Canceled,
+    Defeated,
+    Succeeded,
+    Queued,
+    Expired,
+    Executed,
+}
+
+impl TryFrom<U256> for ProposalState {
+    type Error = EthRpcError;
+
+    fn try_from(state: U256) -> Result<Self, Self::Error> {
+        const STATES: [ProposalState; 8] = [
+            ProposalState::Pending,
+            ProposalState::Active,
+            ProposalState::Canceled,
+            ProposalState::Defeated,
+            ProposalState::Succeeded,
+            ProposalState::Queued,
+            ProposalState::Expired,
+            ProposalState::Executed,
+        ];
+        if state >= U256::from(STATES.len()) {
+            return Err(EthRpcError::AbiDecode(format!(
+                "Unknown proposal state: {}",
+                state
+            )));
+        }
+        Ok(STATES[state.as_usize()])
+    }
+}
+
+/// Vote options of `castVote` (`GovernorCountingSimple`)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum VoteType {
+    Against,
+    For,
+    Abstain,
+}
+
+/// Vote tallies of a proposal
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ProposalVotes {
+    pub against_votes: U256,
+    pub for_votes: U256,
+    pub abstain_votes: U256,
+}
+
+/// State, vote tallies, voting deadline and quorum of a proposal, as returned by
+/// `governor_proposal`. Amounts and timepoints are decimal strings.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ProposalSummary {
+    pub state: ProposalState,
+    pub against_votes: String,
+    pub for_votes: String,
+    pub abstain_votes: String,
+    /// Block number or timestamp (depending on the Governor's clock) at which voting ends
+    pub deadline: String,
+    /// Votes required for quorum, as of the proposal's snapshot
+    pub quorum: String,
+}
+
+/// A proposal submitted from an account, as returned by `governor_propose`
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ProposalSubmission {
+    /// Decimal proposal id, as computed by the Governor's `hashProposal`
+    pub proposal_id: String,
+    /// Operation id of the sequence sending the proposal
+    pub operation_id: u64,
+}
+
+// Call a view function of a Governor contract
+async fn call(
+    network: &str,
+    governor: Address,
+    method: &str,
+    args: &[Token],
+) -> Result<Vec<Token>, EthRpcError> {
+    let abi = registered_abi("governor").expect("Governor ABI not registered");
+    let output = try_execute_contract_call(
+        network,
+        to_hex(governor.as_bytes()),
+        &abi.contract,
+        method,
+        args,
+        &CallOptions::default(),
+    )
+    .await?;
+    Ok(output.tokens)
+}
+
+fn uint(tokens: &[Token], index: usize) -> Result<U256, EthRpcError> {
+    let token = tokens
+        .get(index)
+        .ok_or_else(|| EthRpcError::AbiDecode("Missing output".to_string()))?;
+    DecodedValue(token).as_uint()
+}
+
+/// Get the state of a proposal
+pub async fn proposal_state(
+    network: &str,
+    governor: Address,
+    proposal_id: U256,
+) -> Result<ProposalState, EthRpcError> {
+    let tokens = call(network, governor, "state", &[Token::Uint(proposal_id)]).await?;
+    ProposalState::try_from(uint(&tokens, 0)?)
+}
+
+/// Get the vote tallies of a proposal
+pub async fn proposal_votes(
+    network: &str,
+    governor: Address,
+    proposal_id: U256,
+) -> Result<ProposalVotes, EthRpcError> {
+    let tokens = call(
+        network,
+        governor,
+        "proposalVotes",
+        &[Token::Uint(proposal_id)],
+    )
+    .await?;
+    Ok(ProposalVotes {
+        against_votes: uint(&tokens, 0)?,
+        for_votes: uint(&tokens, 1)?,
+        abstain_votes: uint(&tokens, 2)?,
+    })
+}
+
+/// Get the number of votes required for quorum at a timepoint (block number or timestamp)
+pub async fn quorum(
+    network: &str,
+    governor: Address,
+    timepoint: U256,
+) -> Result<U256, EthRpcError> {
+    let tokens = call(network, governor, "quorum", &[Token::Uint(timepoint)]).await?;
+    uint(&tokens, 0)
+}
+
+/// Get the timepoint at which voting on a proposal ends
+pub async fn voting_deadline(
+    network: &str,
+    governor: Address,
+    proposal_id: U256,
+) -> Result<U256, EthRpcError> {
+    let tokens = call(
+        network,
+        governor,
+        "proposalDeadline",
+        &[Token::Uint(proposal_id)],
+    )
+    .await?;
+    uint(&tokens, 0)
+}
+
+/// Get the timepoint at which the voting power of a proposal's voters is counted
+pub async fn proposal_snapshot(
+    network: &str,
+    governor: Address,
+    proposal_id: U256,
+) -> Result<U256, EthRpcError> {
+    let tokens = call(
+        network,
+        governor,
+        "proposalSnapshot",
+        &[Token::Uint(proposal_id)],
+    )
+    .await?;
+    uint(&tokens, 0)
+}
+
+/// Get the state, vote tallies, voting deadline and quorum of a proposal
+pub async fn proposal_summary(
+    network: &str,
+    governor: Address,
+    proposal_id: U256,
+) -> Result<ProposalSummary, EthRpcError> {
+    let state = proposal_state(network, governor, proposal_id).await?;
+    let votes = proposal_votes(network, governor, proposal_id).await?;
+    let deadline = voting_deadline(network, governor, proposal_id).await?;
+    let snapshot = proposal_snapshot(network, governor, proposal_id).await?;
+    let quorum = quorum(network, governor, snapshot).await?;
+    Ok(ProposalSummary {
+        state,
+        against_votes: votes.against_votes.to_string(),
+        for_votes: votes.for_votes.to_string(),
+        abstain_votes: votes.abstain_votes.to_string(),
+        deadline: deadline.to_string(),
+        quorum: quorum.to_string(),
+    })
+}
+
+/// Compute a proposal id the same way as the Governor's `hashProposal`:
+/// `uint256(keccak256(abi.encode(targets, values, calldatas, keccak256(bytes(description)))))`
+pub fn hash_proposal(
+    targets: &[Address],
+    values: &[U256],
+    calldatas: &[Vec<u8>],
+    description: &str,
+) -> U256 {
+    let encoded = abi::encode(&[
+        Token::Array(targets.iter().copied().map(Token::Address).collect()),
+        Token::Array(values.iter().copied().map(Token::Uint).collect()),
+        Token::Array(calldatas.iter().cloned().map(Token::Bytes).collect()),
+        Token::FixedBytes(keccak256(description.as_bytes()).to_vec()),
+    ]);
+    U256::from_big_endian(&keccak256(encoded))
+}
+
+/// Build a transaction voting on a proposal from an account
+pub async fn cast_vote(
+    account: &EthAccount,
+    governor: Address,
+    proposal_id: U256,
+    support: VoteType,
+) -> Result<TransactionRequest, EthRpcError> {
+    let args = [
+        Token::Uint(proposal_id),
+        Token::Uint((support as u8).into()),
+    ];
+    prepare_contract_tx(
+        &account.network,
+        governor,
+        "governor",
+        "castVote",
+        &args,
+        U256::zero(),
+        Some(account),
+    )
+    .await
+}
+
+/// Build a transaction voting on a proposal with a reason from an account
+pub async fn cast_vote_with_reason(
+    account: &EthAccount,
+    governor: Address,
+    proposal_id: U256,
+    support: VoteType,
+    reason: String,
+) -> Result<TransactionRequest, EthRpcError> {
+    let args = [
+        Token::Uint(proposal_id),
+        Token::Uint((support as u8).into()),
+        Token::String(reason),
+    ];
+    prepare_contract_tx(
+        &account.network,
+        governor,
+        "governor",
+        "castVoteWithReason",
+        &args,
+        U256::zero(),
+        Some(account),
+    )
+    .await
+}
+
+/// Build a transaction creating a proposal from an account. The Governor only
+/// accepts proposals from accounts with at least its proposal threshold of votes.
+pub async fn propose(
+    account: &EthAccount,
+    governor: Address,
+    targets: &[Address],
+    values: &[U256],
+    calldatas: &[Vec<u8>],
+    description: String,
+) -> Result<TransactionRequest, EthRpcError> {
+    if values.len() != targets.len() || calldatas.len() != targets.len() {
+        return Err(EthRpcError::InvalidInput(
+            "Targets, values and calldatas must have the same length".to_string(),
+        ));
+    }
+    let args = [
+        Token::Array(targets.iter().copied().map(Token::Address).collect()),
+        Token::Array(values.iter().copied().map(Token::Uint).collect()),
+        Token::Array(calldatas.iter().cloned().map(Token::Bytes).collect()),
+        Token::String(description),
+    ];
+    prepare_contract_tx(
+        &account.network,
+        governor,
+        "governor",
+        "propose",
+        &args,
+        U256::zero(),
+        Some(account),
+    )
+    .await
+}
+
+/// Vote on a proposal from an account, optionally with a reason, returning the
+/// operation id of the sequence sending the vote
+pub async fn submit_vote(
+    label: &str,
+    governor: Address,
+    proposal_id: U256,
+    support: VoteType,
+    reason: Option<String>,
+) -> Result<u64, String> {
+    let account = accounts::account(label)?;
+    let tx = match reason {
+        Some(reason) => {
+            cast_vote_with_reason(&account, governor, proposal_id, support, reason).await
+        }
+        None => cast_vote(&account, governor, proposal_id, support).await,
+    }
+    .map_err(|err| err.to_string())?;
+    sequences::submit_transaction(label, tx).await
+}
+
+/// Create a proposal from an account, returning its id along with the operation id
+/// of the sequence sending it
+pub async fn submit_proposal(
+    label: &str,
+    governor: Address,
+    targets: &[Address],
+    values: &[U256],
+    calldatas: &[Vec<u8>],
+    description: String,
+) -> Result<ProposalSubmission, String> {
+    let account = accounts::account(label)?;
+    let proposal_id = hash_proposal(targets, values, calldatas, &description);
+    let tx = propose(&account, governor, targets, values, calldatas, description)
+        .await
+        .map_err(|err| err.to_string())?;
+    Ok(ProposalSubmission {
+        proposal_id: proposal_id.to_string(),
+        operation_id: sequences::submit_transaction(label, tx).await?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{set_transport, MockTransport};
+    use crate::util::from_hex;
+    use ethers_core::utils::id;
+    use futures::executor::block_on;
+    use ic_cdk::api::management_canister::http_request::HttpResponse;
+    use serde_json::Value;
+    use std::rc::Rc;
+
+    #[test]
+    fn summarizes_proposal() {
+        set_transport(Rc::new(MockTransport::new(|request| {
+            let body: Value = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            let data = from_hex(body["params"][0]["data"].as_str().unwrap()).unwrap();
+            let outputs = match &data[..4] {
+                selector if selector == id("state(uint256)") => vec![Token::Uint(4.into())],
+                selector if selector == id("proposalVotes(uint256)") => vec![
+                    Token::Uint(1.into()),
+                    Token::Uint(U256::exp10(24)),
+                    Token::Uint(0.into()),
+                ],
+                selector if selector == id("proposalDeadline(uint256)") => {
+                    vec![Token::Uint(19_000_000.into())]
+                }
+                selector if selector == id("proposalSnapshot(uint256)") => {
+                    vec![Token::Uint(18_950_000.into())]
+                }
+                selector if selector == id("quorum(uint256)") => {
+                    assert_eq!(
+                        abi::decode(&[abi::ParamType::Uint(256)], &data[4..]).unwrap(),
+                        vec![Token::Uint(18_950_000.into())]
+                    );
+                    vec![Token::Uint(U256::exp10(22) * 4)]
+                }
+                selector => panic!("Unexpected selector {:?}", selector),
+            };
+            Ok(HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(&serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": body["id"],
+                    "result": to_hex(&abi::encode(&outputs)),
+                }))
+                .unwrap(),
+            })
+        })));
+        let summary = block_on(proposal_summary(
+            "mainnet",
+            Address::repeat_byte(1),
+            U256::from(7),
+        ))
+        .unwrap();
+        assert_eq!(
+            summary,
+            ProposalSummary {
+                state: ProposalState::Succeeded,
+                against_votes: "1".to_string(),
+                for_votes: "1000000000000000000000000".to_string(),
+                abstain_votes: "0".to_string(),
+                deadline: "19000000".to_string(),
+                quorum: "40000000000000000000000".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn refuses_proposal_with_mismatched_actions() {
+        let account = EthAccount {
+            network: "mainnet".to_string(),
+            chain_id: 1,
+            derivation_path: vec![],
+            address: to_hex(Address::repeat_byte(2).as_bytes()),
+            nonce: None,
+            balance_wei: None,
+            balance_updated_at: None,
+            pending_transactions: vec![],
+        };
+        assert_eq!(
+            block_on(propose(
+                &account,
+                Address::repeat_byte(1),
+                &[Address::repeat_byte(3)],
+                &[],
+                &[vec![]],
+                "Empty".to_string(),
+            )),
+            Err(EthRpcError::InvalidInput(
+                "Targets, values and calldatas must have the same length".to_string()
+            ))
+        );
+    }
+
+    // Expected ids were computed independently of ethers, with a separate keccak
+    // implementation and hand-written ABI encoding
+
+    #[test]
+    fn hashes_empty_proposal() {
+        assert_eq!(
+            hash_proposal(&[], &[], &[], ""),
+            U256::from_dec_str(
+                "85241822993846202941891241759820259544799876294067545763914945775461784697891"
+            )
+            .unwrap()
+        );
+    }
+
+    #[test]
+    fn hashes_proposal_with_calls() {
+        let targets = [
+            "0xC18360217D8F7Ab5e7c516566761Ea12Ce7F9D72"
+                .parse()
+                .unwrap(),
+            "0xFe89cc7aBB2C4183683ab71653C4cdc9B02D44b7"
+                .parse()
+                .unwrap(),
+        ];
+        let values = [U256::zero(), U256::exp10(18)];
+        // `transfer(0x2c75…5c23, 25000e18)` and a plain ETH transfer
+        let calldatas = [
+            from_hex(concat!(
+                "0xa9059cbb",
+                "0000000000000000000000002c7536e3605d9c16a7a3d7b1898e529396a65c23",
+                "00000000000000000000000000000000000000000000054b40b1f852bda00000",
+            ))
+            .unwrap(),
+            vec![],
+        ];
+        let description =
+            "# [EP 0.0] Test grant\n\nTransfer 25,000 ENS and 1 ETH to the working group.";
+        assert_eq!(
+            hash_proposal(&targets, &values, &calldatas, description),
+            U256::from_dec_str(
+                "71254518296554265090755433876681837129768978457844453320281918211486208928298"
+            )
+            .unwrap()
+        );
+    }
+}
@@ -0,0 +1,436 @@
+use candid::CandidType;
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::time::Duration;
+
+use crate::error::EthRpcError;
+use crate::eth_rpc::probe_provider;
+use crate::metrics;
+use crate::providers::{self, archive_support, list_providers, ArchiveSupport};
+
+// Consecutive failures after which a provider is put on probation
+const PROBATION_THRESHOLD: u32 = 3;
+// Number of recent latencies kept for percentile estimates
+const LATENCY_WINDOW: usize = 50;
+// Number of recent request outcomes kept for success rates
+const OUTCOME_WINDOW: usize = 100;
+// How often providers on probation are probed
+const PROBE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Selection status of a provider
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub enum ProviderStatus {
+    /// In normal rotation
+    #[default]
+    Active,
+    /// Excluded from rotation after repeated failures until a probe succeeds
+    Probation,
+    /// Kept in rotation by an admin regardless of failures
+    ForceEnabled,
+    /// Excluded from rotation by an admin regardless of probes
+    ForceDisabled,
+}
+
+/// Rolling statistics for a single provider
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct ProviderStats {
+    pub status: ProviderStatus,
+    /// Whether each recent request succeeded, oldest first
+    pub recent_outcomes: VecDeque<bool>,
+    pub consecutive_failures: u32,
+    pub error_counts: BTreeMap<String, u64>,
+    pub recent_latencies_ns: VecDeque<u64>,
+}
+
+/// Health report for a provider, as returned by `provider_health`
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProviderHealth {
+    pub url: String,
+    pub archive: ArchiveSupport,
+    pub status: ProviderStatus,
+    pub score: f64,
+    pub success_rate: f64,
+    pub p95_latency_ns: Option<u64>,
+    pub consecutive_failures: u32,
+    pub error_counts: Vec<(String, u64)>,
+}
+
+impl ProviderStats {
+    fn successes(&self) -> usize {
+        self.recent_outcomes
+            .iter()
+            .filter(|succeeded| **succeeded)
+            .count()
+    }
+
+    fn success_rate(&self) -> f64 {
+        if self.recent_outcomes.is_empty() {
+            1.0
+        } else {
+            self.successes() as f64 / self.recent_outcomes.len() as f64
+        }
+    }
+
+    fn p95_latency_ns(&self) -> Option<u64> {
+        let mut latencies = self.recent_latencies_ns.iter().copied().collect::<Vec<_>>();
+        latencies.sort_unstable();
+        let index = (latencies.len() * 95).div_ceil(100);
+        latencies.get(index.saturating_sub(1)).copied()
+    }
+
+    // Smoothed success rate, so a single early failure doesn't dominate
+    fn score(&self) -> f64 {
+        (self.successes() as f64 + 1.0) / (self.recent_outcomes.len() as f64 + 2.0)
+    }
+
+    fn record_outcome(&mut self, succeeded: bool) {
+        self.recent_outcomes.push_back(succeeded);
+        if self.recent_outcomes.len() > OUTCOME_WINDOW {
+            self.recent_outcomes.pop_front();
+        }
+    }
+
+    fn in_rotation(&self) -> bool {
+        matches!(
+            self.status,
+            ProviderStatus::Active | ProviderStatus::ForceEnabled
+        )
+    }
+}
+
+// Statistics by provider URL template
+thread_local! {
+    static STATS: RefCell<BTreeMap<String, ProviderStats>> = RefCell::default();
+}
+
+fn with_stats<R>(provider: &str, f: impl FnOnce(&mut ProviderStats) -> R) -> R {
+    STATS.with(|stats| f(stats.borrow_mut().entry(provider.to_string()).or_default()))
+}
+
+/// Providers for a network in the order they should be tried: healthy providers
+/// by descending score, falling back to providers on probation if none are healthy.
+pub fn select_providers(network: &str) -> Vec<String> {
+    let providers = list_providers(network);
+    STATS.with(|stats| {
+        let stats = stats.borrow();
+        let default_stats = ProviderStats::default();
+        let mut ranked = providers
+            .into_iter()
+            .map(|url| {
+                let provider_stats = stats.get(&url).unwrap_or(&default_stats).clone();
+                (url, provider_stats)
+            })
+            .filter(|(_, stats)| stats.status != ProviderStatus::ForceDisabled)
+            .collect::<Vec<_>>();
+        ranked.sort_by(|(_, a), (_, b)| b.score().total_cmp(&a.score()));
+        if ranked.iter().any(|(_, stats)| stats.in_rotation()) {
+            ranked.retain(|(_, stats)| stats.in_rotation());
+        }
+        ranked.into_iter().map(|(url, _)| url).collect()
+    })
+}
+
+/// Record a successful request to a provider
+pub fn record_success(provider: &str, latency_ns: u64) {
+    with_stats(provider, |stats| {
+        stats.record_outcome(true);
+        stats.consecutive_failures = 0;
+        stats.recent_latencies_ns.push_back(latency_ns);
+        if stats.recent_latencies_ns.len() > LATENCY_WINDOW {
+            stats.recent_latencies_ns.pop_front();
+        }
+    })
+}
+
+/// Record a failed request to a provider, demoting it after repeated failures
+pub fn record_failure(provider: &str, error: &EthRpcError) {
+    let demoted = with_stats(provider, |stats| {
+        stats.record_outcome(false);
+        stats.consecutive_failures += 1;
+        *stats
+            .error_counts
+            .entry(error.class().to_string())
+            .or_default() += 1;
+        let demote = stats.status == ProviderStatus::Active
+            && stats.consecutive_failures >= PROBATION_THRESHOLD;
+        if demote {
+            stats.status = ProviderStatus::Probation;
+        }
+        demote
+    });
+    if demoted {
+        metrics::increment("provider_demotions");
+    }
+}
+
+/// Override the automatic status of a provider
+pub fn force_status(provider: &str, enabled: bool) {
+    with_stats(provider, |stats| {
+        stats.status = if enabled {
+            ProviderStatus::ForceEnabled
+        } else {
+            ProviderStatus::ForceDisabled
+        };
+    })
+}
+
+/// Remove an admin override, returning a provider to automatic status based on
+/// its recent failures. Providers without an override are left unchanged.
+pub fn clear_forced_status(provider: &str) {
+    with_stats(provider, |stats| {
+        if matches!(
+            stats.status,
+            ProviderStatus::ForceEnabled | ProviderStatus::ForceDisabled
+        ) {
+            stats.status = if stats.consecutive_failures >= PROBATION_THRESHOLD {
+                ProviderStatus::Probation
+            } else {
+                ProviderStatus::Active
+            };
+        }
+    })
+}
+
+/// Health reports for every provider of a network
+pub fn provider_health(network: &str) -> Vec<ProviderHealth> {
+    list_providers(network)
+        .into_iter()
+        .map(|url| {
+            let stats = STATS.with(|stats| stats.borrow().get(&url).cloned().unwrap_or_default());
+            ProviderHealth {
+                archive: archive_support(network, &url),
+                status: stats.status,
+                score: stats.score(),
+                success_rate: stats.success_rate(),
+                p95_latency_ns: stats.p95_latency_ns(),
+                consecutive_failures: stats.consecutive_failures,
+                error_counts: stats.error_counts.into_iter().collect(),
+                url,
+            }
+        })
+        .collect()
+}
+
+/// Forget the statistics of a provider which is no longer registered
+pub fn remove_stats(provider: &str) {
+    STATS.with(|stats| stats.borrow_mut().remove(provider));
+}
+
+/// Probe every registered provider on probation, restoring those which respond
+pub async fn probe_providers() {
+    let registered = providers::snapshot()
+        .into_values()
+        .flatten()
+        .collect::<BTreeSet<_>>();
+    let on_probation = STATS.with(|stats| {
+        stats
+            .borrow()
+            .iter()
+            .filter(|(url, stats)| {
+                stats.status == ProviderStatus::Probation && registered.contains(*url)
+            })
+            .map(|(url, _)| url.clone())
+            .collect::<Vec<_>>()
+    });
+    for provider in on_probation {
+        if probe_provider(&provider).await.is_ok() {
+            let recovered = with_stats(&provider, |stats| {
+                stats.consecutive_failures = 0;
+                let recover = stats.status == ProviderStatus::Probation;
+                if recover {
+                    stats.status = ProviderStatus::Active;
+                }
+                recover
+            });
+            if recovered {
+                metrics::increment("provider_recoveries");
+            }
+        }
+    }
+}
+
+/// Start the timer which periodically probes providers on probation
+pub fn start_probe_timer() {
+    ic_cdk_timers::set_timer_interval(PROBE_INTERVAL, || ic_cdk::spawn(probe_providers()));
+}
+
+pub fn snapshot() -> BTreeMap<String, ProviderStats> {
+    STATS.with(|stats| stats.borrow().clone())
+}
+
+pub fn restore(state: BTreeMap<String, ProviderStats>) {
+    STATS.with(|stats| *stats.borrow_mut() = state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{set_transport, MockTransport};
+    use futures::executor::block_on;
+    use ic_cdk::api::call::RejectionCode;
+    use ic_cdk::api::management_canister::http_request::HttpResponse;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const FLAKY: &str = "https://flaky.example.com";
+    const STEADY: &str = "https://steady.example.com";
+
+    fn set_providers() {
+        crate::providers::restore(
+            [(
+                "mainnet".to_string(),
+                vec![FLAKY.to_string(), STEADY.to_string()],
+            )]
+            .into_iter()
+            .collect(),
+        );
+    }
+
+    fn status(provider: &str) -> ProviderStatus {
+        snapshot()[provider].status
+    }
+
+    // Answer `eth_chainId` probes, or reject them all while `down` is set
+    fn mock_probes(down: Rc<Cell<bool>>) -> Rc<MockTransport> {
+        let transport = Rc::new(MockTransport::new(move |request| {
+            if down.get() {
+                return Err((RejectionCode::SysTransient, "unavailable".to_string()));
+            }
+            let body: serde_json::Value =
+                serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            Ok(HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(&serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": body["id"],
+                    "result": "0x1",
+                }))
+                .unwrap(),
+            })
+        }));
+        set_transport(transport.clone());
+        transport
+    }
+
+    #[test]
+    fn demotes_after_failures_and_recovers_after_probe() {
+        set_providers();
+        let down = Rc::new(Cell::new(true));
+        let transport = mock_probes(down.clone());
+        let error = EthRpcError::HttpOutcall {
+            code: "SysTransient".to_string(),
+            message: "unavailable".to_string(),
+        };
+
+        record_success(FLAKY, 1_000);
+        for _ in 0..PROBATION_THRESHOLD - 1 {
+            record_failure(FLAKY, &error);
+            assert_eq!(status(FLAKY), ProviderStatus::Active);
+        }
+        record_failure(FLAKY, &error);
+        assert_eq!(status(FLAKY), ProviderStatus::Probation);
+        assert_eq!(select_providers("mainnet"), vec![STEADY.to_string()]);
+        assert_eq!(metrics::counters()["provider_demotions"], 1);
+
+        // A failing probe leaves the provider on probation
+        block_on(probe_providers());
+        assert_eq!(status(FLAKY), ProviderStatus::Probation);
+        assert_eq!(transport.requests().len(), 1);
+
+        down.set(false);
+        block_on(probe_providers());
+        assert_eq!(status(FLAKY), ProviderStatus::Active);
+        assert_eq!(snapshot()[FLAKY].consecutive_failures, 0);
+        assert_eq!(metrics::counters()["provider_recoveries"], 1);
+        assert!(select_providers("mainnet").contains(&FLAKY.to_string()));
+
+        // Healthy providers aren't probed
+        block_on(probe_providers());
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[test]
+    fn success_rate_covers_recent_requests_only() {
+        set_providers();
+        let error = EthRpcError::HttpOutcall {
+            code: "SysTransient".to_string(),
+            message: "unavailable".to_string(),
+        };
+        for _ in 0..OUTCOME_WINDOW {
+            record_failure(FLAKY, &error);
+        }
+        for _ in 0..OUTCOME_WINDOW / 2 {
+            record_success(FLAKY, 1_000);
+        }
+        let stats = &snapshot()[FLAKY];
+        assert_eq!(stats.recent_outcomes.len(), OUTCOME_WINDOW);
+        assert_eq!(stats.success_rate(), 0.5);
+
+        // A recovered provider's old failures age out of its score
+        for _ in 0..OUTCOME_WINDOW / 2 {
+            record_success(FLAKY, 1_000);
+        }
+        assert_eq!(snapshot()[FLAKY].success_rate(), 1.0);
+    }
+
+    #[test]
+    fn removed_providers_are_forgotten_and_not_probed() {
+        set_providers();
+        let transport = mock_probes(Rc::new(Cell::new(false)));
+        let error = EthRpcError::HttpOutcall {
+            code: "SysTransient".to_string(),
+            message: "unavailable".to_string(),
+        };
+        for provider in [FLAKY, STEADY] {
+            for _ in 0..PROBATION_THRESHOLD {
+                record_failure(provider, &error);
+            }
+        }
+
+        // Stats restored from before a provider's removal aren't probed
+        crate::providers::restore(
+            [("mainnet".to_string(), vec![STEADY.to_string()])]
+                .into_iter()
+                .collect(),
+        );
+        block_on(probe_providers());
+        assert_eq!(transport.requests().len(), 1);
+        assert_eq!(transport.requests()[0].url, STEADY);
+        assert_eq!(status(FLAKY), ProviderStatus::Probation);
+
+        crate::providers::remove_provider("mainnet", STEADY).unwrap();
+        assert!(!snapshot().contains_key(STEADY));
+    }
+
+    #[test]
+    fn overrides_beat_automatic_status_until_cleared() {
+        set_providers();
+        let error = EthRpcError::HttpOutcall {
+            code: "SysTransient".to_string(),
+            message: "unavailable".to_string(),
+        };
+
+        force_status(FLAKY, true);
+        for _ in 0..PROBATION_THRESHOLD {
+            record_failure(FLAKY, &error);
+        }
+        assert_eq!(status(FLAKY), ProviderStatus::ForceEnabled);
+        assert!(select_providers("mainnet").contains(&FLAKY.to_string()));
+
+        // Without the override, the failures put it on probation
+        clear_forced_status(FLAKY);
+        assert_eq!(status(FLAKY), ProviderStatus::Probation);
+
+        force_status(STEADY, false);
+        assert_eq!(select_providers("mainnet"), vec![FLAKY.to_string()]);
+        clear_forced_status(STEADY);
+        assert_eq!(status(STEADY), ProviderStatus::Active);
+        assert_eq!(select_providers("mainnet"), vec![STEADY.to_string()]);
+
+        // Clearing a provider without an override changes nothing
+        clear_forced_status(FLAKY);
+        assert_eq!(status(FLAKY), ProviderStatus::Probation);
+    }
+}
@@ -1,15 +1,60 @@
 use std::{rc::Rc, str::FromStr};
 
 use candid::candid_method;
-use eth_rpc::execute_contract_call;
+use decoded::DecodedValue;
+use eth_rpc::{
+    execute_contract_call, execute_contract_call_verbose, resolve_function,
+    try_execute_contract_call, CallOptions, ReceiptExt,
+};
 use ethers_core::{
-    abi::{Contract, Token},
-    types::{Address, RecoveryMessage, Signature},
+    abi::{
+        token::{LenientTokenizer, Tokenizer},
+        Contract, Function, FunctionExt, Token,
+    },
+    types::{Address, Filter, Signature, TransactionReceipt, TransactionRequest, H256, U256},
+};
+use limiter::Priority;
+use providers::NetworkConfig;
+use serde::{de::DeserializeOwned, Serialize};
+use types::{
+    AbiSource, AccountSummary, ArchiveSupport, AuditConfig, AuditRecord, BlockTag, ChainMetadata,
+    ContractCallOptions, Derivation, EthRpcError, LimiterConfig, ProposalSubmission,
+    ProposalSummary, ProviderCheck, ProviderHealth, ProxyInfo, Quote, QuoteTerms, SequencePolicy,
+    SequenceStatus, SignatureRecord, SigningDomain, TxType, VoteType,
+};
+use util::{
+    parse_hex_input, parse_word_input, to_hex, HexOrDecimal, JsonOutputFormat, MAX_INPUT_BYTES,
 };
-use util::to_hex;
 
+mod abis;
+mod accounts;
+mod audit;
+mod cache;
+mod clock;
+mod decoded;
+mod ecdsa;
+mod error;
 mod eth_rpc;
+mod events;
+mod governor;
+mod health;
+mod limiter;
+mod metrics;
+mod named;
+mod provider_check;
+mod providers;
+mod proxy;
+mod quotes;
+mod secrets;
+mod sequences;
+mod signing;
+mod state;
+mod storage;
+mod transactions;
+mod transport;
+pub mod types;
 mod util;
+mod weth;
 
 // Load relevant ABIs (Ethereum equivalent of Candid interfaces)
 thread_local! {
@@ -17,17 +62,481 @@ thread_local! {
     static ERC_1155: Rc<Contract> = Rc::new(load_abi!("../abi/erc1155.json"));
 }
 
-/// Verify an ECDSA signature (message signed by an Ethereum wallet).
+// Guard for admin endpoints
+fn controller_only() -> Result<(), String> {
+    if ic_cdk::api::is_controller(&ic_cdk::caller()) {
+        Ok(())
+    } else {
+        Err("Caller is not a controller".to_string())
+    }
+}
+
+#[ic_cdk_macros::init]
+fn init() {
+    health::start_probe_timer();
+    sequences::start_receipt_timer();
+}
+
+#[ic_cdk_macros::pre_upgrade]
+fn pre_upgrade() {
+    state::save();
+}
+
+#[ic_cdk_macros::post_upgrade]
+fn post_upgrade() {
+    state::restore();
+    health::start_probe_timer();
+    sequences::start_receipt_timer();
+}
+
+/// Store a secret which provider URLs can reference as `{{secret:NAME}}`.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn set_secret(name: String, value: String) -> Result<(), String> {
+    secrets::set_secret(name, value)
+}
+
+/// Replace the value of an existing secret.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn rotate_secret(name: String, new_value: String) -> Result<(), String> {
+    secrets::rotate_secret(name, new_value)
+}
+
+/// Delete a secret which is not referenced by any provider.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn delete_secret(name: String) -> Result<(), String> {
+    secrets::delete_secret(&name)
+}
+
+/// Add a JSON-RPC provider URL for a network, with the depth of historical state it serves
+/// (a full node by default).
+/// An endpoint path (e.g. `/v3/{{secret:INFURA_PROJECT_ID}}`) may be given separately from the base URL.
+/// The provider is optionally refused unless a readiness check passes.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn register_provider(
+    network: String,
+    url: String,
+    check: Option<bool>,
+    archive: Option<ArchiveSupport>,
+    path: Option<String>,
+) -> Result<(), String> {
+    let config = NetworkConfig { base: url, path };
+    if check.unwrap_or_default() {
+        let report = provider_check::check_provider_url(&network, &config.url())
+            .await
+            .map_err(|err| format!("Provider check failed: {}", err))?;
+        if !report.is_ready() {
+            return Err(format!("Provider is not ready: {:?}", report));
+        }
+    }
+    providers::register_provider(&network, config, archive.unwrap_or_default())
+}
+
+/// Remove a JSON-RPC provider URL from a network.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn remove_provider(network: String, url: String) -> Result<(), String> {
+    providers::remove_provider(&network, &url)
+}
+
+/// List the JSON-RPC provider URLs for a network (with secrets redacted).
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn list_providers(network: String) -> Vec<String> {
+    providers::list_providers(&network)
+}
+
+/// Wallet configuration (EIP-3085 chain metadata) for a built-in network.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn chain_metadata(network: String) -> Option<ChainMetadata> {
+    providers::chain_metadata(&network)
+}
+
+/// Report the health of each JSON-RPC provider for a network.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn provider_health(network: String) -> Vec<ProviderHealth> {
+    health::provider_health(&network)
+}
+
+/// Check the readiness of a network's provider by its index.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn check_provider(
+    network: String,
+    provider_index: u32,
+) -> Result<ProviderCheck, EthRpcError> {
+    provider_check::check_provider(&network, provider_index as usize).await
+}
+
+/// Check the readiness of every provider of a network.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn check_all_providers(
+    network: String,
+    max_lag_blocks: u64,
+) -> Vec<Result<ProviderCheck, EthRpcError>> {
+    provider_check::check_all_providers(&network, max_lag_blocks).await
+}
+
+/// Keep a provider in rotation regardless of its health.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn force_enable_provider(url: String) {
+    health::force_status(&url, true)
+}
+
+/// Exclude a provider from rotation regardless of its health.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn force_disable_provider(url: String) {
+    health::force_status(&url, false)
+}
+
+/// Remove a provider's enable or disable override, returning it to automatic status.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn clear_provider_override(url: String) {
+    health::clear_forced_status(&url)
+}
+
+/// Set the limits on concurrent outcalls.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn set_outcall_limits(config: LimiterConfig) {
+    limiter::set_config(config)
+}
+
+/// Current limits on concurrent outcalls.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn outcall_limits() -> LimiterConfig {
+    limiter::config()
+}
+
+/// Configure the audit log of transactions sent by the canister.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn set_audit_config(config: AuditConfig) {
+    audit::set_config(config)
+}
+
+/// Current configuration of the transaction audit log.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn audit_config() -> AuditConfig {
+    audit::config()
+}
+
+/// The most recent transactions sent by the canister, newest first.
+#[ic_cdk_macros::query(guard = "controller_only")]
+#[candid_method(query)]
+pub fn transaction_history(limit: u32) -> Vec<AuditRecord> {
+    audit::transaction_history(limit as usize)
+}
+
+/// Set how long (in nanoseconds) a network's head block number is reused.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn set_head_block_ttl(ttl_ns: u64) {
+    eth_rpc::set_head_block_ttl(ttl_ns)
+}
+
+/// Forget the cached head block number of a network.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn invalidate_head_block(network: String) {
+    eth_rpc::invalidate_head_block(&network)
+}
+
+/// Current values of the canister's event counters.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn metrics() -> Vec<(String, u64)> {
+    metrics::counters().into_iter().collect()
+}
+
+/// Create an Ethereum account controlled by the canister on a network.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn create_account(
+    label: String,
+    network: String,
+    derivation: Derivation,
+) -> Result<AccountSummary, String> {
+    accounts::create_account(label, network, derivation).await
+}
+
+/// Refresh the nonce and balance of an account.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn refresh_account(label: String) -> Result<AccountSummary, String> {
+    accounts::refresh_account(label).await
+}
+
+/// Get a summary of an account.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn get_account(label: String) -> Option<AccountSummary> {
+    accounts::get_account(label)
+}
+
+/// Delete an account which has no pending transactions.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn delete_account(label: String) -> Result<(), String> {
+    accounts::delete_account(&label)
+}
+
+/// Add or replace a signing domain.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn set_signing_domain(domain: SigningDomain) {
+    signing::set_signing_domain(domain)
+}
+
+/// Remove a signing domain.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub fn remove_signing_domain(name: String) -> Result<(), String> {
+    signing::remove_signing_domain(&name)
+}
+
+/// List the signing domains.
+#[ic_cdk_macros::query(guard = "controller_only")]
+#[candid_method(query)]
+pub fn signing_domains() -> Vec<SigningDomain> {
+    signing::signing_domains()
+}
+
+/// Signatures issued by the canister, optionally only those of one signing domain.
+#[ic_cdk_macros::query(guard = "controller_only")]
+#[candid_method(query)]
+pub fn signature_log(domain: Option<String>) -> Vec<SignatureRecord> {
+    signing::signature_log(domain)
+}
+
+/// Nonce to be embedded into the next EIP-712 message signed in a signing domain.
 #[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn next_signing_nonce(domain: String) -> u64 {
+    signing::next_nonce(&domain)
+}
+
+/// Sign an EIP-191 personal message with an account's key, within a signing domain.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn sign_message(
+    domain: String,
+    account: String,
+    message: String,
+) -> Result<String, String> {
+    signing::sign_message(&domain, &account, message).await
+}
+
+/// Sign EIP-712 typed data (as JSON) with an account's key, within a signing domain.
+#[ic_cdk_macros::update]
 #[candid_method]
+pub async fn sign_typed_data(
+    domain: String,
+    account: String,
+    typed_data: String,
+) -> Result<String, String> {
+    signing::sign_typed_data(&domain, &account, &typed_data).await
+}
+
+/// Submit an ordered series of dependent transactions (each as a JSON transaction
+/// request) from an account, returning the sequence's operation id.
+/// With a quote id, the sequence is refused if fees have moved since quoting.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn submit_sequence(
+    account: String,
+    steps: Vec<String>,
+    policy: SequencePolicy,
+    quote_id: Option<u64>,
+) -> Result<u64, String> {
+    let steps = steps
+        .iter()
+        .map(|step| serde_json::from_str(step).map_err(|err| format!("Invalid step: {}", err)))
+        .collect::<Result<Vec<_>, _>>()?;
+    sequences::submit_sequence(account, steps, policy, quote_id).await
+}
+
+/// Cancel a stuck transaction of an account by replacing its nonce with a zero-value
+/// transfer to the account itself, with fees raised by `bump_percent` (at least 10).
+/// Returns the hash of the cancelling transaction.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn cancel_transaction(
+    account: String,
+    nonce: u64,
+    bump_percent: u8,
+    tx_type: Option<TxType>,
+) -> Result<String, String> {
+    let eth_account = accounts::account(&account)?;
+    let tx_hash = transactions::cancel_transaction(
+        &eth_account,
+        nonce,
+        bump_percent,
+        tx_type.unwrap_or_default(),
+    )
+    .await
+    .map_err(|err| err.to_string())?;
+    let tx_hash = to_hex(tx_hash.as_bytes());
+    accounts::add_pending_transaction(&account, tx_hash.clone());
+    Ok(tx_hash)
+}
+
+/// Quote the expected cost of sending a transaction, without sending anything.
+/// The terms set how long the quote is valid and how far fees may rise under it.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn quote(
+    network: String,
+    from: Option<String>,
+    to: String,
+    data_hex: String,
+    value_wei: String,
+    terms: Option<QuoteTerms>,
+) -> Result<Quote, EthRpcError> {
+    let from = from.as_deref().map(parse_address).transpose()?;
+    let to = parse_address(&to)?;
+    let data = parse_hex_input(&data_hex, MAX_INPUT_BYTES)?;
+    let value = U256::from_dec_str(&value_wei)
+        .map_err(|_| EthRpcError::InvalidInput(format!("Invalid value: {}", value_wei)))?;
+    quotes::quote(&network, from, to, data, value, terms.unwrap_or_default()).await
+}
+
+/// Status of a sequence of transactions by operation id.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn sequence_status(id: u64) -> Option<SequenceStatus> {
+    sequences::sequence_status(id)
+}
+
+fn parse_address(address: &str) -> Result<Address, EthRpcError> {
+    Address::from_str(address)
+        .map_err(|_| EthRpcError::InvalidInput(format!("Invalid address: {}", address)))
+}
+
+// Parse a decimal or `0x`-hex number
+fn parse_uint(value: &str) -> Result<U256, EthRpcError> {
+    match value.strip_prefix("0x") {
+        Some(digits) => U256::from_str_radix(digits, 16).ok(),
+        None => U256::from_dec_str(value).ok(),
+    }
+    .ok_or_else(|| EthRpcError::InvalidInput(format!("Invalid number: {}", value)))
+}
+
+/// State, vote tallies, voting deadline and quorum of a proposal (by decimal id) of an
+/// OpenZeppelin Governor.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn governor_proposal(
+    network: String,
+    governor: String,
+    proposal_id: String,
+) -> Result<ProposalSummary, EthRpcError> {
+    governor::proposal_summary(
+        &network,
+        parse_address(&governor)?,
+        parse_uint(&proposal_id)?,
+    )
+    .await
+}
+
+/// Vote on a proposal (by decimal id) of an OpenZeppelin Governor from an account,
+/// optionally with a reason. The vote is sent as a single-step sequence, whose
+/// operation id is returned.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn governor_cast_vote(
+    account: String,
+    governor: String,
+    proposal_id: String,
+    support: VoteType,
+    reason: Option<String>,
+) -> Result<u64, String> {
+    let governor = parse_address(&governor).map_err(|err| err.to_string())?;
+    let proposal_id = parse_uint(&proposal_id).map_err(|err| err.to_string())?;
+    governor::submit_vote(&account, governor, proposal_id, support, reason).await
+}
+
+/// Create a proposal on an OpenZeppelin Governor from an account, with a call
+/// (target, value in wei and hex calldata) per action. The proposal is sent as a
+/// single-step sequence; its id and the sequence's operation id are returned.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn governor_propose(
+    account: String,
+    governor: String,
+    actions: Vec<(String, String, String)>,
+    description: String,
+) -> Result<ProposalSubmission, String> {
+    let governor = parse_address(&governor).map_err(|err| err.to_string())?;
+    let mut targets = vec![];
+    let mut values = vec![];
+    let mut calldatas = vec![];
+    for (target, value_wei, calldata_hex) in &actions {
+        targets.push(parse_address(target).map_err(|err| err.to_string())?);
+        values.push(parse_uint(value_wei).map_err(|err| err.to_string())?);
+        calldatas
+            .push(parse_hex_input(calldata_hex, MAX_INPUT_BYTES).map_err(|err| err.to_string())?);
+    }
+    governor::submit_proposal(
+        &account,
+        governor,
+        &targets,
+        &values,
+        &calldatas,
+        description,
+    )
+    .await
+}
+
+/// Wrap ether of an account into WETH (amount in wei). The deposit is sent as a
+/// single-step sequence, whose operation id is returned.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn wrap_ether(account: String, amount_wei: String) -> Result<u64, String> {
+    let amount = parse_uint(&amount_wei).map_err(|err| err.to_string())?;
+    weth::submit_wrap(&account, amount).await
+}
+
+/// Unwrap WETH of an account into ether (amount in wei). The withdrawal is sent as a
+/// single-step sequence, whose operation id is returned.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn unwrap_ether(account: String, amount_wei: String) -> Result<u64, String> {
+    let amount = parse_uint(&amount_wei).map_err(|err| err.to_string())?;
+    weth::submit_unwrap(&account, amount).await
+}
+
+/// Verify an ECDSA signature (message signed by an Ethereum wallet).
+#[ic_cdk_macros::query]
+#[candid_method(query)]
 pub fn verify_ecdsa(eth_address: String, message: String, signature: String) -> bool {
-    Signature::from_str(&signature)
-        .unwrap()
-        .verify(
-            RecoveryMessage::Data(message.into_bytes()),
-            Address::from_str(&eth_address).unwrap(),
-        )
-        .is_ok()
+    util::verify_signature(
+        util::hash_personal_message(message.as_bytes()),
+        &Signature::from_str(&signature).unwrap().into(),
+        Address::from_str(&eth_address).unwrap(),
+    )
+}
+
+/// Recover the Ethereum address which signed a 32-byte message hash.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn recover_signer(message_hash: String, signature: String) -> Result<String, EthRpcError> {
+    let signature: [u8; 65] = parse_hex_input(&signature, 65)?
+        .try_into()
+        .map_err(|_| EthRpcError::InvalidInput("Expected a 65-byte signature".to_string()))?;
+    let signer = util::recover_signer(parse_word_input(&message_hash)?, &signature)?;
+    Ok(to_hex(signer.as_bytes()))
 }
 
 /// Find the owner of an ERC-721 NFT by calling the Ethereum blockchain.
@@ -46,8 +555,8 @@ pub async fn erc721_owner_of(network: String, contract_address: String, token_id
         &[Token::Uint(token_id.into())],
     )
     .await;
-    match result.get(0) {
-        Some(Token::Address(a)) => to_hex(a.as_bytes()),
+    match result.first().map(|token| DecodedValue(token).as_address()) {
+        Some(Ok(owner)) => to_hex(owner.as_bytes()),
         _ => panic!("Unexpected result"),
     }
 }
@@ -70,14 +579,820 @@ pub async fn erc1155_balance_of(
         contract_address,
         abi,
         "balanceOf",
-        &[
-            Token::Address(owner_address.into()),
-            Token::Uint(token_id.into()),
-        ],
+        &[Token::Address(owner_address), Token::Uint(token_id.into())],
     )
     .await;
-    match result.get(0) {
-        Some(Token::Uint(n)) => n.as_u128(),
+    match result.first().map(|token| DecodedValue(token).as_uint()) {
+        Some(Ok(balance)) => balance.as_u128(),
         _ => panic!("Unexpected result"),
     }
 }
+
+// Parse a function's arguments (given as strings) according to its input types
+fn tokenize_args(function: &Function, args: &[String]) -> Result<Vec<Token>, EthRpcError> {
+    if args.len() != function.inputs.len() {
+        return Err(EthRpcError::InvalidInput(format!(
+            "Expected {} arguments, got {}",
+            function.inputs.len(),
+            args.len()
+        )));
+    }
+    function
+        .inputs
+        .iter()
+        .zip(args)
+        .map(|(input, arg)| {
+            LenientTokenizer::tokenize(&input.kind, arg).map_err(|err| {
+                EthRpcError::InvalidInput(format!("Invalid argument {:?}: {}", input.name, err))
+            })
+        })
+        .collect()
+}
+
+fn parse_json_input<T: DeserializeOwned>(json: &str, what: &str) -> Result<T, EthRpcError> {
+    serde_json::from_str(json)
+        .map_err(|err| EthRpcError::InvalidInput(format!("Invalid {}: {}", what, err)))
+}
+
+fn to_json<T: Serialize>(value: &T) -> String {
+    serde_json::to_string(value).expect("Error encoding result")
+}
+
+/// Call a function of a registered ABI, returning its outputs as named JSON.
+/// Arguments are given as strings and parsed according to the function's input types.
+/// With `verbose`, the outputs are wrapped in an envelope with metadata about the outcall
+/// and the raw return data.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn call_contract(
+    network: String,
+    contract_address: String,
+    abi_name: String,
+    method: String,
+    args: Vec<String>,
+    options: Option<ContractCallOptions>,
+) -> Result<String, EthRpcError> {
+    let abi = abi_by_name(&abi_name)?;
+    let function = resolve_function(&abi.contract, &method)?;
+    let arguments = tokenize_args(function, &args)?;
+    let options = options.unwrap_or_default();
+    let call_options = CallOptions {
+        block: options.block.unwrap_or_default(),
+        max_cache_staleness_ns: options.max_cache_staleness_ns,
+        state_fallback: options.state_fallback.unwrap_or_default(),
+        ..Default::default()
+    };
+    let format = JsonOutputFormat {
+        uint_as: if options.uint_as_hex.unwrap_or_default() {
+            HexOrDecimal::Hex
+        } else {
+            HexOrDecimal::Decimal
+        },
+    };
+    if options.verbose.unwrap_or_default() {
+        let result = execute_contract_call_verbose(
+            &network,
+            contract_address,
+            &abi.contract,
+            &function.abi_signature(),
+            &arguments,
+            &call_options,
+        )
+        .await?;
+        let envelope = serde_json::json!({
+            "outputs": abi.name_outputs(function, &result.tokens, format),
+            "raw": to_hex(&result.raw),
+            "block": result.block,
+            "head_block": result.head_block,
+            "provider": result.trace.as_ref().map(|trace| &trace.provider),
+            "response_bytes": result.trace.as_ref().map(|trace| trace.response_bytes),
+            "latency_ns": result.trace.as_ref().map(|trace| trace.latency_ns),
+            "cycles": result.trace.as_ref().map(|trace| trace.cycles),
+            "retries": result.retries,
+            "from_cache": result.from_cache,
+        });
+        return Ok(to_json(&envelope));
+    }
+    let tokens = match options.max_cache_staleness_ns {
+        Some(max_staleness_ns) => {
+            let contract = parse_address(&contract_address)?;
+            let calldata = function
+                .encode_input(&arguments)
+                .map_err(|err| EthRpcError::InvalidInput(err.to_string()))?;
+            let data = cache::get_or_fetch(
+                &network,
+                contract,
+                &calldata,
+                call_options.block,
+                max_staleness_ns,
+            )
+            .await?;
+            function
+                .decode_output(&data)
+                .map_err(|err| EthRpcError::AbiDecode(err.to_string()))?
+        }
+        None => {
+            try_execute_contract_call(
+                &network,
+                contract_address,
+                &abi.contract,
+                &function.abi_signature(),
+                &arguments,
+                &call_options,
+            )
+            .await?
+            .tokens
+        }
+    };
+    Ok(to_json(&abi.name_outputs(function, &tokens, format)))
+}
+
+/// Call a function of a registered ABI together with an estimate of the gas it uses,
+/// returning the named outputs and the gas (as a decimal string) as JSON.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn call_contract_with_gas(
+    network: String,
+    contract_address: String,
+    abi_name: String,
+    method: String,
+    args: Vec<String>,
+    block: Option<BlockTag>,
+) -> Result<String, EthRpcError> {
+    let abi = abi_by_name(&abi_name)?;
+    let function = resolve_function(&abi.contract, &method)?;
+    let arguments = tokenize_args(function, &args)?;
+    let (tokens, gas_used) = eth_rpc::call_with_gas(
+        &network,
+        parse_address(&contract_address)?,
+        &abi.contract,
+        &function.abi_signature(),
+        &arguments,
+        block.unwrap_or_default(),
+    )
+    .await?;
+    Ok(to_json(&serde_json::json!({
+        "outputs": abi.name_outputs(function, &tokens, JsonOutputFormat::default()),
+        "gas_used": gas_used.to_string(),
+    })))
+}
+
+/// Call a function of a registered ABI at two blocks, returning the named outputs
+/// at each and whether they differ as JSON.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn diff_contract_call(
+    network: String,
+    contract_address: String,
+    abi_name: String,
+    method: String,
+    args: Vec<String>,
+    block_a: BlockTag,
+    block_b: BlockTag,
+) -> Result<String, EthRpcError> {
+    let abi = abi_by_name(&abi_name)?;
+    let function = resolve_function(&abi.contract, &method)?;
+    let arguments = tokenize_args(function, &args)?;
+    let (outputs_a, outputs_b, changed) = eth_rpc::diff_call(
+        &network,
+        parse_address(&contract_address)?,
+        &abi.contract,
+        &function.abi_signature(),
+        &arguments,
+        block_a,
+        block_b,
+    )
+    .await?;
+    let format = JsonOutputFormat::default();
+    Ok(to_json(&serde_json::json!({
+        "outputs_a": abi.name_outputs(function, &outputs_a, format),
+        "outputs_b": abi.name_outputs(function, &outputs_b, format),
+        "changed": changed,
+    })))
+}
+
+/// Encode a call to a function of a registered ABI, with its arguments as a JSON
+/// array in the format of the canister's JSON outputs. Returns the hex calldata.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn encode_call(
+    abi_name: String,
+    method: String,
+    args_json: String,
+) -> Result<String, EthRpcError> {
+    let abi = abi_by_name(&abi_name)?;
+    let function = resolve_function(&abi.contract, &method)?;
+    let args: Vec<serde_json::Value> = parse_json_input(&args_json, "arguments")?;
+    if args.len() != function.inputs.len() {
+        return Err(EthRpcError::InvalidInput(format!(
+            "Expected {} arguments, got {}",
+            function.inputs.len(),
+            args.len()
+        )));
+    }
+    let arguments = function
+        .inputs
+        .iter()
+        .zip(&args)
+        .map(|(input, arg)| util::json_to_token(arg, &input.kind))
+        .collect::<Result<Vec<_>, _>>()?;
+    let calldata = function
+        .encode_input(&arguments)
+        .map_err(|err| EthRpcError::InvalidInput(err.to_string()))?;
+    Ok(to_hex(&calldata))
+}
+
+fn abi_by_name(abi_name: &str) -> Result<Rc<abis::NamedAbi>, EthRpcError> {
+    abis::registered_abi(abi_name)
+        .ok_or_else(|| EthRpcError::InvalidInput(format!("ABI not found: {}", abi_name)))
+}
+
+/// Decode a function's return data (e.g. an `eth_call` result) into named JSON
+/// using a registered ABI, without calling the Ethereum blockchain.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn decode_result(
+    abi_name: String,
+    method: String,
+    data_hex: String,
+) -> Result<String, EthRpcError> {
+    let abi = abi_by_name(&abi_name)?;
+    let function = resolve_function(&abi.contract, &method)?;
+    let data = parse_hex_input(&data_hex, MAX_INPUT_BYTES)?;
+    let outputs = abi
+        .decode_output_named(function, &data)
+        .map_err(|err| EthRpcError::AbiDecode(err.to_string()))?;
+    Ok(to_json(&outputs))
+}
+
+/// Decode a log (e.g. from a transaction receipt) into a named JSON event
+/// using a registered ABI, without calling the Ethereum blockchain.
+/// Logs of anonymous events have no signature topic to match them by, so the
+/// anonymous event must be named.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn decode_event(
+    abi_name: String,
+    topics: Vec<String>,
+    data_hex: String,
+    anonymous_event: Option<String>,
+) -> Result<String, EthRpcError> {
+    let abi = abi_by_name(&abi_name)?;
+    let topics = topics
+        .iter()
+        .map(|topic| parse_word_input(topic).map(H256))
+        .collect::<Result<Vec<_>, _>>()?;
+    let data = parse_hex_input(&data_hex, MAX_INPUT_BYTES)?;
+    let event = match anonymous_event {
+        Some(event_name) => abi.decode_anonymous_log_named(&event_name, topics, data),
+        None => abi.decode_log_named(topics, data),
+    }
+    .map_err(|err| EthRpcError::AbiDecode(err.to_string()))?;
+    Ok(to_json(&event))
+}
+
+/// Signature topic (topic0) of an event of a registered ABI, for log filters.
+/// Overloaded events must be given by their full signature.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn event_topic(abi_name: String, event: String) -> Result<String, EthRpcError> {
+    let abi = abi_by_name(&abi_name)?;
+    let topic = events::event_topic(&abi.contract, &event)?;
+    Ok(to_hex(topic.as_bytes()))
+}
+
+/// Whether a block's (or receipt's) logs bloom may contain every item (addresses
+/// or topics). Blooms have false positives, so `true` only means "maybe present".
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn bloom_contains(bloom_hex: String, items: Vec<String>) -> Result<bool, EthRpcError> {
+    let bloom: [u8; 256] = parse_hex_input(&bloom_hex, 256)?
+        .try_into()
+        .map_err(|_| EthRpcError::InvalidInput("Expected a 256-byte bloom".to_string()))?;
+    for item in &items {
+        if !util::bloom_contains(&bloom, &parse_hex_input(item, MAX_INPUT_BYTES)?) {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+/// Fetch the ABI of a verified contract from a public source, returning it as JSON.
+/// Restricted to controllers, since an Etherscan API URL may reference secrets.
+#[ic_cdk_macros::update(guard = "controller_only")]
+#[candid_method]
+pub async fn fetch_abi(
+    network: String,
+    contract_address: String,
+    source: AbiSource,
+) -> Result<String, EthRpcError> {
+    let contract = abis::fetch_abi(&network, contract_address, source).await?;
+    Ok(to_json(&contract))
+}
+
+/// Decode the constructor arguments of a contract from its deployment input (the
+/// data of the contract-creation transaction), given its JSON ABI and creation
+/// bytecode. Returns the arguments as a JSON array.
+#[ic_cdk_macros::query]
+#[candid_method(query)]
+pub fn decode_constructor_args(
+    abi_json: String,
+    creation_code_hex: String,
+    deployment_input_hex: String,
+) -> Result<String, EthRpcError> {
+    let abi: Contract = parse_json_input(&abi_json, "ABI")?;
+    let creation_code = parse_hex_input(&creation_code_hex, MAX_INPUT_BYTES)?;
+    let deployment_input = parse_hex_input(&deployment_input_hex, MAX_INPUT_BYTES)?;
+    let tokens = abis::decode_constructor_args(&abi, &creation_code, &deployment_input)?;
+    Ok(to_json(
+        &tokens.iter().map(util::token_to_json).collect::<Vec<_>>(),
+    ))
+}
+
+/// Latest block number of a network.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn block_number(network: String) -> Result<u64, EthRpcError> {
+    eth_rpc::block_number(&network).await
+}
+
+/// Bytecode deployed at an address, as hex.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn get_code(
+    network: String,
+    address: String,
+    block: Option<BlockTag>,
+) -> Result<String, EthRpcError> {
+    let code = eth_rpc::get_code(
+        &network,
+        parse_address(&address)?,
+        block.unwrap_or_default(),
+    )
+    .await?;
+    Ok(to_hex(&code))
+}
+
+/// Read storage slots (decimal or `0x`-hex) of a contract in one outcall, returning
+/// each slot's 32-byte word as hex.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn get_storage_at(
+    network: String,
+    address: String,
+    slots: Vec<String>,
+    block: Option<BlockTag>,
+) -> Result<Vec<String>, EthRpcError> {
+    let slots = slots
+        .iter()
+        .map(|slot| parse_uint(slot))
+        .collect::<Result<Vec<_>, _>>()?;
+    let words = eth_rpc::get_storage_slots(
+        &network,
+        parse_address(&address)?,
+        &slots,
+        block.unwrap_or_default(),
+    )
+    .await?;
+    Ok(words.iter().map(|word| to_hex(word.as_bytes())).collect())
+}
+
+/// Read a state variable of a contract by name, locating it with the contract's
+/// storage layout JSON (from `solc --storage-layout`). Returns the value as JSON.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn read_storage_var(
+    network: String,
+    address: String,
+    layout_json: String,
+    variable: String,
+    block: Option<BlockTag>,
+) -> Result<String, EthRpcError> {
+    let layout: storage::StorageLayout = parse_json_input(&layout_json, "storage layout")?;
+    let token = storage::read_storage_var(
+        &network,
+        parse_address(&address)?,
+        &layout,
+        &variable,
+        block.unwrap_or_default(),
+    )
+    .await?;
+    Ok(to_json(&util::token_to_json(&token)))
+}
+
+/// Implementation, admin and beacon addresses held in the EIP-1967 slots of a proxy.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn resolve_proxy(network: String, proxy: String) -> Result<ProxyInfo, EthRpcError> {
+    proxy::resolve_proxy(&network, parse_address(&proxy)?).await
+}
+
+/// Whether a contract reports support for an ERC-165 interface (a 4-byte hex id).
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn supports_interface(
+    network: String,
+    address: String,
+    interface_id: String,
+    block: Option<BlockTag>,
+) -> Result<bool, EthRpcError> {
+    let interface_id: [u8; 4] = parse_hex_input(&interface_id, 4)?
+        .try_into()
+        .map_err(|_| EthRpcError::InvalidInput("Expected a 4-byte interface id".to_string()))?;
+    eth_rpc::supports_interface(&network, address, interface_id, block.unwrap_or_default()).await
+}
+
+/// A block (with transaction hashes) as JSON, or none if the block doesn't exist yet.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn get_block(network: String, block: BlockTag) -> Result<Option<String>, EthRpcError> {
+    let block = eth_rpc::get_block_by_number(&network, block).await?;
+    Ok(block.map(|block| to_json(&block)))
+}
+
+// Render a receipt as JSON, along with the fee the transaction paid (in wei)
+fn receipt_json(receipt: &TransactionReceipt) -> serde_json::Value {
+    let mut json = serde_json::to_value(receipt).expect("Error encoding receipt");
+    json["totalFee"] = serde_json::json!(receipt.total_fee().map(|fee| fee.to_string()));
+    json
+}
+
+/// Receipts of transactions (as JSON, with the fee paid as `totalFee`) fetched in one
+/// outcall, in the order of the hashes; none for transactions not yet mined.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn get_transaction_receipts(
+    network: String,
+    tx_hashes: Vec<String>,
+) -> Result<Vec<Option<String>>, EthRpcError> {
+    let hashes = tx_hashes
+        .iter()
+        .map(|hash| parse_word_input(hash).map(H256))
+        .collect::<Result<Vec<_>, _>>()?;
+    let receipts = eth_rpc::get_transaction_receipts(&network, &hashes).await?;
+    Ok(receipts
+        .iter()
+        .map(|receipt| {
+            receipt
+                .as_ref()
+                .map(|receipt| to_json(&receipt_json(receipt)))
+        })
+        .collect())
+}
+
+/// Receipt of a transaction (as JSON) once it has at least `confirmations`
+/// confirmations in the canonical chain, or none until then.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn confirmed_receipt(
+    network: String,
+    tx_hash: String,
+    confirmations: u64,
+) -> Result<Option<String>, EthRpcError> {
+    let tx_hash = H256(parse_word_input(&tx_hash)?);
+    let receipt = eth_rpc::wait_for_receipt(&network, tx_hash, confirmations).await?;
+    Ok(receipt
+        .as_ref()
+        .map(|receipt| to_json(&receipt_json(receipt))))
+}
+
+/// Receipts of every transaction in a block, as a JSON array.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn get_block_receipts(network: String, block: BlockTag) -> Result<String, EthRpcError> {
+    let receipts = eth_rpc::get_block_receipts(&network, block).await?;
+    let receipts = receipts.iter().map(receipt_json).collect::<Vec<_>>();
+    Ok(to_json(&receipts))
+}
+
+/// Logs matching a filter (as JSON, in the format of `eth_getLogs`), as a JSON array.
+/// With a page size, the filter's numeric block range is queried in pages of at most
+/// that many blocks, so ranges too large for one response are still fetched.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn get_logs(
+    network: String,
+    filter_json: String,
+    page_size: Option<u64>,
+) -> Result<String, EthRpcError> {
+    let filter: Filter = parse_json_input(&filter_json, "filter")?;
+    let logs = match page_size {
+        Some(page_size) => {
+            let (Some(from), Some(to)) = (filter.get_from_block(), filter.get_to_block()) else {
+                return Err(EthRpcError::InvalidInput(
+                    "Paged log queries need a numeric fromBlock and toBlock".to_string(),
+                ));
+            };
+            eth_rpc::get_logs_paged(
+                &network,
+                &filter,
+                from.as_u64(),
+                to.as_u64(),
+                page_size,
+                Some(Priority::Interactive),
+            )
+            .await?
+        }
+        None => eth_rpc::get_logs(&network, &filter, &CallOptions::default()).await?,
+    };
+    Ok(to_json(&logs))
+}
+
+/// Traces of every transaction in a block (including internal calls), as a JSON
+/// array. Needs a provider exposing the trace or debug namespace.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn trace_block(network: String, block: BlockTag) -> Result<String, EthRpcError> {
+    let traces = eth_rpc::trace_block(&network, block).await?;
+    Ok(to_json(&traces))
+}
+
+/// Access list of the storage a transaction (as a JSON transaction request) would
+/// touch, with the gas it uses with that access list, as JSON.
+#[ic_cdk_macros::update]
+#[candid_method]
+pub async fn create_access_list(
+    network: String,
+    tx_json: String,
+    block: Option<BlockTag>,
+) -> Result<String, EthRpcError> {
+    let tx: TransactionRequest = parse_json_input(&tx_json, "transaction")?;
+    let (access_list, gas_used) =
+        eth_rpc::create_access_list(&network, &tx, block.unwrap_or_default()).await?;
+    Ok(to_json(&serde_json::json!({
+        "access_list": access_list,
+        "gas_used": gas_used.to_string(),
+    })))
+}
+
+candid::export_service!();
+
+/// Candid interface of the canister, generated from the endpoints.
+#[ic_cdk_macros::query(name = "__get_candid_interface_tmp_hack")]
+fn export_candid() -> String {
+    __export_service()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::utils::{service_equal, CandidSource};
+    use candid::CandidType;
+    use futures::executor::block_on;
+    use ic_cdk::api::management_canister::http_request::HttpResponse;
+    use transport::{set_transport, MockTransport};
+
+    #[test]
+    fn candid_interface_matches_did_file() {
+        let generated = __export_service();
+        let path = std::path::Path::new(env!("CARGO_MANIFEST_DIR")).join("ic_eth.did");
+        service_equal(CandidSource::Text(&generated), CandidSource::File(&path))
+            .unwrap_or_else(|err| panic!("ic_eth.did is out of date: {}", err));
+    }
+
+    // `name()` of mainnet WETH as returned by `eth_call`
+    const WETH_NAME_RESULT: &str = concat!(
+        "0x",
+        "0000000000000000000000000000000000000000000000000000000000000020",
+        "000000000000000000000000000000000000000000000000000000000000000d",
+        "5772617070656420457468657200000000000000000000000000000000000000",
+    );
+
+    #[test]
+    fn decodes_results_of_registered_abis() {
+        let decoded = decode_result(
+            "weth".to_string(),
+            "name".to_string(),
+            WETH_NAME_RESULT.to_string(),
+        )
+        .unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&decoded).unwrap(),
+            serde_json::json!([{"name": "_0", "type": "string", "value": "Wrapped Ether"}])
+        );
+
+        // Functions resolve by full signature and by selector as in `call_contract`
+        let balance = "0x0000000000000000000000000000000000000000000000000de0b6b3a7640000";
+        let by_signature = decode_result(
+            "weth".to_string(),
+            "balanceOf(address)".to_string(),
+            balance.to_string(),
+        )
+        .unwrap();
+        let by_selector = decode_result(
+            "weth".to_string(),
+            "0x70A08231".to_string(),
+            balance.to_string(),
+        )
+        .unwrap();
+        assert_eq!(by_signature, by_selector);
+        assert!(by_signature.contains("1000000000000000000"));
+
+        assert!(matches!(
+            decode_result(
+                "weth".to_string(),
+                "0xdeadbeef".to_string(),
+                balance.to_string()
+            ),
+            Err(EthRpcError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            decode_result("erc20".to_string(), "name".to_string(), balance.to_string()),
+            Err(EthRpcError::InvalidInput(_))
+        ));
+        // Truncated return data is an error rather than a partial result
+        assert!(matches!(
+            decode_result(
+                "weth".to_string(),
+                "name".to_string(),
+                WETH_NAME_RESULT[..130].to_string()
+            ),
+            Err(EthRpcError::AbiDecode(_))
+        ));
+    }
+
+    #[test]
+    fn decode_validates_hex_input() {
+        assert_eq!(
+            decode_result("weth".to_string(), "name".to_string(), "0xzz".to_string()),
+            Err(EthRpcError::InvalidInput(
+                "Invalid hex: Invalid character 'z' at position 0".to_string()
+            ))
+        );
+        let oversized = format!("0x{}", "00".repeat(MAX_INPUT_BYTES + 1));
+        assert_eq!(
+            decode_result("weth".to_string(), "name".to_string(), oversized.clone()),
+            Err(EthRpcError::InvalidInput(format!(
+                "Input of {} bytes exceeds the limit of {} bytes",
+                MAX_INPUT_BYTES + 1,
+                MAX_INPUT_BYTES
+            )))
+        );
+        assert!(matches!(
+            decode_event("weth".to_string(), vec![], oversized, None),
+            Err(EthRpcError::InvalidInput(_))
+        ));
+        assert!(matches!(
+            decode_event(
+                "weth".to_string(),
+                vec!["0x1234".to_string()],
+                "0x".to_string(),
+                None
+            ),
+            Err(EthRpcError::InvalidInput(_))
+        ));
+    }
+
+    #[test]
+    fn decodes_receipt_logs_of_registered_abis() {
+        // A log as found in the receipt of a WETH `deposit()`
+        let topics = vec![
+            "0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c".to_string(),
+            "0x0000000000000000000000002c7536e3605d9c16a7a3d7b1898e529396a65c23".to_string(),
+        ];
+        let data = "0x0000000000000000000000000000000000000000000000000de0b6b3a7640000";
+        let decoded =
+            decode_event("weth".to_string(), topics.clone(), data.to_string(), None).unwrap();
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&decoded).unwrap(),
+            serde_json::json!({
+                "name": "Deposit",
+                "params": [
+                    {
+                        "name": "dst",
+                        "type": "address",
+                        "value": "0x2c7536E3605D9C16a7a3D7b1898e529396a65c23",
+                    },
+                    {"name": "wad", "type": "uint256", "value": "1000000000000000000"},
+                ],
+            })
+        );
+
+        // A signature topic the ABI doesn't know
+        let mut unknown = topics.clone();
+        unknown[0] = format!("0x{}", "11".repeat(32));
+        assert!(matches!(
+            decode_event("weth".to_string(), unknown, data.to_string(), None),
+            Err(EthRpcError::AbiDecode(_))
+        ));
+        // Missing the indexed topic
+        assert!(matches!(
+            decode_event(
+                "weth".to_string(),
+                topics[..1].to_vec(),
+                data.to_string(),
+                None
+            ),
+            Err(EthRpcError::AbiDecode(_))
+        ));
+    }
+
+    // Answer every JSON-RPC request with the given result
+    fn mock_result(result: &'static str) -> Rc<MockTransport> {
+        let transport = Rc::new(MockTransport::new(move |request| {
+            let body: serde_json::Value =
+                serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            Ok(HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(&serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": body["id"],
+                    "result": result,
+                }))
+                .unwrap(),
+            })
+        }));
+        set_transport(transport.clone());
+        transport
+    }
+
+    fn call_weth(method: &str, args: &[&str]) -> Result<String, EthRpcError> {
+        block_on(call_contract(
+            "mainnet".to_string(),
+            "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".to_string(),
+            "weth".to_string(),
+            method.to_string(),
+            args.iter().map(|arg| arg.to_string()).collect(),
+            None,
+        ))
+    }
+
+    #[test]
+    fn call_contract_returns_errors() {
+        let transport = mock_result(WETH_NAME_RESULT);
+        assert_eq!(
+            serde_json::from_str::<serde_json::Value>(&call_weth("name", &[]).unwrap()).unwrap(),
+            serde_json::json!([{"name": "_0", "type": "string", "value": "Wrapped Ether"}])
+        );
+        assert_eq!(transport.requests().len(), 1);
+
+        assert_eq!(
+            call_weth("mint", &[]),
+            Err(EthRpcError::InvalidInput("Function not found".to_string()))
+        );
+        assert!(matches!(
+            call_weth("balanceOf", &["not an address"]),
+            Err(EthRpcError::InvalidInput(_))
+        ));
+        assert_eq!(
+            block_on(call_contract(
+                "mainnet".to_string(),
+                "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2".to_string(),
+                "erc20".to_string(),
+                "name".to_string(),
+                vec![],
+                None,
+            )),
+            Err(EthRpcError::InvalidInput(
+                "ABI not found: erc20".to_string()
+            ))
+        );
+        assert_eq!(transport.requests().len(), 1);
+
+        // Return data that doesn't decode as the function's outputs
+        mock_result("0x1234");
+        assert!(matches!(
+            call_weth("name", &[]),
+            Err(EthRpcError::AbiDecode(_))
+        ));
+    }
+
+    #[test]
+    fn interface_types_are_candid_types() {
+        fn assert_candid<T: CandidType>() {}
+        assert_candid::<types::AbiSource>();
+        assert_candid::<types::AccountSummary>();
+        assert_candid::<types::Derivation>();
+        assert_candid::<types::AuditConfig>();
+        assert_candid::<types::AuditRecord>();
+        assert_candid::<types::EthRpcError>();
+        assert_candid::<types::Overload>();
+        assert_candid::<types::BlockTag>();
+        assert_candid::<types::ContractCallOptions>();
+        assert_candid::<types::StateFallback>();
+        assert_candid::<types::TxStatus>();
+        assert_candid::<types::ProviderHealth>();
+        assert_candid::<types::ProviderStatus>();
+        assert_candid::<types::LimiterConfig>();
+        assert_candid::<types::ProviderCheck>();
+        assert_candid::<types::ArchiveSupport>();
+        assert_candid::<types::ChainMetadata>();
+        assert_candid::<types::NativeCurrency>();
+        assert_candid::<types::ProxyInfo>();
+        assert_candid::<types::ProposalState>();
+        assert_candid::<types::ProposalSummary>();
+        assert_candid::<types::ProposalSubmission>();
+        assert_candid::<types::VoteType>();
+        assert_candid::<types::TxType>();
+        assert_candid::<types::Quote>();
+        assert_candid::<types::QuoteTerms>();
+        assert_candid::<types::SequencePolicy>();
+        assert_candid::<types::SequenceStatus>();
+        assert_candid::<types::StepState>();
+        assert_candid::<types::StepStatus>();
+        assert_candid::<types::Eip712Constraints>();
+        assert_candid::<types::SignatureRecord>();
+        assert_candid::<types::SigningDomain>();
+    }
+}
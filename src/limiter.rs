@@ -0,0 +1,204 @@
+use candid::CandidType;
+use serde::Deserialize;
+use std::cell::RefCell;
+
+use crate::clock;
+use crate::error::EthRpcError;
+use crate::metrics;
+
+/// Scheduling priority of an outcall
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub enum Priority {
+    /// Latency-sensitive work such as direct endpoint calls
+    #[default]
+    Interactive,
+    /// Timer-driven work such as provider probes
+    Background,
+}
+
+impl Priority {
+    fn name(self) -> &'static str {
+        match self {
+            Priority::Interactive => "interactive",
+            Priority::Background => "background",
+        }
+    }
+}
+
+/// Limits on concurrent outcalls.
+///
+/// Calls over the limit are refused with `OutcallLimitReached` rather than queued:
+/// a message can't wait on other messages' outcalls, since it would end without
+/// replying. Background work is retried by its timer, and callers may retry
+/// Interactive work.
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct LimiterConfig {
+    /// Maximum number of outcalls in flight at once
+    pub max_in_flight: u32,
+    /// Slots which only Interactive work may occupy
+    pub reserved_interactive: u32,
+    /// Time for which Background work must have been refused before it may
+    /// also occupy the reserved slots
+    pub background_boost_ns: u64,
+}
+
+impl Default for LimiterConfig {
+    fn default() -> Self {
+        Self {
+            max_in_flight: 10,
+            reserved_interactive: 2,
+            background_boost_ns: 30_000_000_000,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Limiter {
+    config: LimiterConfig,
+    in_flight: u32,
+    // When Background work was first refused since it last got a slot
+    background_refused_since: Option<u64>,
+}
+
+impl Limiter {
+    // Number of slots the priority may occupy, including those already in flight
+    fn limit(&self, priority: Priority, now: u64) -> u32 {
+        let boosted = self
+            .background_refused_since
+            .is_some_and(|since| now.saturating_sub(since) >= self.config.background_boost_ns);
+        let limit = match priority {
+            Priority::Background if !boosted => self
+                .config
+                .max_in_flight
+                .saturating_sub(self.config.reserved_interactive),
+            _ => self.config.max_in_flight,
+        };
+        limit.max(1)
+    }
+}
+
+thread_local! {
+    static LIMITER: RefCell<Limiter> = RefCell::default();
+}
+
+/// Set the limits on concurrent outcalls
+pub fn set_config(config: LimiterConfig) {
+    LIMITER.with(|limiter| limiter.borrow_mut().config = config);
+}
+
+/// Current limits on concurrent outcalls
+pub fn config() -> LimiterConfig {
+    LIMITER.with(|limiter| limiter.borrow().config.clone())
+}
+
+/// Take an outcall slot for the given priority, or refuse with a retryable
+/// `OutcallLimitReached` error if none is available.
+/// The slot is held until the returned permit is dropped.
+pub fn try_acquire(priority: Priority) -> Result<Permit, EthRpcError> {
+    LIMITER.with(|limiter| {
+        let mut limiter = limiter.borrow_mut();
+        let now = clock::now();
+        let limit = limiter.limit(priority, now);
+        if limiter.in_flight >= limit {
+            if priority == Priority::Background {
+                limiter.background_refused_since.get_or_insert(now);
+            }
+            metrics::increment(&format!("outcall_refusals_{}", priority.name()));
+            return Err(EthRpcError::OutcallLimitReached {
+                in_flight: limiter.in_flight,
+                limit,
+            });
+        }
+        if priority == Priority::Background {
+            limiter.background_refused_since = None;
+        }
+        limiter.in_flight += 1;
+        metrics::increment(&format!("outcall_dispatches_{}", priority.name()));
+        Ok(Permit { _private: () })
+    })
+}
+
+/// An outcall slot, released when dropped
+pub struct Permit {
+    _private: (),
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        LIMITER.with(|limiter| {
+            let mut limiter = limiter.borrow_mut();
+            limiter.in_flight = limiter.in_flight.saturating_sub(1);
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn refused(in_flight: u32, limit: u32) -> Result<(), EthRpcError> {
+        Err(EthRpcError::OutcallLimitReached { in_flight, limit })
+    }
+
+    #[test]
+    fn refuses_over_limit_until_release() {
+        set_config(LimiterConfig {
+            max_in_flight: 2,
+            reserved_interactive: 0,
+            ..Default::default()
+        });
+        let first = try_acquire(Priority::Interactive).unwrap();
+        let _second = try_acquire(Priority::Interactive).unwrap();
+        assert_eq!(try_acquire(Priority::Interactive).map(drop), refused(2, 2));
+        drop(first);
+        let _third = try_acquire(Priority::Interactive).unwrap();
+        assert_eq!(metrics::counters()["outcall_refusals_interactive"], 1);
+    }
+
+    #[test]
+    fn interactive_work_is_admitted_when_background_saturates() {
+        set_config(LimiterConfig {
+            max_in_flight: 3,
+            reserved_interactive: 1,
+            background_boost_ns: 30_000_000_000,
+        });
+        clock::set_now(0);
+        // Background work can't take the reserved slot
+        let _background_1 = try_acquire(Priority::Background).unwrap();
+        let background_2 = try_acquire(Priority::Background).unwrap();
+        assert_eq!(try_acquire(Priority::Background).map(drop), refused(2, 2));
+
+        // Interactive work submitted next is admitted
+        let interactive = try_acquire(Priority::Interactive).unwrap();
+        assert_eq!(try_acquire(Priority::Interactive).map(drop), refused(3, 3));
+
+        // Freeing a slot leaves it to Interactive work while the reserve is used
+        drop(background_2);
+        assert_eq!(try_acquire(Priority::Background).map(drop), refused(2, 2));
+        drop(interactive);
+        let _background_3 = try_acquire(Priority::Background).unwrap();
+    }
+
+    #[test]
+    fn background_work_is_boosted_after_refusals() {
+        set_config(LimiterConfig {
+            max_in_flight: 2,
+            reserved_interactive: 1,
+            background_boost_ns: 30_000_000_000,
+        });
+        clock::set_now(0);
+        let _background = try_acquire(Priority::Background).unwrap();
+        assert_eq!(try_acquire(Priority::Background).map(drop), refused(1, 1));
+        clock::set_now(29_999_999_999);
+        assert_eq!(try_acquire(Priority::Background).map(drop), refused(1, 1));
+
+        // Once refused for long enough, Background work may take the reserved slot
+        clock::set_now(30_000_000_000);
+        let boosted = try_acquire(Priority::Background).unwrap();
+        assert_eq!(try_acquire(Priority::Interactive).map(drop), refused(2, 2));
+
+        // Getting a slot ends the boost
+        drop(boosted);
+        assert_eq!(try_acquire(Priority::Background).map(drop), refused(1, 1));
+    }
+}
@@ -0,0 +1,26 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+// Named event counters
+thread_local! {
+    static COUNTERS: RefCell<BTreeMap<String, u64>> = RefCell::default();
+}
+
+/// Increment a named counter
+pub fn increment(name: &str) {
+    add(name, 1);
+}
+
+/// Add an amount to a named counter
+pub fn add(name: &str, amount: u64) {
+    COUNTERS.with(|counters| {
+        let mut counters = counters.borrow_mut();
+        let counter = counters.entry(name.to_string()).or_default();
+        *counter = counter.saturating_add(amount);
+    });
+}
+
+/// Current value of every counter
+pub fn counters() -> BTreeMap<String, u64> {
+    COUNTERS.with(|counters| counters.borrow().clone())
+}
@@ -0,0 +1,198 @@
+use ethers_core::abi::{Component, Function, ParamType, Token};
+use serde::Serialize;
+use serde_json::Value;
+
+use crate::decoded::DecodedValue;
+use crate::util::{token_to_json_with, JsonOutputFormat};
+
+/// A decoded value together with its ABI parameter name and type.
+///
+/// Tuple values are rendered as a list of named components, and arrays as a list of
+/// element values, so structs (and arrays of structs) are self-describing.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct NamedToken {
+    pub name: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub value: Value,
+}
+
+/// A decoded event with named parameters
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct DecodedEvent {
+    pub name: String,
+    pub params: Vec<NamedToken>,
+}
+
+fn named_value(
+    kind: &ParamType,
+    component: Option<&Component>,
+    value: DecodedValue,
+    format: JsonOutputFormat,
+) -> Value {
+    let elements = match kind {
+        ParamType::Tuple(kinds) => {
+            if let Ok(values) = value.as_tuple() {
+                let components = component.map_or(&[][..], |component| &component.components);
+                return Value::Array(
+                    named_values(kinds, components, values, format)
+                        .map(|named| {
+                            serde_json::to_value(named).expect("Error encoding named token")
+                        })
+                        .collect(),
+                );
+            }
+            None
+        }
+        ParamType::Array(kind) => value
+            .as_array()
+            .ok()
+            .map(|values| (kind, values.collect::<Vec<_>>())),
+        ParamType::FixedArray(kind, _) => value
+            .as_fixed_array()
+            .ok()
+            .map(|values| (kind, values.collect())),
+        _ => None,
+    };
+    match elements {
+        Some((kind, values)) => Value::Array(
+            values
+                .into_iter()
+                .map(|value| named_value(kind, component, value, format))
+                .collect(),
+        ),
+        None => token_to_json_with(value.0, format),
+    }
+}
+
+fn named_values<'a>(
+    kinds: &'a [ParamType],
+    components: &'a [Component],
+    values: impl Iterator<Item = DecodedValue<'a>> + 'a,
+    format: JsonOutputFormat,
+) -> impl Iterator<Item = NamedToken> + 'a {
+    values
+        .zip(kinds)
+        .enumerate()
+        .map(move |(i, (value, kind))| {
+            let component = components.get(i);
+            NamedToken {
+                name: component
+                    .map(|component| component.name.clone())
+                    .filter(|name| !name.is_empty())
+                    .unwrap_or_else(|| format!("_{}", i)),
+                kind: component
+                    .map(|component| component.type_field.clone())
+                    .unwrap_or_else(|| kind.to_string()),
+                value: named_value(kind, component, value, format),
+            }
+        })
+}
+
+/// Pair decoded tokens with their ABI types and component names.
+/// Parameters without a name are named by position (`_0`, `_1`, ...).
+pub fn named_tokens(
+    kinds: &[ParamType],
+    components: &[Component],
+    tokens: &[Token],
+) -> Vec<NamedToken> {
+    named_tokens_with(kinds, components, tokens, JsonOutputFormat::default())
+}
+
+/// Pair decoded tokens with their ABI types and component names like
+/// [`named_tokens`], rendering values in the given format
+pub fn named_tokens_with(
+    kinds: &[ParamType],
+    components: &[Component],
+    tokens: &[Token],
+    format: JsonOutputFormat,
+) -> Vec<NamedToken> {
+    named_values(kinds, components, tokens.iter().map(DecodedValue), format).collect()
+}
+
+/// Decode a function's return data into named outputs, using the output
+/// components (with their names) from the JSON ABI
+pub fn decode_output_named(
+    function: &Function,
+    outputs: &[Component],
+    data: &[u8],
+) -> Result<Vec<NamedToken>, ethers_core::abi::Error> {
+    let tokens = function.decode_output(data)?;
+    let kinds = function
+        .outputs
+        .iter()
+        .map(|output| output.kind.clone())
+        .collect::<Vec<_>>();
+    Ok(named_tokens(&kinds, outputs, &tokens))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::types::{Address, U256};
+    use serde_json::json;
+
+    #[test]
+    fn names_unnamed_components_by_position() {
+        let kinds = vec![
+            ParamType::Address,
+            ParamType::Tuple(vec![ParamType::Uint(256), ParamType::Bool]),
+        ];
+        let components = serde_json::from_value::<Vec<Component>>(json!([
+            {"name": "", "type": "address"},
+            {
+                "name": "",
+                "type": "tuple",
+                "components": [
+                    {"name": "", "type": "uint256"},
+                    {"name": "paused", "type": "bool"},
+                ],
+            },
+        ]))
+        .unwrap();
+        let tokens = vec![
+            Token::Address(Address::repeat_byte(0x11)),
+            Token::Tuple(vec![Token::Uint(U256::from(7)), Token::Bool(false)]),
+        ];
+
+        let expected = json!([
+            {
+                "name": "_0",
+                "type": "address",
+                "value": "0x1111111111111111111111111111111111111111",
+            },
+            {
+                "name": "_1",
+                "type": "tuple",
+                "value": [
+                    {"name": "_0", "type": "uint256", "value": "7"},
+                    {"name": "paused", "type": "bool", "value": false},
+                ],
+            },
+        ]);
+        assert_eq!(
+            serde_json::to_value(named_tokens(&kinds, &components, &tokens)).unwrap(),
+            expected
+        );
+        // Without components (e.g. an ABI not parsed by `NamedAbi`) the types come from
+        // the `ParamType`s
+        assert_eq!(
+            serde_json::to_value(named_tokens(&kinds, &[], &tokens)).unwrap(),
+            json!([
+                {
+                    "name": "_0",
+                    "type": "address",
+                    "value": "0x1111111111111111111111111111111111111111",
+                },
+                {
+                    "name": "_1",
+                    "type": "(uint256,bool)",
+                    "value": [
+                        {"name": "_0", "type": "uint256", "value": "7"},
+                        {"name": "_1", "type": "bool", "value": false},
+                    ],
+                },
+            ])
+        );
+    }
+}
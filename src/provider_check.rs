@@ -0,0 +1,269 @@
+use candid::CandidType;
+use ethers_core::types::U64;
+use futures::future::join_all;
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::clock;
+use crate::error::EthRpcError;
+use crate::eth_rpc::{batch_call_to, observe_head_block, BatchRequest, CallOptions};
+use crate::providers::{expected_chain_id, list_providers};
+
+/// Readiness report for a single provider
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct ProviderCheck {
+    pub url: String,
+    pub chain_id: u64,
+    pub expected_chain_id: Option<u64>,
+    pub chain_id_matches: bool,
+    pub syncing: bool,
+    pub head_block: u64,
+    pub latency_ns: u64,
+    /// Whether the head block lags the most recent head among the network's providers
+    pub lagging: bool,
+}
+
+impl ProviderCheck {
+    /// Whether the provider is fit to serve traffic
+    pub fn is_ready(&self) -> bool {
+        self.chain_id_matches && !self.syncing && !self.lagging
+    }
+}
+
+fn parse_quantity(value: Value) -> Result<u64, EthRpcError> {
+    serde_json::from_value::<U64>(value)
+        .map(|n| n.as_u64())
+        .map_err(|_| EthRpcError::InvalidResponse("Invalid quantity".to_string()))
+}
+
+// `eth_syncing` returns `false`, or an object describing sync progress
+fn parse_syncing(value: Value) -> Result<bool, EthRpcError> {
+    match value {
+        Value::Bool(syncing) => Ok(syncing),
+        Value::Object(_) => Ok(true),
+        _ => Err(EthRpcError::InvalidResponse(
+            "Invalid eth_syncing result".to_string(),
+        )),
+    }
+}
+
+/// Check a provider URL template against a network, bypassing provider rotation.
+/// The URL may not be one of the network's providers (e.g. one about to be
+/// registered), so its head block isn't recorded as the network's.
+pub async fn check_provider_url(network: &str, url: &str) -> Result<ProviderCheck, EthRpcError> {
+    let requests = ["eth_chainId", "eth_syncing", "eth_blockNumber"].map(|method| BatchRequest {
+        method: method.to_string(),
+        params: json!([]),
+        rounding: None,
+    });
+    let start = clock::now();
+    let results = batch_call_to(url, &requests, &CallOptions::default()).await?;
+    let latency_ns = clock::now() - start;
+    let [chain_id, syncing, head_block]: [Result<Value, EthRpcError>; 3] = results
+        .try_into()
+        .map_err(|_| EthRpcError::InvalidResponse("Unexpected batch response".to_string()))?;
+    let chain_id = parse_quantity(chain_id?)?;
+    let expected_chain_id = expected_chain_id(network);
+    let chain_id_matches = expected_chain_id.is_none_or(|expected| expected == chain_id);
+    Ok(ProviderCheck {
+        url: url.to_string(),
+        chain_id,
+        expected_chain_id,
+        chain_id_matches,
+        syncing: parse_syncing(syncing?)?,
+        head_block: parse_quantity(head_block?)?,
+        latency_ns,
+        lagging: false,
+    })
+}
+
+// Check one of a network's providers, recording its head block if it serves the network
+async fn check_registered(network: &str, url: &str) -> Result<ProviderCheck, EthRpcError> {
+    let check = check_provider_url(network, url).await?;
+    if check.chain_id_matches {
+        observe_head_block(network, check.head_block);
+    }
+    Ok(check)
+}
+
+/// Check the provider at the given index of a network's provider list
+pub async fn check_provider(
+    network: &str,
+    provider_index: usize,
+) -> Result<ProviderCheck, EthRpcError> {
+    let providers = list_providers(network);
+    if providers.is_empty() {
+        return Err(EthRpcError::UnsupportedNetwork(network.to_string()));
+    }
+    let url = providers
+        .into_iter()
+        .nth(provider_index)
+        .ok_or_else(|| EthRpcError::InvalidInput("Provider index out of range".to_string()))?;
+    check_registered(network, &url).await
+}
+
+/// Check every provider of a network concurrently, flagging providers whose
+/// head block lags the highest head by more than `max_lag_blocks`
+pub async fn check_all_providers(
+    network: &str,
+    max_lag_blocks: u64,
+) -> Vec<Result<ProviderCheck, EthRpcError>> {
+    let providers = list_providers(network);
+    let mut results = join_all(providers.iter().map(|url| check_registered(network, url))).await;
+    let max_head = results
+        .iter()
+        .filter_map(|result| result.as_ref().ok())
+        .map(|check| check.head_block)
+        .max()
+        .unwrap_or_default();
+    for check in results.iter_mut().flatten() {
+        check.lagging = max_head - check.head_block > max_lag_blocks;
+    }
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eth_rpc::known_head_block;
+    use crate::providers::NetworkConfig;
+    use crate::transport::{set_transport, MockTransport};
+    use futures::executor::block_on;
+    use ic_cdk::api::management_canister::http_request::HttpResponse;
+    use std::rc::Rc;
+
+    // Answer readiness checks with the result for each provider URL and method
+    fn mock_rpc(result: impl Fn(&str, &str) -> Value + 'static) -> Rc<MockTransport> {
+        let transport = Rc::new(MockTransport::new(move |request| {
+            let body: Vec<Value> = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            let responses = body
+                .iter()
+                .map(|entry| {
+                    let result = result(&request.url, entry["method"].as_str().unwrap());
+                    json!({"jsonrpc": "2.0", "id": entry["id"], "result": result})
+                })
+                .collect::<Vec<_>>();
+            Ok(HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(&responses).unwrap(),
+            })
+        }));
+        set_transport(transport.clone());
+        transport
+    }
+
+    // Result of a synced mainnet provider at the given head block
+    fn synced(method: &str, head_block: u64) -> Value {
+        match method {
+            "eth_chainId" => json!("0x1"),
+            "eth_syncing" => json!(false),
+            "eth_blockNumber" => json!(format!("{:#x}", head_block)),
+            method => panic!("Unexpected method {}", method),
+        }
+    }
+
+    fn mock_provider() -> Rc<MockTransport> {
+        mock_rpc(|_, method| synced(method, 16))
+    }
+
+    #[test]
+    fn checks_provider_by_index() {
+        mock_provider();
+        let check = block_on(check_provider("mainnet", 0)).unwrap();
+        assert_eq!(check.url, list_providers("mainnet")[0]);
+        assert_eq!(check.chain_id, 1);
+        assert!(check.chain_id_matches);
+        assert_eq!(check.head_block, 16);
+        assert!(check.is_ready());
+    }
+
+    #[test]
+    fn refuses_provider_index_out_of_range() {
+        let transport = mock_provider();
+        let index = list_providers("mainnet").len();
+        assert_eq!(
+            block_on(check_provider("mainnet", index)).unwrap_err(),
+            EthRpcError::InvalidInput("Provider index out of range".to_string())
+        );
+        assert_eq!(
+            block_on(check_provider("unknown", 0)).unwrap_err(),
+            EthRpcError::UnsupportedNetwork("unknown".to_string())
+        );
+        assert!(transport.requests().is_empty());
+    }
+
+    #[test]
+    fn reports_sync_progress_as_syncing() {
+        mock_rpc(|_, method| match method {
+            "eth_syncing" => json!({
+                "startingBlock": "0x0",
+                "currentBlock": "0x8",
+                "highestBlock": "0x10",
+            }),
+            method => synced(method, 8),
+        });
+        let check = block_on(check_provider("mainnet", 0)).unwrap();
+        assert!(check.syncing);
+        assert!(!check.is_ready());
+    }
+
+    #[test]
+    fn flags_lagging_providers() {
+        crate::providers::register_provider(
+            "mainnet",
+            NetworkConfig {
+                base: "https://rpc.example.org".to_string(),
+                path: None,
+            },
+            Default::default(),
+        )
+        .unwrap();
+        crate::providers::register_provider(
+            "mainnet",
+            NetworkConfig {
+                base: "https://lagging.example.org".to_string(),
+                path: None,
+            },
+            Default::default(),
+        )
+        .unwrap();
+        mock_rpc(|url, method| match url {
+            "https://lagging.example.org" => synced(method, 90),
+            "https://rpc.example.org" => synced(method, 98),
+            _ => synced(method, 100),
+        });
+
+        let checks = block_on(check_all_providers("mainnet", 4))
+            .into_iter()
+            .map(|check| {
+                let check = check.unwrap();
+                (check.url, check.head_block, check.lagging)
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            checks,
+            vec![
+                (list_providers("mainnet")[0].clone(), 100, false),
+                ("https://rpc.example.org".to_string(), 98, false),
+                ("https://lagging.example.org".to_string(), 90, true),
+            ]
+        );
+        assert_eq!(known_head_block("mainnet"), Some(100));
+    }
+
+    #[test]
+    fn unregistered_urls_dont_move_the_head_block() {
+        mock_rpc(|_, method| synced(method, 1_000));
+        let check = block_on(check_provider_url(
+            "mainnet",
+            "https://unvetted.example.org",
+        ))
+        .unwrap();
+        assert_eq!(check.head_block, 1_000);
+        assert_eq!(known_head_block("mainnet"), None);
+
+        block_on(check_provider("mainnet", 0)).unwrap();
+        assert_eq!(known_head_block("mainnet"), Some(1_000));
+    }
+}
@@ -0,0 +1,462 @@
+use candid::CandidType;
+use ethers_core::types::Address;
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::health;
+use crate::secrets::{expand_secrets, referenced_secrets};
+
+// Built-in JSON-RPC providers for each supported network: (network, base URL, path)
+const DEFAULT_PROVIDERS: &[(&str, &str, Option<&str>)] = &[
+    ("mainnet", "https://cloudflare-eth.com", Some("/v1/mainnet")),
+    ("goerli", "https://ethereum-goerli.publicnode.com", None),
+    ("sepolia", "https://rpc.sepolia.org", None),
+];
+
+// Built-in networks: (name, chain id, chain name, native currency symbol, block explorer)
+const BUILTIN_NETWORKS: &[(&str, u64, &str, &str, &str)] = &[
+    (
+        "mainnet",
+        1,
+        "Ethereum Mainnet",
+        "ETH",
+        "https://etherscan.io",
+    ),
+    (
+        "goerli",
+        5,
+        "Goerli",
+        "GoerliETH",
+        "https://goerli.etherscan.io",
+    ),
+    (
+        "sepolia",
+        11_155_111,
+        "Sepolia",
+        "SepoliaETH",
+        "https://sepolia.etherscan.io",
+    ),
+];
+
+// Canonical WETH contracts of the built-in networks
+const WETH_ADDRESSES: &[(&str, &str)] = &[
+    ("mainnet", "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2"),
+    ("goerli", "0xB4FBF271143F4FBf7B91A5ded31805e42b2208d6"),
+    ("sepolia", "0x7b79995e5f793A07Bc00c21412e50Ecae098E7f9"),
+];
+
+// Depth of recent state kept by a full node before pruning (geth's default)
+const FULL_NODE_STATE_DEPTH: u64 = 128;
+
+/// How much historical state a provider serves
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum ArchiveSupport {
+    /// A full node, keeping state for about the last 128 blocks
+    #[default]
+    Full,
+    /// State is kept for the given number of blocks behind the head
+    Recent(u64),
+    /// An archive node, keeping state for every block
+    Archive,
+}
+
+impl ArchiveSupport {
+    /// Whether state this many blocks behind the head can be served.
+    /// An unbounded depth (e.g. the earliest block) is only served by archive nodes.
+    pub fn can_serve(&self, depth: Option<u64>) -> bool {
+        match (self, depth) {
+            (ArchiveSupport::Archive, _) => true,
+            (_, None) => false,
+            (ArchiveSupport::Full, Some(depth)) => depth <= FULL_NODE_STATE_DEPTH,
+            (ArchiveSupport::Recent(blocks), Some(depth)) => depth <= *blocks,
+        }
+    }
+}
+
+/// A network's native currency, as described by EIP-3085
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub struct NativeCurrency {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+/// Chain metadata in the format of EIP-3085 (`wallet_addEthereumChain`)
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct ChainMetadata {
+    /// Hex-encoded chain id (e.g. `0x1`)
+    pub chain_id: String,
+    pub chain_name: String,
+    pub rpc_urls: Vec<String>,
+    pub native_currency: NativeCurrency,
+    pub block_explorer_urls: Vec<String>,
+}
+
+/// A provider's base URL and optional endpoint path (e.g. `/v3/<project id>` for
+/// Infura), kept apart so that neither has to be concatenated by hand. Either may
+/// contain `{{secret:NAME}}` placeholders.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    pub base: String,
+    pub path: Option<String>,
+}
+
+impl NetworkConfig {
+    /// The provider's URL template, which identifies the provider
+    pub fn url(&self) -> String {
+        match &self.path {
+            Some(path) => join_url(&self.base, path),
+            None => self.base.clone(),
+        }
+    }
+}
+
+// Join a base URL and an endpoint path with exactly one `/` between them
+fn join_url(base: &str, path: &str) -> String {
+    let path = path.trim_start_matches('/');
+    if path.is_empty() {
+        return base.to_string();
+    }
+    format!("{}/{}", base.trim_end_matches('/'), path)
+}
+
+// Providers by network
+thread_local! {
+    static PROVIDERS: RefCell<BTreeMap<String, Vec<NetworkConfig>>> = RefCell::new(
+        DEFAULT_PROVIDERS
+            .iter()
+            .map(|(network, base, path)| {
+                let config = NetworkConfig {
+                    base: base.to_string(),
+                    path: path.map(str::to_string),
+                };
+                (network.to_string(), vec![config])
+            })
+            .collect()
+    );
+    // Historical state served by each network's provider URL templates (`Full` when absent)
+    static ARCHIVE_SUPPORT: RefCell<BTreeMap<String, BTreeMap<String, ArchiveSupport>>> =
+        RefCell::default();
+}
+
+// Map network aliases to the name used in the registry
+fn canonical_network(network: &str) -> &str {
+    match network {
+        "ethereum" => "mainnet",
+        other => other,
+    }
+}
+
+/// Chain id expected for a network, if it is a built-in network
+pub fn expected_chain_id(network: &str) -> Option<u64> {
+    let network = canonical_network(network);
+    BUILTIN_NETWORKS
+        .iter()
+        .find(|(name, ..)| *name == network)
+        .map(|(_, chain_id, ..)| *chain_id)
+}
+
+/// Address of the canonical WETH contract on a built-in network
+pub fn weth_address(network: &str) -> Option<Address> {
+    let network = canonical_network(network);
+    WETH_ADDRESSES
+        .iter()
+        .find(|(name, _)| *name == network)
+        .map(|(_, address)| address.parse().expect("Invalid WETH address"))
+}
+
+/// Wallet configuration for a built-in network.
+/// Only provider URLs which don't reference secrets are included.
+pub fn chain_metadata(network: &str) -> Option<ChainMetadata> {
+    let network = canonical_network(network);
+    let (_, chain_id, chain_name, symbol, explorer) = BUILTIN_NETWORKS
+        .iter()
+        .find(|(name, ..)| *name == network)?;
+    Some(ChainMetadata {
+        chain_id: format!("{:#x}", chain_id),
+        chain_name: chain_name.to_string(),
+        rpc_urls: list_providers(network)
+            .into_iter()
+            .filter(|url| referenced_secrets(url).is_empty())
+            .collect(),
+        native_currency: NativeCurrency {
+            name: "Ether".to_string(),
+            symbol: symbol.to_string(),
+            decimals: 18,
+        },
+        block_explorer_urls: vec![explorer.to_string()],
+    })
+}
+
+/// List the provider URL templates configured for a network.
+/// Secret placeholders are returned as-is and never expanded.
+pub fn list_providers(network: &str) -> Vec<String> {
+    PROVIDERS.with(|providers| {
+        providers
+            .borrow()
+            .get(canonical_network(network))
+            .map(|configs| configs.iter().map(NetworkConfig::url).collect())
+            .unwrap_or_default()
+    })
+}
+
+/// Add a provider for a network
+pub fn register_provider(
+    network: &str,
+    config: NetworkConfig,
+    archive: ArchiveSupport,
+) -> Result<(), String> {
+    let url = config.url();
+    let expanded = expand_secrets(&url)?;
+    url::Url::parse(&expanded).map_err(|_| "Invalid provider URL".to_string())?;
+    PROVIDERS.with(|providers| {
+        let mut providers = providers.borrow_mut();
+        let configs = providers
+            .entry(canonical_network(network).to_string())
+            .or_default();
+        if configs.iter().any(|existing| existing.url() == url) {
+            return Err(format!("Provider already registered for {}", network));
+        }
+        set_archive_support(network, &url, archive);
+        configs.push(config);
+        Ok(())
+    })
+}
+
+// Record the historical state served by a network's provider
+fn set_archive_support(network: &str, url: &str, archive: ArchiveSupport) {
+    let network = canonical_network(network);
+    ARCHIVE_SUPPORT.with(|support| {
+        let mut support = support.borrow_mut();
+        if archive == ArchiveSupport::Full {
+            if let Some(urls) = support.get_mut(network) {
+                urls.remove(url);
+                if urls.is_empty() {
+                    support.remove(network);
+                }
+            }
+        } else {
+            support
+                .entry(network.to_string())
+                .or_default()
+                .insert(url.to_string(), archive);
+        }
+    })
+}
+
+/// Historical state served by a network's provider URL template
+pub fn archive_support(network: &str, url: &str) -> ArchiveSupport {
+    ARCHIVE_SUPPORT.with(|support| {
+        support
+            .borrow()
+            .get(canonical_network(network))
+            .and_then(|urls| urls.get(url))
+            .copied()
+            .unwrap_or_default()
+    })
+}
+
+/// Whether a network's provider URL template is an archive node
+pub fn is_archive(network: &str, url: &str) -> bool {
+    archive_support(network, url) == ArchiveSupport::Archive
+}
+
+/// Remove a provider URL template from a network. Its health statistics are
+/// dropped once no network uses it.
+pub fn remove_provider(network: &str, url: &str) -> Result<(), String> {
+    PROVIDERS.with(|providers| {
+        let mut providers = providers.borrow_mut();
+        let configs = providers
+            .get_mut(canonical_network(network))
+            .ok_or_else(|| format!("Unsupported network: {}", network))?;
+        let len = configs.len();
+        configs.retain(|existing| existing.url() != url);
+        if configs.len() == len {
+            return Err(format!("Provider not registered for {}", network));
+        }
+        set_archive_support(network, url, ArchiveSupport::Full);
+        if !providers
+            .values()
+            .flatten()
+            .any(|existing| existing.url() == url)
+        {
+            health::remove_stats(url);
+        }
+        Ok(())
+    })
+}
+
+/// Find the providers (as `network: url` entries) that reference a secret
+pub fn secret_referents(name: &str) -> Vec<String> {
+    PROVIDERS.with(|providers| {
+        providers
+            .borrow()
+            .iter()
+            .flat_map(|(network, configs)| {
+                configs.iter().map(move |config| (network, config.url()))
+            })
+            .filter(|(_, url)| referenced_secrets(url).iter().any(|secret| secret == name))
+            .map(|(network, url)| format!("{}: {}", network, url))
+            .collect()
+    })
+}
+
+/// Provider URL templates by network
+pub fn snapshot() -> BTreeMap<String, Vec<String>> {
+    PROVIDERS.with(|providers| {
+        providers
+            .borrow()
+            .iter()
+            .map(|(network, configs)| {
+                let urls = configs.iter().map(NetworkConfig::url).collect();
+                (network.clone(), urls)
+            })
+            .collect()
+    })
+}
+
+/// Restore providers given by URL only (as saved before base URLs and paths were
+/// kept apart)
+pub fn restore(state: BTreeMap<String, Vec<String>>) {
+    restore_configs(
+        state
+            .into_iter()
+            .map(|(network, urls)| {
+                let configs = urls
+                    .into_iter()
+                    .map(|base| NetworkConfig { base, path: None })
+                    .collect();
+                (network, configs)
+            })
+            .collect(),
+    );
+}
+
+pub fn snapshot_configs() -> BTreeMap<String, Vec<NetworkConfig>> {
+    PROVIDERS.with(|providers| providers.borrow().clone())
+}
+
+pub fn restore_configs(state: BTreeMap<String, Vec<NetworkConfig>>) {
+    PROVIDERS.with(|providers| *providers.borrow_mut() = state);
+}
+
+pub fn snapshot_archive() -> BTreeMap<String, BTreeMap<String, ArchiveSupport>> {
+    ARCHIVE_SUPPORT.with(|support| support.borrow().clone())
+}
+
+pub fn restore_archive(state: BTreeMap<String, BTreeMap<String, ArchiveSupport>>) {
+    ARCHIVE_SUPPORT.with(|support| *support.borrow_mut() = state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn archive_support_is_kept_per_network() {
+        let url = "https://rpc.example.com".to_string();
+        let config = NetworkConfig {
+            base: url.clone(),
+            path: None,
+        };
+        register_provider("mainnet", config.clone(), ArchiveSupport::Archive).unwrap();
+        register_provider("sepolia", config.clone(), ArchiveSupport::Recent(1000)).unwrap();
+        assert_eq!(archive_support("ethereum", &url), ArchiveSupport::Archive);
+        assert!(is_archive("mainnet", &url));
+        assert_eq!(
+            archive_support("sepolia", &url),
+            ArchiveSupport::Recent(1000)
+        );
+        assert!(!is_archive("sepolia", &url));
+
+        // Re-registering as a full node clears the previous support
+        remove_provider("mainnet", &url).unwrap();
+        register_provider("mainnet", config, ArchiveSupport::Full).unwrap();
+        assert_eq!(archive_support("mainnet", &url), ArchiveSupport::Full);
+        assert_eq!(
+            archive_support("sepolia", &url),
+            ArchiveSupport::Recent(1000)
+        );
+
+        remove_provider("sepolia", &url).unwrap();
+        assert!(snapshot_archive().is_empty());
+    }
+
+    #[test]
+    fn joins_base_url_and_path() {
+        let url = |base: &str, path: Option<&str>| {
+            NetworkConfig {
+                base: base.to_string(),
+                path: path.map(str::to_string),
+            }
+            .url()
+        };
+        let joined = "https://mainnet.infura.io/v3/{{secret:INFURA}}";
+        assert_eq!(
+            url("https://mainnet.infura.io", Some("/v3/{{secret:INFURA}}")),
+            joined
+        );
+        assert_eq!(
+            url("https://mainnet.infura.io/", Some("/v3/{{secret:INFURA}}")),
+            joined
+        );
+        assert_eq!(
+            url("https://mainnet.infura.io/", Some("v3/{{secret:INFURA}}")),
+            joined
+        );
+        assert_eq!(
+            url("https://mainnet.infura.io", Some("v3/{{secret:INFURA}}")),
+            joined
+        );
+        // A trailing slash of the path is kept, as the provider may need it
+        assert_eq!(
+            url("https://rpc.example.com", Some("/v1/")),
+            "https://rpc.example.com/v1/"
+        );
+        // An empty path leaves the base URL as it is
+        assert_eq!(
+            url("https://rpc.example.com", Some("")),
+            "https://rpc.example.com"
+        );
+        assert_eq!(
+            url("https://rpc.example.com", Some("/")),
+            "https://rpc.example.com"
+        );
+        assert_eq!(
+            url("https://rpc.example.com/", None),
+            "https://rpc.example.com/"
+        );
+    }
+
+    #[test]
+    fn keeps_base_url_and_path_apart() {
+        let config = NetworkConfig {
+            base: "https://mainnet.infura.io/".to_string(),
+            path: Some("/v3/0123456789abcdef".to_string()),
+        };
+        register_provider("goerli", config.clone(), ArchiveSupport::Full).unwrap();
+        assert!(list_providers("goerli")
+            .contains(&"https://mainnet.infura.io/v3/0123456789abcdef".to_string()));
+        assert!(snapshot_configs()["goerli"].contains(&config));
+        // The joined URL identifies the provider
+        let same_url = NetworkConfig {
+            base: "https://mainnet.infura.io/v3/0123456789abcdef".to_string(),
+            path: None,
+        };
+        assert_eq!(
+            register_provider("goerli", same_url, ArchiveSupport::Full),
+            Err("Provider already registered for goerli".to_string())
+        );
+        remove_provider("goerli", "https://mainnet.infura.io/v3/0123456789abcdef").unwrap();
+        assert!(!snapshot_configs()["goerli"].contains(&config));
+    }
+
+    #[test]
+    fn built_in_providers_keep_their_path() {
+        let mainnet = &snapshot_configs()["mainnet"][0];
+        assert_eq!(mainnet.base, "https://cloudflare-eth.com");
+        assert_eq!(mainnet.url(), "https://cloudflare-eth.com/v1/mainnet");
+    }
+}
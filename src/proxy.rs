@@ -0,0 +1,68 @@
+use candid::CandidType;
+use ethers_core::types::{Address, U256};
+use ethers_core::utils::keccak256;
+use serde::Deserialize;
+
+use crate::error::EthRpcError;
+use crate::eth_rpc::{get_storage_at, BlockTag};
+use crate::util::to_hex;
+
+// EIP-1967 slots are `keccak256(label) - 1`, so they have no known preimage
+const IMPLEMENTATION_LABEL: &str = "eip1967.proxy.implementation"; // 0x360894a1...
+const ADMIN_LABEL: &str = "eip1967.proxy.admin"; // 0xb5312768...
+const BEACON_LABEL: &str = "eip1967.proxy.beacon"; // 0xa3f0ad74...
+
+fn eip1967_slot(label: &str) -> U256 {
+    U256::from_big_endian(&keccak256(label)) - 1
+}
+
+// Read an address stored in an EIP-1967 slot of a proxy (unset when zero)
+async fn read_address_slot(
+    network: &str,
+    proxy: Address,
+    label: &str,
+) -> Result<Option<Address>, EthRpcError> {
+    let word = get_storage_at(network, proxy, eip1967_slot(label), BlockTag::Latest).await?;
+    let address = Address::from_slice(&word.as_bytes()[12..]);
+    Ok(Some(address).filter(|address| !address.is_zero()))
+}
+
+/// Address of the implementation behind an EIP-1967 proxy, if the slot is set
+pub async fn resolve_implementation(
+    network: &str,
+    proxy: Address,
+) -> Result<Option<Address>, EthRpcError> {
+    read_address_slot(network, proxy, IMPLEMENTATION_LABEL).await
+}
+
+/// Admin of an EIP-1967 (e.g. OpenZeppelin transparent) proxy, if the slot is set
+pub async fn resolve_proxy_admin(
+    network: &str,
+    proxy: Address,
+) -> Result<Option<Address>, EthRpcError> {
+    read_address_slot(network, proxy, ADMIN_LABEL).await
+}
+
+/// Beacon of an EIP-1967 beacon proxy, if the slot is set. The implementation
+/// is then given by the beacon's `implementation()` function.
+pub async fn resolve_beacon(network: &str, proxy: Address) -> Result<Option<Address>, EthRpcError> {
+    read_address_slot(network, proxy, BEACON_LABEL).await
+}
+
+/// Addresses held in the EIP-1967 slots of a proxy, as returned by `resolve_proxy`
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct ProxyInfo {
+    pub implementation: Option<String>,
+    pub admin: Option<String>,
+    pub beacon: Option<String>,
+}
+
+/// Read the implementation, admin and beacon slots of an EIP-1967 proxy
+pub async fn resolve_proxy(network: &str, proxy: Address) -> Result<ProxyInfo, EthRpcError> {
+    let hex = |address: Option<Address>| address.map(|address| to_hex(address.as_bytes()));
+    Ok(ProxyInfo {
+        implementation: hex(resolve_implementation(network, proxy).await?),
+        admin: hex(resolve_proxy_admin(network, proxy).await?),
+        beacon: hex(resolve_beacon(network, proxy).await?),
+    })
+}
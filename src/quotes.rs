@@ -0,0 +1,364 @@
+use candid::CandidType;
+use ethers_core::abi::{encode, Token};
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::{Address, Bytes, TransactionRequest, H160, U256};
+use ethers_core::utils::{format_ether, id};
+use serde::Deserialize;
+use serde_json::json;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+
+use crate::clock;
+use crate::ecdsa::SIGN_WITH_ECDSA_CYCLES;
+use crate::error::EthRpcError;
+use crate::eth_rpc::{
+    batch_call, estimate_outcall_cost, BatchRequest, BlockTag, CallOptions, Rounding,
+    FEE_SIGNIFICANT_DIGITS,
+};
+
+// Margin added to the gas estimate, in percent
+const GAS_BUFFER_PERCENT: u64 = 20;
+// How long a quote remains valid, unless its terms say otherwise
+const DEFAULT_VALIDITY_NS: u64 = 60_000_000_000;
+// How far the gas price may rise above a quote's before sending is refused, in
+// percent, unless its terms say otherwise
+const DEFAULT_FEE_TOLERANCE_PERCENT: u64 = 10;
+// Outcalls made when sending a transaction (gas estimate, gas price and broadcast)
+const SEND_OUTCALLS: u128 = 3;
+// Request size assumed for the cycles estimate of each send outcall
+const SEND_REQUEST_BYTES: usize = 1024;
+const SEND_RESPONSE_BYTES: u64 = 2048;
+// Maximum number of quotes kept
+const MAX_QUOTES: usize = 1000;
+// GasPriceOracle predeploy of OP-stack networks, which prices the L1 data fee
+const GAS_PRICE_ORACLE: H160 = H160([
+    0x42, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x0f,
+]);
+
+/// Expected cost of sending a transaction. Amounts are decimal strings in wei.
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Quote {
+    pub id: u64,
+    /// Gas estimate including a safety buffer
+    pub gas_limit: String,
+    pub gas_price_wei: String,
+    /// L1 data fee of OP-stack networks, as priced by their GasPriceOracle
+    pub l1_data_fee_wei: Option<String>,
+    /// Value plus the gas limit at the gas price, plus any L1 data fee
+    pub max_total_cost_wei: String,
+    pub max_total_cost_ether: String,
+    /// Cycles the canister spends on outcalls and signing to send the transaction
+    pub cycles_cost: u128,
+    /// Whether the sender's balance covers the maximum total cost, if a sender was given
+    pub balance_sufficient: Option<bool>,
+    /// Time (in nanoseconds since the epoch) after which the quote can no longer be used
+    pub valid_until: u64,
+    /// How far the gas price may rise above the quoted price before sending is refused, in percent
+    pub fee_tolerance_percent: u64,
+}
+
+/// Terms under which a quote can be used. Unset terms default to a validity of
+/// 60 seconds and a fee tolerance of 10%.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub struct QuoteTerms {
+    /// How long the quote remains valid, in nanoseconds
+    pub validity_ns: Option<u64>,
+    /// How far the gas price may rise above the quoted price before sending is refused, in percent
+    pub fee_tolerance_percent: Option<u64>,
+}
+
+thread_local! {
+    static QUOTES: RefCell<BTreeMap<u64, Quote>> = RefCell::default();
+    static NEXT_QUOTE_ID: Cell<u64> = Cell::default();
+}
+
+fn batch_value<T: serde::de::DeserializeOwned>(
+    result: Option<Result<serde_json::Value, EthRpcError>>,
+) -> Result<T, EthRpcError> {
+    let value =
+        result.ok_or_else(|| EthRpcError::InvalidResponse("Missing result".to_string()))??;
+    serde_json::from_value(value)
+        .map_err(|_| EthRpcError::InvalidResponse("Malformed result".to_string()))
+}
+
+// The L1 data fee from a GasPriceOracle `getL1Fee` result. Networks without the
+// oracle return no data (or an error), and charge no fee.
+fn l1_data_fee(result: Option<Result<serde_json::Value, EthRpcError>>) -> Option<U256> {
+    let result = result?.ok()?;
+    let digits = result.as_str()?.strip_prefix("0x")?;
+    if digits.is_empty() {
+        return None;
+    }
+    U256::from_str_radix(digits, 16)
+        .ok()
+        .filter(|fee| !fee.is_zero())
+}
+
+/// Quote the cost of sending a transaction, using a single batched outcall.
+///
+/// The gas estimate, gas price and L1 data fee are rounded up in the transform
+/// (see [`Rounding`]) so that replicas agree on them. The gas price is rounded
+/// the same way as by [`crate::eth_rpc::gas_price`], which [`check_quote`] is
+/// later given.
+pub async fn quote(
+    network: &str,
+    from: Option<Address>,
+    to: Address,
+    data: Vec<u8>,
+    value: U256,
+    terms: QuoteTerms,
+) -> Result<Quote, EthRpcError> {
+    let mut call = TransactionRequest::new().to(to).data(data).value(value);
+    if let Some(from) = from {
+        call = call.from(from);
+    }
+    // The oracle prices the RLP encoding of the unsigned transaction
+    let l1_fee_data = [
+        id("getL1Fee(bytes)").to_vec(),
+        encode(&[Token::Bytes(
+            TypedTransaction::Legacy(call.clone()).rlp().to_vec(),
+        )]),
+    ]
+    .concat();
+    let l1_fee_call = TransactionRequest::new()
+        .to(GAS_PRICE_ORACLE)
+        .data(Bytes::from(l1_fee_data));
+    let fee_rounding = Some(Rounding::SignificantDigits(FEE_SIGNIFICANT_DIGITS));
+    let mut requests = vec![
+        BatchRequest {
+            method: "eth_estimateGas".to_string(),
+            params: json!([call, BlockTag::Pending]),
+            rounding: fee_rounding,
+        },
+        BatchRequest {
+            method: "eth_gasPrice".to_string(),
+            params: json!([]),
+            rounding: fee_rounding,
+        },
+        BatchRequest {
+            method: "eth_call".to_string(),
+            params: json!([l1_fee_call, BlockTag::Latest]),
+            rounding: fee_rounding,
+        },
+    ];
+    if let Some(from) = from {
+        requests.push(BatchRequest {
+            method: "eth_getBalance".to_string(),
+            params: json!([from, BlockTag::Latest]),
+            rounding: None,
+        });
+    }
+    let mut results = batch_call(network, &requests, &CallOptions::default())
+        .await?
+        .into_iter();
+    let gas: U256 = batch_value(results.next())?;
+    let gas_price: U256 = batch_value(results.next())?;
+    let l1_data_fee = l1_data_fee(results.next());
+    let balance = results
+        .next()
+        .map(|result| batch_value::<U256>(Some(result)))
+        .transpose()?;
+
+    let gas_limit = gas + gas * GAS_BUFFER_PERCENT / 100;
+    let max_total_cost = gas_limit * gas_price + l1_data_fee.unwrap_or_default() + value;
+    let cycles_cost = SEND_OUTCALLS
+        * estimate_outcall_cost(SEND_REQUEST_BYTES, SEND_RESPONSE_BYTES)
+        + SIGN_WITH_ECDSA_CYCLES;
+    let id = NEXT_QUOTE_ID.with(|id| {
+        id.set(id.get() + 1);
+        id.get()
+    });
+    let quote = Quote {
+        id,
+        gas_limit: gas_limit.to_string(),
+        gas_price_wei: gas_price.to_string(),
+        l1_data_fee_wei: l1_data_fee.map(|fee| fee.to_string()),
+        max_total_cost_wei: max_total_cost.to_string(),
+        max_total_cost_ether: format_ether(max_total_cost),
+        cycles_cost,
+        balance_sufficient: balance.map(|balance| balance >= max_total_cost),
+        valid_until: clock::now().saturating_add(terms.validity_ns.unwrap_or(DEFAULT_VALIDITY_NS)),
+        fee_tolerance_percent: terms
+            .fee_tolerance_percent
+            .unwrap_or(DEFAULT_FEE_TOLERANCE_PERCENT),
+    };
+    QUOTES.with(|quotes| {
+        let mut quotes = quotes.borrow_mut();
+        let now = clock::now();
+        quotes.retain(|_, quote| quote.valid_until > now);
+        if quotes.len() >= MAX_QUOTES {
+            quotes.pop_first();
+        }
+        quotes.insert(id, quote.clone());
+    });
+    Ok(quote)
+}
+
+/// Refuse to send under a quote which has expired, or whose gas price has since
+/// risen beyond the tolerance
+pub fn check_quote(quote_id: u64, gas_price: U256) -> Result<(), EthRpcError> {
+    let quote = QUOTES
+        .with(|quotes| quotes.borrow().get(&quote_id).cloned())
+        .ok_or_else(|| EthRpcError::QuoteRejected(format!("Unknown quote {}", quote_id)))?;
+    if clock::now() > quote.valid_until {
+        return Err(EthRpcError::QuoteRejected(format!(
+            "Quote {} has expired",
+            quote_id
+        )));
+    }
+    let quoted_price = U256::from_dec_str(&quote.gas_price_wei).expect("Invalid quoted gas price");
+    if gas_price > quoted_price + quoted_price * quote.fee_tolerance_percent / 100 {
+        return Err(EthRpcError::QuoteRejected(format!(
+            "Gas price rose from {} to {} wei since quote {}",
+            quoted_price, gas_price, quote_id
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{set_transport, MockTransport};
+    use futures::executor::block_on;
+    use ic_cdk::api::management_canister::http_request::HttpResponse;
+    use std::rc::Rc;
+
+    const GWEI: u64 = 1_000_000_000;
+
+    // Answer quote batches with a 21000 gas estimate, a 1 gwei gas price, the
+    // given GasPriceOracle result and a 1 ether balance
+    fn mock_provider(l1_fee: &'static str) {
+        set_transport(Rc::new(MockTransport::new(move |request| {
+            let body: Vec<serde_json::Value> =
+                serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            let responses = body
+                .iter()
+                .map(|entry| {
+                    let result = match entry["method"].as_str().unwrap() {
+                        "eth_estimateGas" => "0x5208",
+                        "eth_gasPrice" => "0x3b9aca00",
+                        "eth_call" => {
+                            let call = &entry["params"][0];
+                            assert_eq!(call["to"], "0x420000000000000000000000000000000000000f");
+                            // getL1Fee(bytes)
+                            assert!(call["data"].as_str().unwrap().starts_with("0x49948e0e"));
+                            l1_fee
+                        }
+                        "eth_getBalance" => "0xde0b6b3a7640000",
+                        method => panic!("Unexpected method {}", method),
+                    };
+                    json!({"jsonrpc": "2.0", "id": entry["id"], "result": result})
+                })
+                .collect::<Vec<_>>();
+            Ok(HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(&responses).unwrap(),
+            })
+        })));
+    }
+
+    fn quote_transfer(value: U256, terms: QuoteTerms) -> Quote {
+        // Calls to accounts without code return no data
+        mock_provider("0x");
+        block_on(quote(
+            "mainnet",
+            Some(Address::repeat_byte(1)),
+            Address::repeat_byte(2),
+            vec![],
+            value,
+            terms,
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn quotes_buffered_gas_and_total_cost() {
+        clock::set_now(1_000);
+        let quote = quote_transfer(U256::exp10(17), QuoteTerms::default());
+        // 21000 gas plus 20%, at 1 gwei, plus 0.1 ether
+        assert_eq!(quote.gas_limit, "25200");
+        assert_eq!(quote.gas_price_wei, GWEI.to_string());
+        assert_eq!(quote.l1_data_fee_wei, None);
+        assert_eq!(quote.max_total_cost_wei, "100025200000000000");
+        assert_eq!(quote.max_total_cost_ether, "0.100025200000000000");
+        assert_eq!(quote.balance_sufficient, Some(true));
+        assert_eq!(quote.valid_until, 1_000 + DEFAULT_VALIDITY_NS);
+        assert_eq!(quote.fee_tolerance_percent, DEFAULT_FEE_TOLERANCE_PERCENT);
+
+        let quote = quote_transfer(U256::exp10(18), QuoteTerms::default());
+        assert_eq!(quote.balance_sufficient, Some(false));
+    }
+
+    #[test]
+    fn adds_l1_data_fee_of_op_stack_networks() {
+        clock::set_now(1_000);
+        // An ABI-encoded fee of 0x12d687 wei
+        mock_provider("0x000000000000000000000000000000000000000000000000000000000012d687");
+        let quote = block_on(quote(
+            "mainnet",
+            None,
+            Address::repeat_byte(2),
+            vec![],
+            U256::zero(),
+            QuoteTerms::default(),
+        ))
+        .unwrap();
+        assert_eq!(quote.l1_data_fee_wei, Some("1234567".to_string()));
+        assert_eq!(quote.max_total_cost_wei, "25200001234567");
+        assert_eq!(quote.balance_sufficient, None);
+    }
+
+    #[test]
+    fn refuses_gas_price_beyond_tolerance() {
+        clock::set_now(1_000);
+        let quote = quote_transfer(U256::zero(), QuoteTerms::default());
+        assert_eq!(check_quote(quote.id, U256::from(GWEI)), Ok(()));
+        assert_eq!(check_quote(quote.id, U256::from(GWEI * 11 / 10)), Ok(()));
+        assert_eq!(
+            check_quote(quote.id, U256::from(GWEI * 11 / 10 + 1)),
+            Err(EthRpcError::QuoteRejected(format!(
+                "Gas price rose from 1000000000 to 1100000001 wei since quote {}",
+                quote.id
+            )))
+        );
+        // A lower gas price is always accepted
+        assert_eq!(check_quote(quote.id, U256::one()), Ok(()));
+        assert!(check_quote(quote.id + 1, U256::one()).is_err());
+    }
+
+    #[test]
+    fn applies_quote_terms() {
+        clock::set_now(1_000);
+        let strict = quote_transfer(
+            U256::zero(),
+            QuoteTerms {
+                validity_ns: Some(5_000),
+                fee_tolerance_percent: Some(0),
+            },
+        );
+        let lenient = quote_transfer(
+            U256::zero(),
+            QuoteTerms {
+                validity_ns: None,
+                fee_tolerance_percent: Some(50),
+            },
+        );
+        assert_eq!(strict.valid_until, 6_000);
+        assert!(check_quote(strict.id, U256::from(GWEI + 1)).is_err());
+        assert_eq!(check_quote(lenient.id, U256::from(GWEI * 3 / 2)), Ok(()));
+
+        clock::set_now(6_000);
+        assert_eq!(check_quote(strict.id, U256::from(GWEI)), Ok(()));
+        clock::set_now(6_001);
+        assert_eq!(
+            check_quote(strict.id, U256::from(GWEI)),
+            Err(EthRpcError::QuoteRejected(format!(
+                "Quote {} has expired",
+                strict.id
+            )))
+        );
+        assert_eq!(check_quote(lenient.id, U256::from(GWEI)), Ok(()));
+    }
+}
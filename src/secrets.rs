@@ -0,0 +1,118 @@
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+
+use crate::providers::secret_referents;
+
+// Placeholder syntax used to reference a secret from a provider URL
+const PLACEHOLDER_PREFIX: &str = "{{secret:";
+const PLACEHOLDER_SUFFIX: &str = "}}";
+
+// Secret values by name (e.g. provider API keys)
+thread_local! {
+    static SECRETS: RefCell<BTreeMap<String, String>> = RefCell::default();
+}
+
+fn placeholder(name: &str) -> String {
+    format!("{}{}{}", PLACEHOLDER_PREFIX, name, PLACEHOLDER_SUFFIX)
+}
+
+fn validate_secret(name: &str, value: &str) -> Result<(), String> {
+    if name.is_empty() || name.contains(PLACEHOLDER_SUFFIX) {
+        return Err(format!("Invalid secret name: {:?}", name));
+    }
+    if value.is_empty() {
+        return Err("Secret value must not be empty".to_string());
+    }
+    Ok(())
+}
+
+/// Store a secret, replacing any existing value
+pub fn set_secret(name: String, value: String) -> Result<(), String> {
+    validate_secret(&name, &value)?;
+    SECRETS.with(|secrets| secrets.borrow_mut().insert(name, value));
+    Ok(())
+}
+
+/// Replace the value of an existing secret
+pub fn rotate_secret(name: String, new_value: String) -> Result<(), String> {
+    validate_secret(&name, &new_value)?;
+    SECRETS.with(|secrets| match secrets.borrow_mut().get_mut(&name) {
+        Some(value) => {
+            *value = new_value;
+            Ok(())
+        }
+        None => Err(format!("Secret not found: {}", name)),
+    })
+}
+
+/// Delete a secret which is no longer referenced by any provider
+pub fn delete_secret(name: &str) -> Result<(), String> {
+    let referents = secret_referents(name);
+    if !referents.is_empty() {
+        return Err(format!(
+            "Secret {} is still referenced by: {}",
+            name,
+            referents.join(", ")
+        ));
+    }
+    SECRETS
+        .with(|secrets| secrets.borrow_mut().remove(name))
+        .map(|_| ())
+        .ok_or_else(|| format!("Secret not found: {}", name))
+}
+
+/// Find the names of all secrets referenced by a template
+pub fn referenced_secrets(template: &str) -> Vec<String> {
+    let mut names = vec![];
+    let mut rest = template;
+    while let Some(start) = rest.find(PLACEHOLDER_PREFIX) {
+        rest = &rest[start + PLACEHOLDER_PREFIX.len()..];
+        match rest.find(PLACEHOLDER_SUFFIX) {
+            Some(end) => {
+                names.push(rest[..end].to_string());
+                rest = &rest[end + PLACEHOLDER_SUFFIX.len()..];
+            }
+            None => break,
+        }
+    }
+    names
+}
+
+/// Replace each `{{secret:NAME}}` placeholder with the secret's value.
+/// Only call this when building a request; never return the result to callers.
+pub fn expand_secrets(template: &str) -> Result<String, String> {
+    SECRETS.with(|secrets| {
+        let secrets = secrets.borrow();
+        referenced_secrets(template)
+            .into_iter()
+            .try_fold(template.to_string(), |expanded, name| {
+                let value = secrets
+                    .get(&name)
+                    .ok_or_else(|| format!("Secret not found: {}", name))?;
+                Ok(expanded.replace(&placeholder(&name), value))
+            })
+    })
+}
+
+/// Replace any secret values occurring in the text with their placeholders
+pub fn redact(text: &str) -> String {
+    SECRETS.with(|secrets| {
+        let secrets = secrets.borrow();
+        let mut entries = secrets.iter().collect::<Vec<_>>();
+        // Redact longer values first in case one secret contains another
+        entries.sort_by_key(|(_, value)| std::cmp::Reverse(value.len()));
+        entries
+            .into_iter()
+            .fold(text.to_string(), |text, (name, value)| {
+                text.replace(value.as_str(), &placeholder(name))
+            })
+    })
+}
+
+pub fn snapshot() -> BTreeMap<String, String> {
+    SECRETS.with(|secrets| secrets.borrow().clone())
+}
+
+pub fn restore(state: BTreeMap<String, String>) {
+    SECRETS.with(|secrets| *secrets.borrow_mut() = state);
+}
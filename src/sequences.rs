@@ -0,0 +1,469 @@
+use candid::CandidType;
+use ethers_core::types::{Address, NameOrAddress, TransactionRequest, H256, U256};
+use serde::Deserialize;
+use std::cell::{Cell, RefCell};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+use crate::accounts;
+use crate::eth_rpc::{self, BlockTag, CallOptions, TxStatus};
+use crate::limiter::Priority;
+use crate::quotes::check_quote;
+use crate::transactions::{fill_transaction, sign_transaction, TxType};
+use crate::util::to_hex;
+
+// Interval between polls for the receipts of pending sequence steps
+const RECEIPT_POLL_INTERVAL: Duration = Duration::from_secs(15);
+
+/// What to do with the remaining steps of a sequence when a step's transaction reverts
+#[derive(Clone, Copy, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum SequencePolicy {
+    AbortOnFailure,
+    ContinueOnFailure,
+}
+
+/// State of a single step of a sequence
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum StepState {
+    /// Waiting for the previous step
+    Queued,
+    /// Being simulated, signed and broadcast
+    Broadcasting,
+    /// Broadcast, waiting to be mined
+    Pending,
+    Succeeded,
+    /// Could not be broadcast, or reverted when mined
+    Failed(String),
+    /// Not broadcast because an earlier step failed
+    Skipped,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct Step {
+    // JSON-encoded `TransactionRequest`
+    tx: String,
+    nonce: u64,
+    tx_hash: Option<String>,
+    state: StepState,
+}
+
+#[derive(Clone, Debug, CandidType, Deserialize)]
+struct Sequence {
+    account: String,
+    policy: SequencePolicy,
+    steps: Vec<Step>,
+}
+
+/// A sequence step's nonce, transaction hash (once broadcast) and state
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct StepStatus {
+    pub nonce: u64,
+    pub tx_hash: Option<String>,
+    pub state: StepState,
+}
+
+/// Status of a sequence of transactions
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SequenceStatus {
+    pub id: u64,
+    pub account: String,
+    pub policy: SequencePolicy,
+    pub steps: Vec<StepStatus>,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct SequencesState {
+    next_id: u64,
+    sequences: BTreeMap<u64, Sequence>,
+}
+
+thread_local! {
+    static SEQUENCES: RefCell<SequencesState> = RefCell::default();
+    // Set while the receipts of pending steps are being polled
+    static POLLING: Cell<bool> = Cell::default();
+}
+
+// Clears the polling flag when a poll ends (including when it traps)
+struct PollGuard;
+
+impl Drop for PollGuard {
+    fn drop(&mut self) {
+        POLLING.with(|polling| polling.set(false));
+    }
+}
+
+fn with_step<R>(id: u64, index: usize, f: impl FnOnce(&mut Step) -> R) -> Option<R> {
+    SEQUENCES.with(|sequences| {
+        sequences
+            .borrow_mut()
+            .sequences
+            .get_mut(&id)
+            .and_then(|sequence| sequence.steps.get_mut(index))
+            .map(f)
+    })
+}
+
+fn sequence(id: u64) -> Option<Sequence> {
+    SEQUENCES.with(|sequences| sequences.borrow().sequences.get(&id).cloned())
+}
+
+/// Submit an ordered series of dependent transactions from an account, returning
+/// the sequence's operation id.
+///
+/// Consecutive nonces are reserved for the steps. The first step is broadcast
+/// immediately, and each following step once the previous one is mined (and,
+/// with `AbortOnFailure`, only if it succeeded). Each step is simulated again just
+/// before it is broadcast, since earlier steps change state.
+///
+/// If a quote id is given, the sequence is refused when fees have moved beyond
+/// the quote's tolerance.
+pub async fn submit_sequence(
+    account: String,
+    steps: Vec<TransactionRequest>,
+    policy: SequencePolicy,
+    quote_id: Option<u64>,
+) -> Result<u64, String> {
+    if steps.is_empty() {
+        return Err("Sequence has no steps".to_string());
+    }
+    if let Some(quote_id) = quote_id {
+        let network = accounts::account(&account)?.network;
+        let gas_price = eth_rpc::gas_price(&network)
+            .await
+            .map_err(|err| err.to_string())?;
+        check_quote(quote_id, gas_price).map_err(|err| err.to_string())?;
+    }
+    let first_nonce = accounts::reserve_nonces(&account, steps.len() as u64).await?;
+    let steps = steps
+        .iter()
+        .zip(first_nonce..)
+        .map(|(tx, nonce)| Step {
+            tx: serde_json::to_string(tx).expect("Error encoding transaction"),
+            nonce,
+            tx_hash: None,
+            state: StepState::Queued,
+        })
+        .collect();
+    let id = SEQUENCES.with(|sequences| {
+        let mut sequences = sequences.borrow_mut();
+        let id = sequences.next_id;
+        sequences.next_id += 1;
+        sequences.sequences.insert(
+            id,
+            Sequence {
+                account,
+                policy,
+                steps,
+            },
+        );
+        id
+    });
+    broadcast_step(id, 0).await;
+    Ok(id)
+}
+
+/// Send a single transaction from an account as a sequence of one step, returning
+/// its operation id
+pub async fn submit_transaction(account: &str, tx: TransactionRequest) -> Result<u64, String> {
+    submit_sequence(
+        account.to_string(),
+        vec![tx],
+        SequencePolicy::AbortOnFailure,
+        None,
+    )
+    .await
+}
+
+// Mark a queued step as being broadcast, returning its sequence. Steps in any other
+// state are already being (or were) broadcast, or must not be broadcast at all.
+fn begin_broadcast(id: u64, index: usize) -> Option<Sequence> {
+    let queued = with_step(id, index, |step| {
+        let queued = step.state == StepState::Queued;
+        if queued {
+            step.state = StepState::Broadcasting;
+        }
+        queued
+    })?;
+    queued.then(|| sequence(id)).flatten()
+}
+
+// Simulate, sign and broadcast a queued step of a sequence
+async fn broadcast_step(id: u64, index: usize) {
+    let Some(sequence) = begin_broadcast(id, index) else {
+        return;
+    };
+    let step = &sequence.steps[index];
+    let result = async {
+        let account = accounts::account(&sequence.account)?;
+        let tx = serde_json::from_str::<TransactionRequest>(&step.tx)
+            .map_err(|err| format!("Invalid transaction: {}", err))?;
+        // Earlier steps change state, so every step is simulated again from the account
+        let options = CallOptions {
+            block: BlockTag::Pending,
+            from_derivation_path: Some(account.derivation_path.clone()),
+            ..Default::default()
+        };
+        match &tx.to {
+            Some(NameOrAddress::Address(to)) => {
+                let data = tx.data.clone().unwrap_or_default();
+                let value = tx.value.unwrap_or_else(U256::zero);
+                eth_rpc::simulate_call(&account.network, *to, &data, value, &options)
+                    .await
+                    .map_err(|err| err.to_string())?;
+            }
+            Some(NameOrAddress::Name(name)) => {
+                return Err(format!("Unresolved recipient name: {}", name));
+            }
+            // Contract creations can't be simulated with a call, but estimating gas executes them
+            None => {
+                let from: Address = account.address.parse().map_err(|_| "Invalid address")?;
+                let tx = tx.clone().from(from).nonce(step.nonce);
+                eth_rpc::estimate_gas(&account.network, &tx, BlockTag::Pending)
+                    .await
+                    .map_err(|err| err.to_string())?;
+            }
+        }
+        let tx = fill_transaction(&account, tx, step.nonce, TxType::Auto)
+            .await
+            .map_err(|err| err.to_string())?;
+        let raw = sign_transaction(&account, tx)
+            .await
+            .map_err(|err| err.to_string())?;
+        eth_rpc::send_raw_transaction(&account.network, &raw)
+            .await
+            .map_err(|err| err.to_string())
+    }
+    .await;
+    match result {
+        Ok(tx_hash) => {
+            let tx_hash = to_hex(tx_hash.as_bytes());
+            accounts::add_pending_transaction(&sequence.account, tx_hash.clone());
+            with_step(id, index, |step| {
+                step.tx_hash = Some(tx_hash);
+                step.state = StepState::Pending;
+            });
+        }
+        Err(err) => {
+            with_step(id, index, |step| step.state = StepState::Failed(err));
+            // The step's nonce is unused, so later steps could never be mined
+            abort(id, index + 1, step.nonce);
+        }
+    }
+}
+
+// Skip the steps of a sequence from an index on, releasing the reserved nonces
+// from the first one which won't be used
+fn abort(id: u64, from: usize, first_unused_nonce: u64) {
+    let Some(sequence) = sequence(id) else {
+        return;
+    };
+    for index in from..sequence.steps.len() {
+        with_step(id, index, |step| step.state = StepState::Skipped);
+    }
+    let end = sequence.steps.last().map_or(0, |step| step.nonce + 1);
+    accounts::release_nonces(&sequence.account, first_unused_nonce, end);
+}
+
+// Record that a pending step was mined, returning the index of the step to broadcast
+// next. The rest of the sequence is aborted if it must not continue.
+fn record_outcome(id: u64, index: usize, succeeded: bool) -> Option<usize> {
+    let sequence = sequence(id)?;
+    let step = sequence.steps.get(index)?;
+    if step.state != StepState::Pending {
+        return None;
+    }
+    with_step(id, index, |step| {
+        step.state = if succeeded {
+            StepState::Succeeded
+        } else {
+            StepState::Failed("Transaction reverted".to_string())
+        }
+    });
+    let next = sequence.steps.get(index + 1)?;
+    if succeeded || sequence.policy == SequencePolicy::ContinueOnFailure {
+        Some(index + 1)
+    } else {
+        abort(id, index + 1, next.nonce);
+        None
+    }
+}
+
+/// Check the receipts of pending sequence steps, and advance their sequences.
+/// A poll is skipped while an earlier one is still in progress.
+pub async fn poll_sequences() {
+    if POLLING.with(|polling| polling.replace(true)) {
+        return;
+    }
+    let _guard = PollGuard;
+    let pending = SEQUENCES.with(|sequences| {
+        sequences
+            .borrow()
+            .sequences
+            .iter()
+            .flat_map(|(id, sequence)| {
+                sequence
+                    .steps
+                    .iter()
+                    .enumerate()
+                    .filter(|(_, step)| step.state == StepState::Pending)
+                    .filter_map(move |(index, step)| {
+                        let tx_hash = step.tx_hash.clone()?;
+                        Some((*id, index, tx_hash, sequence.account.clone()))
+                    })
+            })
+            .collect::<Vec<_>>()
+    });
+    let options = CallOptions {
+        priority: Priority::Background,
+        ..Default::default()
+    };
+    for (id, index, tx_hash, account) in pending {
+        let Ok(network) = accounts::account(&account).map(|account| account.network) else {
+            continue;
+        };
+        let Ok(hash) = tx_hash.parse::<H256>() else {
+            continue;
+        };
+        let status = match eth_rpc::transaction_status(&network, hash, &options).await {
+            Ok(TxStatus::Pending) | Err(_) => continue,
+            Ok(status) => status,
+        };
+        accounts::remove_pending_transaction(&account, &tx_hash);
+        if let Some(next) = record_outcome(id, index, status == TxStatus::Success) {
+            broadcast_step(id, next).await;
+        }
+    }
+}
+
+/// Start the timer which periodically polls the receipts of pending sequence steps
+pub fn start_receipt_timer() {
+    ic_cdk_timers::set_timer_interval(RECEIPT_POLL_INTERVAL, || ic_cdk::spawn(poll_sequences()));
+}
+
+/// Status of a sequence by operation id
+pub fn sequence_status(id: u64) -> Option<SequenceStatus> {
+    sequence(id).map(|sequence| SequenceStatus {
+        id,
+        account: sequence.account,
+        policy: sequence.policy,
+        steps: sequence
+            .steps
+            .into_iter()
+            .map(|step| StepStatus {
+                nonce: step.nonce,
+                tx_hash: step.tx_hash,
+                state: step.state,
+            })
+            .collect(),
+    })
+}
+
+pub fn snapshot() -> SequencesState {
+    SEQUENCES.with(|sequences| sequences.borrow().clone())
+}
+
+pub fn restore(state: SequencesState) {
+    SEQUENCES.with(|sequences| *sequences.borrow_mut() = state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::accounts::EthAccount;
+
+    // A sequence of three steps from an account which reserved their nonces 5..8,
+    // with the first step broadcast
+    fn three_step_sequence(policy: SequencePolicy) -> u64 {
+        let account = EthAccount {
+            network: "mainnet".to_string(),
+            chain_id: 1,
+            derivation_path: vec![],
+            address: "0x2c7536E3605D9C16a7a3D7b1898e529396a65c23".to_string(),
+            nonce: Some(8),
+            balance_wei: None,
+            balance_updated_at: None,
+            pending_transactions: vec![],
+        };
+        accounts::restore([("alice".to_string(), account)].into_iter().collect());
+        let steps = (5..8)
+            .map(|nonce| Step {
+                tx: serde_json::to_string(&TransactionRequest::new()).unwrap(),
+                nonce,
+                tx_hash: None,
+                state: StepState::Queued,
+            })
+            .collect();
+        let mut state = SequencesState::default();
+        state.sequences.insert(
+            0,
+            Sequence {
+                account: "alice".to_string(),
+                policy,
+                steps,
+            },
+        );
+        restore(state);
+        broadcast(0, 0);
+        0
+    }
+
+    // Stand-in for a successful `broadcast_step`, which needs the threshold ECDSA key
+    fn broadcast(id: u64, index: usize) {
+        assert!(begin_broadcast(id, index).is_some());
+        with_step(id, index, |step| {
+            step.tx_hash = Some(format!("0x{:064x}", index));
+            step.state = StepState::Pending;
+        });
+    }
+
+    fn states(id: u64) -> Vec<StepState> {
+        sequence_status(id)
+            .unwrap()
+            .steps
+            .into_iter()
+            .map(|step| step.state)
+            .collect()
+    }
+
+    #[test]
+    fn reverted_step_aborts_the_rest_of_the_sequence() {
+        let id = three_step_sequence(SequencePolicy::AbortOnFailure);
+        assert_eq!(record_outcome(id, 0, true), Some(1));
+        broadcast(id, 1);
+        assert_eq!(record_outcome(id, 1, false), None);
+        assert_eq!(
+            states(id),
+            vec![
+                StepState::Succeeded,
+                StepState::Failed("Transaction reverted".to_string()),
+                StepState::Skipped,
+            ]
+        );
+        // Step three is never broadcast, and its nonce is released
+        assert!(begin_broadcast(id, 2).is_none());
+        assert_eq!(sequence_status(id).unwrap().steps[2].tx_hash, None);
+        assert_eq!(accounts::account("alice").unwrap().nonce, Some(7));
+    }
+
+    #[test]
+    fn reverted_step_continues_with_continue_on_failure() {
+        let id = three_step_sequence(SequencePolicy::ContinueOnFailure);
+        assert_eq!(record_outcome(id, 0, true), Some(1));
+        broadcast(id, 1);
+        assert_eq!(record_outcome(id, 1, false), Some(2));
+        assert_eq!(states(id)[2], StepState::Queued);
+        assert_eq!(accounts::account("alice").unwrap().nonce, Some(8));
+    }
+
+    #[test]
+    fn step_is_advanced_and_broadcast_only_once() {
+        let id = three_step_sequence(SequencePolicy::AbortOnFailure);
+        assert_eq!(record_outcome(id, 0, true), Some(1));
+        // An overlapping poll sees the step as already mined
+        assert_eq!(record_outcome(id, 0, true), None);
+        assert!(begin_broadcast(id, 1).is_some());
+        // An overlapping broadcast finds the step no longer queued
+        assert!(begin_broadcast(id, 1).is_none());
+        assert_eq!(states(id)[1], StepState::Broadcasting);
+    }
+}
@@ -0,0 +1,464 @@
+use candid::{CandidType, Principal};
+use ethers_core::types::transaction::eip712::{Eip712, TypedData};
+use serde::Deserialize;
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+
+use crate::accounts;
+use crate::clock;
+use crate::ecdsa::sign_digest;
+use crate::util::{hash_personal_message, to_hex};
+
+// Maximum number of entries kept in the signature log
+const SIGNATURE_LOG_SIZE: usize = 1000;
+
+/// Constraints on the EIP-712 domain of typed data signed in a signing domain.
+/// Unset fields are unconstrained.
+#[derive(Clone, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub struct Eip712Constraints {
+    pub name: Option<String>,
+    pub version: Option<String>,
+    pub chain_id: Option<u64>,
+    pub verifying_contract: Option<String>,
+}
+
+/// A purpose for which the canister signs payloads on behalf of callers
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub struct SigningDomain {
+    pub name: String,
+    pub allowed_callers: Vec<Principal>,
+    /// Labels of the accounts whose keys may sign in the domain
+    pub allowed_accounts: Vec<String>,
+    /// Prefix required of EIP-191 messages
+    pub required_prefix: Option<String>,
+    /// Constraints on the domain of EIP-712 typed data
+    pub eip712: Option<Eip712Constraints>,
+    /// Set the `nonce` field of EIP-712 messages to a canister-generated nonce
+    pub embed_nonce: bool,
+}
+
+/// An issued signature, as recorded in the signature log
+#[derive(Clone, Debug, CandidType, Deserialize)]
+pub struct SignatureRecord {
+    pub domain: String,
+    pub caller: Principal,
+    pub digest: String,
+    pub timestamp: u64,
+}
+
+#[derive(Clone, Debug, Default, CandidType, Deserialize)]
+pub struct SigningState {
+    domains: BTreeMap<String, SigningDomain>,
+    nonces: BTreeMap<String, u64>,
+    log: VecDeque<SignatureRecord>,
+}
+
+thread_local! {
+    static SIGNING: RefCell<SigningState> = RefCell::default();
+}
+
+/// Add or replace a signing domain
+pub fn set_signing_domain(domain: SigningDomain) {
+    SIGNING.with(|signing| {
+        signing
+            .borrow_mut()
+            .domains
+            .insert(domain.name.clone(), domain)
+    });
+}
+
+/// Remove a signing domain
+pub fn remove_signing_domain(name: &str) -> Result<(), String> {
+    SIGNING
+        .with(|signing| signing.borrow_mut().domains.remove(name))
+        .map(|_| ())
+        .ok_or_else(|| format!("Signing domain not found: {}", name))
+}
+
+/// List the signing domains
+pub fn signing_domains() -> Vec<SigningDomain> {
+    SIGNING.with(|signing| signing.borrow().domains.values().cloned().collect())
+}
+
+/// Nonce which will be embedded into the next EIP-712 message signed in a domain
+pub fn next_nonce(domain: &str) -> u64 {
+    SIGNING.with(|signing| {
+        signing
+            .borrow()
+            .nonces
+            .get(domain)
+            .copied()
+            .unwrap_or_default()
+    })
+}
+
+/// Signatures issued in a domain (or in every domain), oldest first
+pub fn signature_log(domain: Option<String>) -> Vec<SignatureRecord> {
+    SIGNING.with(|signing| {
+        signing
+            .borrow()
+            .log
+            .iter()
+            .filter(|record| {
+                domain
+                    .as_ref()
+                    .is_none_or(|domain| &record.domain == domain)
+            })
+            .cloned()
+            .collect()
+    })
+}
+
+// Look up a domain which the caller may sign in with an account's key
+fn authorized_domain(
+    name: &str,
+    caller: Principal,
+    account_label: &str,
+) -> Result<SigningDomain, String> {
+    let domain = SIGNING
+        .with(|signing| signing.borrow().domains.get(name).cloned())
+        .ok_or_else(|| format!("Signing domain not found: {}", name))?;
+    if !domain.allowed_callers.contains(&caller) {
+        return Err(format!("Caller may not sign in domain {}", name));
+    }
+    if !domain
+        .allowed_accounts
+        .iter()
+        .any(|account| account == account_label)
+    {
+        return Err(format!(
+            "Account {} may not sign in domain {}",
+            account_label, name
+        ));
+    }
+    Ok(domain)
+}
+
+// Consume a nonce embedded into a signed message, unless another message was
+// signed with it in the meantime
+fn consume_nonce(domain: &str, nonce: u64) -> Result<(), String> {
+    SIGNING.with(|signing| {
+        let mut signing = signing.borrow_mut();
+        let next = signing.nonces.entry(domain.to_string()).or_default();
+        if *next != nonce {
+            return Err(format!(
+                "Nonce {} was used concurrently in domain {}",
+                nonce, domain
+            ));
+        }
+        *next += 1;
+        Ok(())
+    })
+}
+
+// Record an issued signature in the signature log
+fn record_signature(domain: &str, caller: Principal, digest: [u8; 32]) {
+    SIGNING.with(|signing| {
+        let log = &mut signing.borrow_mut().log;
+        log.push_back(SignatureRecord {
+            domain: domain.to_string(),
+            caller,
+            digest: to_hex(&digest),
+            timestamp: clock::now(),
+        });
+        if log.len() > SIGNATURE_LOG_SIZE {
+            log.pop_front();
+        }
+    });
+}
+
+// Sign a digest with an account's key and record the signature. A nonce embedded
+// into the signed message is consumed only once the signature is made.
+async fn sign_and_record(
+    domain: &str,
+    caller: Principal,
+    account_label: &str,
+    digest: [u8; 32],
+    nonce: Option<u64>,
+) -> Result<String, String> {
+    let account = accounts::account(account_label)?;
+    let signature = sign_digest(account.derivation_path, digest)
+        .await
+        .map_err(|err| err.to_string())?;
+    if let Some(nonce) = nonce {
+        consume_nonce(domain, nonce)?;
+    }
+    record_signature(domain, caller, digest);
+    Ok(to_hex(&signature.to_vec()))
+}
+
+// Digest of an EIP-191 personal message which the caller may sign in a domain
+fn message_digest(
+    domain: &str,
+    caller: Principal,
+    account_label: &str,
+    message: &str,
+) -> Result<[u8; 32], String> {
+    let signing_domain = authorized_domain(domain, caller, account_label)?;
+    if let Some(prefix) = &signing_domain.required_prefix {
+        if !message.starts_with(prefix.as_str()) {
+            return Err(format!("Message must start with {:?}", prefix));
+        }
+    }
+    Ok(hash_personal_message(message.as_bytes()))
+}
+
+/// Sign an EIP-191 personal message with an account's key, within a signing domain
+pub async fn sign_message(
+    domain: &str,
+    account_label: &str,
+    message: String,
+) -> Result<String, String> {
+    let caller = ic_cdk::caller();
+    let digest = message_digest(domain, caller, account_label, &message)?;
+    sign_and_record(domain, caller, account_label, digest, None).await
+}
+
+fn check_eip712_domain(
+    typed_data: &TypedData,
+    constraints: &Eip712Constraints,
+) -> Result<(), String> {
+    let domain = &typed_data.domain;
+    if constraints.name.is_some() && constraints.name != domain.name {
+        return Err("EIP-712 domain name is not allowed".to_string());
+    }
+    if constraints.version.is_some() && constraints.version != domain.version {
+        return Err("EIP-712 domain version is not allowed".to_string());
+    }
+    if let Some(chain_id) = constraints.chain_id {
+        if domain.chain_id != Some(chain_id.into()) {
+            return Err("EIP-712 domain chain id is not allowed".to_string());
+        }
+    }
+    if let Some(contract) = &constraints.verifying_contract {
+        let contract = contract.parse().map_err(|_| "Invalid verifying contract")?;
+        if domain.verifying_contract != Some(contract) {
+            return Err("EIP-712 verifying contract is not allowed".to_string());
+        }
+    }
+    Ok(())
+}
+
+// Digest of EIP-712 typed data (given as JSON) which the caller may sign in a
+// domain, embedding the domain's next nonce if required. The embedded nonce is
+// returned, to be consumed once the digest is signed.
+fn typed_data_digest(
+    domain: &str,
+    caller: Principal,
+    account_label: &str,
+    typed_data_json: &str,
+) -> Result<([u8; 32], Option<u64>), String> {
+    let signing_domain = authorized_domain(domain, caller, account_label)?;
+    let mut typed_data = serde_json::from_str::<TypedData>(typed_data_json)
+        .map_err(|err| format!("Invalid typed data: {}", err))?;
+    if let Some(constraints) = &signing_domain.eip712 {
+        check_eip712_domain(&typed_data, constraints)?;
+    }
+    let declares_nonce = typed_data
+        .types
+        .get(&typed_data.primary_type)
+        .is_some_and(|fields| fields.iter().any(|field| field.name == "nonce"));
+    let nonce = (signing_domain.embed_nonce && declares_nonce).then(|| next_nonce(domain));
+    if let Some(nonce) = nonce {
+        typed_data
+            .message
+            .insert("nonce".to_string(), serde_json::json!(nonce));
+    }
+    let digest = typed_data
+        .encode_eip712()
+        .map_err(|err| format!("Invalid typed data: {}", err))?;
+    Ok((digest, nonce))
+}
+
+/// Sign EIP-712 typed data (given as JSON) with an account's key, within a signing domain
+pub async fn sign_typed_data(
+    domain: &str,
+    account_label: &str,
+    typed_data_json: &str,
+) -> Result<String, String> {
+    let caller = ic_cdk::caller();
+    let (digest, nonce) = typed_data_digest(domain, caller, account_label, typed_data_json)?;
+    sign_and_record(domain, caller, account_label, digest, nonce).await
+}
+
+pub fn snapshot() -> SigningState {
+    SIGNING.with(|signing| signing.borrow().clone())
+}
+
+pub fn restore(state: SigningState) {
+    SIGNING.with(|signing| *signing.borrow_mut() = state);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // The example from EIP-712, whose digest is given by the EIP
+    const MAIL: &str = r#"{
+        "types": {
+            "EIP712Domain": [
+                {"name": "name", "type": "string"},
+                {"name": "version", "type": "string"},
+                {"name": "chainId", "type": "uint256"},
+                {"name": "verifyingContract", "type": "address"}
+            ],
+            "Person": [
+                {"name": "name", "type": "string"},
+                {"name": "wallet", "type": "address"}
+            ],
+            "Mail": [
+                {"name": "from", "type": "Person"},
+                {"name": "to", "type": "Person"},
+                {"name": "contents", "type": "string"}
+            ]
+        },
+        "primaryType": "Mail",
+        "domain": {
+            "name": "Ether Mail",
+            "version": "1",
+            "chainId": 1,
+            "verifyingContract": "0xCcCCccccCCCCcCCCCCCcCcCccCcCCCcCcccccccC"
+        },
+        "message": {
+            "from": {"name": "Cow", "wallet": "0xCD2a3d9F938E13CD947Ec05AbC7FE734Df8DD826"},
+            "to": {"name": "Bob", "wallet": "0xbBbBBBBbbBBBbbbBbbBbbbbBBbBbbbbBbBbbBBbB"},
+            "contents": "Hello, Bob!"
+        }
+    }"#;
+    const MAIL_DIGEST: &str = "0xbe609aee343fb3c4b28e1df9e632fca64fcfaede20f02e86244efddf30957bd2";
+
+    fn alice() -> Principal {
+        Principal::from_slice(&[1])
+    }
+
+    fn set_domain(required_prefix: Option<&str>, eip712: Option<Eip712Constraints>) {
+        set_signing_domain(SigningDomain {
+            name: "login".to_string(),
+            allowed_callers: vec![alice()],
+            allowed_accounts: vec!["main".to_string()],
+            required_prefix: required_prefix.map(str::to_string),
+            eip712,
+            embed_nonce: false,
+        });
+    }
+
+    #[test]
+    fn refuses_callers_outside_domain() {
+        set_domain(None, None);
+        let bob = Principal::from_slice(&[2]);
+        assert_eq!(
+            message_digest("login", bob, "main", "hello world"),
+            Err("Caller may not sign in domain login".to_string())
+        );
+        assert_eq!(
+            typed_data_digest("login", bob, "main", MAIL),
+            Err("Caller may not sign in domain login".to_string())
+        );
+        assert_eq!(
+            message_digest("other", alice(), "main", "hello world"),
+            Err("Signing domain not found: other".to_string())
+        );
+        assert_eq!(next_nonce("login"), 0);
+    }
+
+    #[test]
+    fn refuses_accounts_outside_domain() {
+        set_domain(None, None);
+        assert_eq!(
+            message_digest("login", alice(), "treasury", "hello world"),
+            Err("Account treasury may not sign in domain login".to_string())
+        );
+        assert_eq!(
+            typed_data_digest("login", alice(), "treasury", MAIL),
+            Err("Account treasury may not sign in domain login".to_string())
+        );
+    }
+
+    #[test]
+    fn consumes_embedded_nonce_once_signed() {
+        set_signing_domain(SigningDomain {
+            name: "permit".to_string(),
+            allowed_callers: vec![alice()],
+            allowed_accounts: vec!["main".to_string()],
+            required_prefix: None,
+            eip712: None,
+            embed_nonce: true,
+        });
+        let mut mail: serde_json::Value = serde_json::from_str(MAIL).unwrap();
+        mail["types"]["Mail"]
+            .as_array_mut()
+            .unwrap()
+            .push(serde_json::json!({"name": "nonce", "type": "uint256"}));
+        let mail = mail.to_string();
+
+        // Preparing a digest (e.g. before a signature which fails) uses up no nonce
+        let (first, nonce) = typed_data_digest("permit", alice(), "main", &mail).unwrap();
+        assert_eq!(nonce, Some(0));
+        assert_eq!(next_nonce("permit"), 0);
+        let (again, _) = typed_data_digest("permit", alice(), "main", &mail).unwrap();
+        assert_eq!(again, first);
+
+        consume_nonce("permit", 0).unwrap();
+        assert_eq!(next_nonce("permit"), 1);
+        let (second, nonce) = typed_data_digest("permit", alice(), "main", &mail).unwrap();
+        assert_eq!(nonce, Some(1));
+        assert_ne!(second, first);
+        // A signature made concurrently with the same nonce is refused
+        assert_eq!(
+            consume_nonce("permit", 0),
+            Err("Nonce 0 was used concurrently in domain permit".to_string())
+        );
+    }
+
+    #[test]
+    fn enforces_domain_constraints() {
+        set_domain(
+            Some("Sign in:"),
+            Some(Eip712Constraints {
+                chain_id: Some(5),
+                ..Default::default()
+            }),
+        );
+        assert_eq!(
+            message_digest("login", alice(), "main", "hello world"),
+            Err("Message must start with \"Sign in:\"".to_string())
+        );
+        assert!(message_digest("login", alice(), "main", "Sign in: 1234").is_ok());
+        assert_eq!(
+            typed_data_digest("login", alice(), "main", MAIL),
+            Err("EIP-712 domain chain id is not allowed".to_string())
+        );
+    }
+
+    #[test]
+    fn logs_signed_digests() {
+        set_domain(None, None);
+        clock::set_now(42);
+        let message = message_digest("login", alice(), "main", "hello world").unwrap();
+        assert_eq!(
+            to_hex(&message),
+            "0xd9eba16ed0ecae432b71fe008c98cc872bb4cc214d3220a36f365326cf807d68"
+        );
+        record_signature("login", alice(), message);
+        let (typed_data, nonce) = typed_data_digest("login", alice(), "main", MAIL).unwrap();
+        assert_eq!(nonce, None);
+        assert_eq!(to_hex(&typed_data), MAIL_DIGEST);
+        record_signature("login", alice(), typed_data);
+
+        let log = signature_log(Some("login".to_string()));
+        assert_eq!(
+            log.iter()
+                .map(|record| record.digest.as_str())
+                .collect::<Vec<_>>(),
+            vec![
+                "0xd9eba16ed0ecae432b71fe008c98cc872bb4cc214d3220a36f365326cf807d68",
+                MAIL_DIGEST,
+            ]
+        );
+        assert!(log
+            .iter()
+            .all(|record| record.caller == alice() && record.timestamp == 42));
+        assert!(signature_log(Some("other".to_string())).is_empty());
+        assert_eq!(signature_log(None).len(), 2);
+    }
+}
@@ -0,0 +1,69 @@
+use candid::CandidType;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::accounts::{self, EthAccount};
+use crate::audit::{self, AuditState};
+use crate::health::{self, ProviderStats};
+use crate::limiter::{self, LimiterConfig};
+use crate::providers::{ArchiveSupport, NetworkConfig};
+use crate::sequences::{self, SequencesState};
+use crate::signing::{self, SigningState};
+use crate::{providers, secrets};
+
+// State persisted in stable memory across upgrades.
+// Fields added after the first release are optional so older state still decodes.
+#[derive(CandidType, Deserialize)]
+struct StableState {
+    providers: BTreeMap<String, Vec<String>>,
+    secrets: BTreeMap<String, String>,
+    provider_stats: Option<BTreeMap<String, ProviderStats>>,
+    accounts: Option<BTreeMap<String, EthAccount>>,
+    signing: Option<SigningState>,
+    sequences: Option<SequencesState>,
+    archive_support: Option<BTreeMap<String, BTreeMap<String, ArchiveSupport>>>,
+    audit: Option<AuditState>,
+    limiter: Option<LimiterConfig>,
+    provider_configs: Option<BTreeMap<String, Vec<NetworkConfig>>>,
+}
+
+/// Save the canister state to stable memory
+pub fn save() {
+    let state = StableState {
+        providers: providers::snapshot(),
+        secrets: secrets::snapshot(),
+        provider_stats: Some(health::snapshot()),
+        accounts: Some(accounts::snapshot()),
+        signing: Some(signing::snapshot()),
+        sequences: Some(sequences::snapshot()),
+        archive_support: Some(providers::snapshot_archive()),
+        audit: Some(audit::snapshot()),
+        limiter: Some(limiter::config()),
+        provider_configs: Some(providers::snapshot_configs()),
+    };
+    ic_cdk::storage::stable_save((state,)).expect("Error saving state to stable memory");
+}
+
+/// Restore the canister state from stable memory, if any was saved.
+/// Traps if saved state can't be decoded, so the upgrade is rolled back
+/// rather than leaving the canister running without its state.
+pub fn restore() {
+    // Releases before stable state was introduced saved nothing
+    if ic_cdk::api::stable::stable64_size() == 0 {
+        return;
+    }
+    let (state,) = ic_cdk::storage::stable_restore::<(StableState,)>()
+        .expect("Error restoring state from stable memory");
+    match state.provider_configs {
+        Some(configs) => providers::restore_configs(configs),
+        None => providers::restore(state.providers),
+    }
+    secrets::restore(state.secrets);
+    health::restore(state.provider_stats.unwrap_or_default());
+    accounts::restore(state.accounts.unwrap_or_default());
+    providers::restore_archive(state.archive_support.unwrap_or_default());
+    signing::restore(state.signing.unwrap_or_default());
+    sequences::restore(state.sequences.unwrap_or_default());
+    audit::restore(state.audit.unwrap_or_default());
+    limiter::set_config(state.limiter.unwrap_or_default());
+}
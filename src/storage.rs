@@ -0,0 +1,231 @@
+use ethers_core::abi::Token;
+use ethers_core::types::{Address, U256};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::error::EthRpcError;
+use crate::eth_rpc::{get_storage_at, BlockTag};
+
+/// Storage layout of a contract, as produced by `solc --storage-layout`
+#[derive(Clone, Debug, Deserialize)]
+pub struct StorageLayout {
+    pub storage: Vec<StorageVariable>,
+    #[serde(default)]
+    pub types: BTreeMap<String, StorageType>,
+}
+
+/// A state variable in a storage layout
+#[derive(Clone, Debug, Deserialize)]
+pub struct StorageVariable {
+    pub label: String,
+    /// Byte offset of the variable within its slot (for packed variables)
+    pub offset: usize,
+    /// Slot number as a decimal string
+    pub slot: String,
+    #[serde(rename = "type")]
+    pub type_id: String,
+}
+
+/// A type referenced by a storage layout
+#[derive(Clone, Debug, Deserialize)]
+pub struct StorageType {
+    pub encoding: String,
+    pub label: String,
+    #[serde(rename = "numberOfBytes")]
+    pub number_of_bytes: String,
+}
+
+fn layout_error(message: String) -> EthRpcError {
+    EthRpcError::StorageLayout(message)
+}
+
+/// Read a state variable by name, using the contract's storage layout to find its slot
+/// and decode it. Only value types stored in place (including packed ones) are supported.
+pub async fn read_storage_var(
+    network: &str,
+    address: Address,
+    layout: &StorageLayout,
+    var_name: &str,
+    block: BlockTag,
+) -> Result<Token, EthRpcError> {
+    let variable = layout
+        .storage
+        .iter()
+        .find(|variable| variable.label == var_name)
+        .ok_or_else(|| layout_error(format!("Variable not found: {}", var_name)))?;
+    let var_type = layout
+        .types
+        .get(&variable.type_id)
+        .ok_or_else(|| layout_error(format!("Type not found: {}", variable.type_id)))?;
+    let size = inplace_size(variable, var_type)?;
+    let slot = U256::from_dec_str(&variable.slot)
+        .map_err(|_| layout_error(format!("Invalid slot for {}", var_name)))?;
+
+    let word = get_storage_at(network, address, slot, block).await?;
+    decode_packed(&var_type.label, variable.offset, size, word.as_bytes())
+}
+
+// Size in bytes of a variable stored in place in a single slot
+fn inplace_size(variable: &StorageVariable, var_type: &StorageType) -> Result<usize, EthRpcError> {
+    if var_type.encoding != "inplace" {
+        return Err(layout_error(format!(
+            "Unsupported encoding for {}: {}",
+            variable.label, var_type.encoding
+        )));
+    }
+    // Structs and static arrays are in place too, but span whole slots of their own
+    if var_type.label.starts_with("struct ") || var_type.label.ends_with(']') {
+        return Err(layout_error(format!(
+            "Unsupported type for {}: {}",
+            variable.label, var_type.label
+        )));
+    }
+    var_type
+        .number_of_bytes
+        .parse::<usize>()
+        .ok()
+        .filter(|size| *size > 0 && variable.offset + size <= 32)
+        .ok_or_else(|| layout_error(format!("Invalid size for {}", variable.label)))
+}
+
+// Decode a value of `size` bytes packed at byte `offset` of a storage word.
+// Packed variables are stored right-aligned, starting `offset` bytes from the low end.
+fn decode_packed(
+    label: &str,
+    offset: usize,
+    size: usize,
+    word: &[u8],
+) -> Result<Token, EthRpcError> {
+    let end = 32 - offset;
+    decode_value(label, &word[end - size..end])
+}
+
+// Decode the bytes of an in-place value according to its Solidity type label
+fn decode_value(label: &str, bytes: &[u8]) -> Result<Token, EthRpcError> {
+    let value = U256::from_big_endian(bytes);
+    match label {
+        "bool" => Ok(Token::Bool(!value.is_zero())),
+        "address" | "address payable" if bytes.len() == 20 => {
+            Ok(Token::Address(Address::from_slice(bytes)))
+        }
+        _ if label.starts_with("contract ") && bytes.len() == 20 => {
+            Ok(Token::Address(Address::from_slice(bytes)))
+        }
+        _ if label.starts_with("uint") || label.starts_with("enum ") => Ok(Token::Uint(value)),
+        _ if label.starts_with("int") => {
+            // Sign-extend from the variable's width
+            let bits = bytes.len() * 8;
+            let value = if bits < 256 && value.bit(bits - 1) {
+                value | (U256::MAX << bits)
+            } else {
+                value
+            };
+            Ok(Token::Int(value))
+        }
+        _ if label.starts_with("bytes") => Ok(Token::FixedBytes(bytes.to_vec())),
+        _ => Err(layout_error(format!("Unsupported type: {}", label))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::types::H256;
+
+    // `address owner; uint96 nonce;` packed into one slot, and `uint128 a; uint128 b;` in another
+    const OWNER_AND_NONCE: &str =
+        "0x0000000000000000000000072c7536e3605d9c16a7a3d7b1898e529396a65c23";
+    const TWO_UINT128: &str = "0x0000000000000000000000000000000500000000000000000de0b6b3a7640000";
+
+    fn storage_word(hex: &str) -> H256 {
+        hex.parse().unwrap()
+    }
+
+    #[test]
+    fn slices_packed_values_by_offset() {
+        let word = storage_word(OWNER_AND_NONCE);
+        assert_eq!(
+            decode_packed("address", 0, 20, word.as_bytes()).unwrap(),
+            Token::Address(
+                "0x2c7536e3605d9c16a7a3d7b1898e529396a65c23"
+                    .parse()
+                    .unwrap()
+            )
+        );
+        assert_eq!(
+            decode_packed("uint96", 20, 12, word.as_bytes()).unwrap(),
+            Token::Uint(7.into())
+        );
+
+        let word = storage_word(TWO_UINT128);
+        assert_eq!(
+            decode_packed("uint128", 0, 16, word.as_bytes()).unwrap(),
+            Token::Uint(U256::exp10(18))
+        );
+        assert_eq!(
+            decode_packed("uint128", 16, 16, word.as_bytes()).unwrap(),
+            Token::Uint(5.into())
+        );
+    }
+
+    #[test]
+    fn sign_extends_packed_ints() {
+        // `int8 a = -1; int8 b = -128; int8 c = 127;` packed from the low end
+        let word =
+            storage_word("0x00000000000000000000000000000000000000000000000000000000007f80ff");
+        assert_eq!(
+            decode_packed("int8", 0, 1, word.as_bytes()).unwrap(),
+            Token::Int(U256::MAX)
+        );
+        assert_eq!(
+            decode_packed("int8", 1, 1, word.as_bytes()).unwrap(),
+            Token::Int(U256::MAX - 127)
+        );
+        assert_eq!(
+            decode_packed("int8", 2, 1, word.as_bytes()).unwrap(),
+            Token::Int(127.into())
+        );
+    }
+
+    #[test]
+    fn refuses_multi_slot_inplace_types() {
+        let layout: StorageLayout = serde_json::from_value(serde_json::json!({
+            "storage": [
+                {"label": "config", "offset": 0, "slot": "0", "type": "t_struct(Config)12_storage"},
+                {"label": "limits", "offset": 0, "slot": "2", "type": "t_array(t_uint8)3_storage"},
+                {"label": "owner", "offset": 0, "slot": "3", "type": "t_address"},
+            ],
+            "types": {
+                "t_struct(Config)12_storage": {
+                    "encoding": "inplace",
+                    "label": "struct Vault.Config",
+                    "numberOfBytes": "64",
+                },
+                "t_array(t_uint8)3_storage": {
+                    "encoding": "inplace",
+                    "label": "uint8[3]",
+                    "numberOfBytes": "32",
+                },
+                "t_address": {"encoding": "inplace", "label": "address", "numberOfBytes": "20"},
+            },
+        }))
+        .unwrap();
+        let size = |index: usize| {
+            let variable = &layout.storage[index];
+            inplace_size(variable, &layout.types[&variable.type_id])
+        };
+        assert_eq!(
+            size(0),
+            Err(EthRpcError::StorageLayout(
+                "Unsupported type for config: struct Vault.Config".to_string()
+            ))
+        );
+        assert_eq!(
+            size(1),
+            Err(EthRpcError::StorageLayout(
+                "Unsupported type for limits: uint8[3]".to_string()
+            ))
+        );
+        assert_eq!(size(2), Ok(20));
+    }
+}
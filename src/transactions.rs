@@ -0,0 +1,374 @@
+use candid::CandidType;
+use ethers_core::abi::{StateMutability, Token};
+use ethers_core::types::transaction::eip2718::TypedTransaction;
+use ethers_core::types::{
+    Address, Bytes, Eip1559TransactionRequest, Transaction, TransactionRequest, H256, U256,
+};
+use serde::Deserialize;
+
+use crate::abis::registered_abi;
+use crate::accounts::EthAccount;
+use crate::ecdsa::sign_digest;
+use crate::error::EthRpcError;
+use crate::eth_rpc::{self, resolve_function, BlockTag, CallOptions};
+use crate::providers::expected_chain_id;
+
+// Gas used by a plain value transfer
+const TRANSFER_GAS: u64 = 21_000;
+// Smallest fee bump nodes accept for a replacement transaction, in percent
+pub const MIN_REPLACEMENT_BUMP_PERCENT: u8 = 10;
+
+// Encode a call to a function of a registered ABI, refusing value sent to a
+// function which isn't payable
+fn encode_contract_call(
+    abi_name: &str,
+    method: &str,
+    args: &[Token],
+    value: U256,
+) -> Result<Vec<u8>, EthRpcError> {
+    let abi = registered_abi(abi_name)
+        .ok_or_else(|| EthRpcError::InvalidInput(format!("ABI not found: {}", abi_name)))?;
+    let function = resolve_function(&abi.contract, method)?;
+    if !value.is_zero() && function.state_mutability != StateMutability::Payable {
+        return Err(EthRpcError::InvalidInput(format!(
+            "Cannot send value to non-payable function {}",
+            function.name
+        )));
+    }
+    function
+        .encode_input(args)
+        .map_err(|err| EthRpcError::InvalidInput(format!("Invalid arguments: {}", err)))
+}
+
+/// Build an unsigned transaction calling a function of a registered ABI,
+/// optionally sending `value` (in wei) to a payable function.
+///
+/// The call is simulated with `eth_call` (including the value) first, from the
+/// sending account if given, so a transaction which would revert is never prepared.
+pub async fn prepare_contract_tx(
+    network: &str,
+    address: Address,
+    abi_name: &str,
+    method: &str,
+    args: &[Token],
+    value: U256,
+    sender: Option<&EthAccount>,
+) -> Result<TransactionRequest, EthRpcError> {
+    let data = encode_contract_call(abi_name, method, args, value)?;
+
+    let from = sender
+        .map(|account| account.address.parse())
+        .transpose()
+        .map_err(|_| EthRpcError::InvalidInput("Invalid account address".to_string()))?;
+    let options = CallOptions {
+        from,
+        ..CallOptions::default()
+    };
+    eth_rpc::simulate_call(network, address, &data, value, &options).await?;
+
+    let chain_id = match expected_chain_id(network) {
+        Some(chain_id) => chain_id,
+        None => eth_rpc::chain_id(network).await?,
+    };
+    Ok(TransactionRequest::new()
+        .to(address)
+        .data(data)
+        .value(value)
+        .chain_id(chain_id))
+}
+
+/// Type of transaction to sign
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, CandidType, Deserialize)]
+pub enum TxType {
+    /// EIP-1559 if the network supports it, legacy otherwise
+    #[default]
+    Auto,
+    Legacy,
+    Eip1559,
+}
+
+/// Fill in the sender, nonce and chain id of a transaction sent from an account,
+/// and its gas limit and fees unless already set. Estimating the gas limit
+/// simulates the transaction against the latest state.
+///
+/// EIP-1559 transactions pay the network's suggested priority fee on top of up to
+/// twice the latest base fee. A gas price set by the caller is used as both the
+/// maximum fee and the priority fee instead.
+pub async fn fill_transaction(
+    account: &EthAccount,
+    mut tx: TransactionRequest,
+    nonce: u64,
+    tx_type: TxType,
+) -> Result<TypedTransaction, EthRpcError> {
+    let from: Address = account
+        .address
+        .parse()
+        .map_err(|_| EthRpcError::InvalidInput("Invalid account address".to_string()))?;
+    tx = tx.from(from).nonce(nonce).chain_id(account.chain_id);
+    if tx.gas.is_none() {
+        let gas = eth_rpc::estimate_gas(&account.network, &tx, BlockTag::Pending).await?;
+        tx = tx.gas(gas);
+    }
+    let eip1559 = match tx_type {
+        TxType::Auto => eth_rpc::supports_eip1559(&account.network).await?,
+        TxType::Legacy => false,
+        TxType::Eip1559 => true,
+    };
+    if !eip1559 {
+        if tx.gas_price.is_none() {
+            tx = tx.gas_price(eth_rpc::gas_price(&account.network).await?);
+        }
+        return Ok(TypedTransaction::Legacy(tx));
+    }
+
+    let (max_fee, priority_fee) = match tx.gas_price {
+        Some(gas_price) => (gas_price, gas_price),
+        None => {
+            let base_fee = eth_rpc::latest_base_fee(&account.network)
+                .await?
+                .ok_or_else(|| {
+                    EthRpcError::InvalidInput(format!(
+                        "Network {} doesn't support EIP-1559 transactions",
+                        account.network
+                    ))
+                })?;
+            let priority_fee = eth_rpc::max_priority_fee_per_gas(&account.network).await?;
+            (base_fee * 2 + priority_fee, priority_fee)
+        }
+    };
+    Ok(TypedTransaction::Eip1559(Eip1559TransactionRequest {
+        from: tx.from,
+        to: tx.to,
+        gas: tx.gas,
+        value: tx.value,
+        data: tx.data,
+        nonce: tx.nonce,
+        access_list: Default::default(),
+        max_priority_fee_per_gas: Some(priority_fee),
+        max_fee_per_gas: Some(max_fee),
+        chain_id: tx.chain_id,
+    }))
+}
+
+/// Sign a (filled) transaction with an account's key, returning its signed encoding
+pub async fn sign_transaction(
+    account: &EthAccount,
+    tx: TypedTransaction,
+) -> Result<Bytes, EthRpcError> {
+    let mut signature = sign_digest(account.derivation_path.clone(), tx.sighash().0).await?;
+    signature.v = match tx {
+        // EIP-155: v = recovery id + 35 + 2 * chain id
+        TypedTransaction::Legacy(_) => signature.v - 27 + 35 + 2 * account.chain_id,
+        // Typed transactions encode the recovery id itself
+        _ => signature.v - 27,
+    };
+    Ok(tx.rlp_signed(&signature))
+}
+
+// Raise a fee by a percentage, rounding up
+fn bump(fee: U256, bump_percent: u8) -> U256 {
+    (fee * (100 + u64::from(bump_percent)) + 99) / 100
+}
+
+/// Raise the fees of a filled transaction by a percentage, e.g. so that it can
+/// replace a pending transaction with the same nonce (nodes usually require 10%)
+pub fn bump_fees(tx: &mut TypedTransaction, bump_percent: u8) {
+    match tx {
+        TypedTransaction::Eip1559(tx) => {
+            tx.max_fee_per_gas = tx.max_fee_per_gas.map(|fee| bump(fee, bump_percent));
+            tx.max_priority_fee_per_gas = tx
+                .max_priority_fee_per_gas
+                .map(|fee| bump(fee, bump_percent));
+        }
+        TypedTransaction::Legacy(tx) => {
+            tx.gas_price = tx.gas_price.map(|fee| bump(fee, bump_percent));
+        }
+        TypedTransaction::Eip2930(tx) => {
+            tx.tx.gas_price = tx.tx.gas_price.map(|fee| bump(fee, bump_percent));
+        }
+    }
+}
+
+// Raise the fees of a transaction to at least those of the transaction it replaces.
+// A legacy replacement of an EIP-1559 transaction must pay at least its maximum fee.
+fn match_fees(tx: &mut TypedTransaction, replaced: &Transaction) {
+    let max_fee = replaced.max_fee_per_gas.or(replaced.gas_price);
+    let priority_fee = replaced.max_priority_fee_per_gas.or(replaced.gas_price);
+    match tx {
+        TypedTransaction::Eip1559(tx) => {
+            tx.max_fee_per_gas = tx.max_fee_per_gas.max(max_fee);
+            tx.max_priority_fee_per_gas = tx.max_priority_fee_per_gas.max(priority_fee);
+        }
+        TypedTransaction::Legacy(tx) => tx.gas_price = tx.gas_price.max(max_fee),
+        TypedTransaction::Eip2930(tx) => tx.tx.gas_price = tx.tx.gas_price.max(max_fee),
+    }
+}
+
+/// Find the pending transaction of an account with a nonce, among the transactions
+/// the account recorded as pending
+pub async fn find_pending_transaction(
+    account: &EthAccount,
+    nonce: u64,
+) -> Result<Option<Transaction>, EthRpcError> {
+    for tx_hash in &account.pending_transactions {
+        let Ok(tx_hash) = tx_hash.parse() else {
+            continue;
+        };
+        if let Some(tx) = eth_rpc::get_transaction_by_hash(&account.network, tx_hash).await? {
+            if tx.nonce == nonce.into() {
+                return Ok(Some(tx));
+            }
+        }
+    }
+    Ok(None)
+}
+
+/// Cancel a pending transaction of an account by replacing it with a zero-value
+/// transfer to the account itself with the same nonce.
+///
+/// The replacement's fees are the higher of the pending transaction's fees and the
+/// network's current suggestion, raised by `bump_percent` (at least
+/// [`MIN_REPLACEMENT_BUMP_PERCENT`], since nodes drop smaller bumps). If the pending transaction
+/// isn't among the account's recorded pending transactions, its fees are unknown and
+/// only the current suggestion is raised. Returns the hash of the cancelling
+/// transaction; the original is cancelled only if this one is mined first.
+pub async fn cancel_transaction(
+    account: &EthAccount,
+    nonce: u64,
+    bump_percent: u8,
+    tx_type: TxType,
+) -> Result<H256, EthRpcError> {
+    if bump_percent < MIN_REPLACEMENT_BUMP_PERCENT {
+        return Err(EthRpcError::InvalidInput(format!(
+            "Fee bump must be at least {}%",
+            MIN_REPLACEMENT_BUMP_PERCENT
+        )));
+    }
+    let from: Address = account
+        .address
+        .parse()
+        .map_err(|_| EthRpcError::InvalidInput("Invalid account address".to_string()))?;
+    let tx = TransactionRequest::new()
+        .to(from)
+        .value(U256::zero())
+        .gas(TRANSFER_GAS);
+    let mut tx = fill_transaction(account, tx, nonce, tx_type).await?;
+    if let Some(pending) = find_pending_transaction(account, nonce).await? {
+        match_fees(&mut tx, &pending);
+    }
+    bump_fees(&mut tx, bump_percent);
+    let raw = sign_transaction(account, tx).await?;
+    eth_rpc::send_raw_transaction(&account.network, &raw).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending_eip1559(max_fee: u64, priority_fee: u64) -> Transaction {
+        Transaction {
+            transaction_type: Some(2.into()),
+            max_fee_per_gas: Some(max_fee.into()),
+            max_priority_fee_per_gas: Some(priority_fee.into()),
+            ..Default::default()
+        }
+    }
+
+    fn eip1559(max_fee: u64, priority_fee: u64) -> TypedTransaction {
+        TypedTransaction::Eip1559(
+            Eip1559TransactionRequest::new()
+                .max_fee_per_gas(max_fee)
+                .max_priority_fee_per_gas(priority_fee),
+        )
+    }
+
+    fn fees(tx: &TypedTransaction) -> (Option<U256>, Option<U256>) {
+        match tx {
+            TypedTransaction::Eip1559(tx) => (tx.max_fee_per_gas, tx.max_priority_fee_per_gas),
+            tx => (tx.gas_price(), None),
+        }
+    }
+
+    #[test]
+    fn replacement_bumps_pending_fees_when_fees_dropped() {
+        let mut tx = eip1559(50, 1);
+        match_fees(&mut tx, &pending_eip1559(100, 2));
+        bump_fees(&mut tx, 10);
+        assert_eq!(fees(&tx), (Some(110.into()), Some(3.into())));
+    }
+
+    #[test]
+    fn replacement_bumps_current_fees_when_fees_rose() {
+        let mut tx = eip1559(200, 5);
+        match_fees(&mut tx, &pending_eip1559(100, 2));
+        bump_fees(&mut tx, 10);
+        assert_eq!(fees(&tx), (Some(220.into()), Some(6.into())));
+    }
+
+    #[test]
+    fn legacy_replacement_pays_pending_max_fee() {
+        let mut tx = TypedTransaction::Legacy(TransactionRequest::new().gas_price(50));
+        match_fees(&mut tx, &pending_eip1559(100, 2));
+        bump_fees(&mut tx, 10);
+        assert_eq!(fees(&tx), (Some(110.into()), None));
+
+        let mut tx = TypedTransaction::Legacy(TransactionRequest::new().gas_price(50));
+        let pending = Transaction {
+            gas_price: Some(80.into()),
+            ..Default::default()
+        };
+        match_fees(&mut tx, &pending);
+        bump_fees(&mut tx, 10);
+        assert_eq!(fees(&tx), (Some(88.into()), None));
+    }
+
+    #[test]
+    fn refuses_cancellation_with_small_bump() {
+        let account = EthAccount {
+            network: "mainnet".to_string(),
+            chain_id: 1,
+            derivation_path: vec![],
+            address: "0x0101010101010101010101010101010101010101".to_string(),
+            nonce: None,
+            balance_wei: None,
+            balance_updated_at: None,
+            pending_transactions: vec![],
+        };
+        // Nothing is sent for a bump which nodes would refuse
+        assert_eq!(
+            futures::executor::block_on(cancel_transaction(&account, 7, 5, TxType::Auto)),
+            Err(EthRpcError::InvalidInput(
+                "Fee bump must be at least 10%".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn refuses_value_sent_to_non_payable_function() {
+        let one_ether = U256::exp10(18);
+        assert!(encode_contract_call("weth", "deposit", &[], one_ether).is_ok());
+        assert_eq!(
+            encode_contract_call("weth", "withdraw", &[Token::Uint(one_ether)], one_ether),
+            Err(EthRpcError::InvalidInput(
+                "Cannot send value to non-payable function withdraw".to_string()
+            ))
+        );
+        // Value checks come before argument checks
+        assert_eq!(
+            encode_contract_call("weth", "transfer", &[], U256::one()),
+            Err(EthRpcError::InvalidInput(
+                "Cannot send value to non-payable function transfer".to_string()
+            ))
+        );
+        assert!(matches!(
+            encode_contract_call("weth", "withdraw", &[], U256::zero()),
+            Err(EthRpcError::InvalidInput(_))
+        ));
+        assert_eq!(
+            encode_contract_call("missing", "deposit", &[], U256::zero()),
+            Err(EthRpcError::InvalidInput(
+                "ABI not found: missing".to_string()
+            ))
+        );
+    }
+}
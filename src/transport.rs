@@ -0,0 +1,412 @@
+use futures::future::LocalBoxFuture;
+use ic_cdk::api::call::CallResult;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpResponse, TransformContext,
+};
+use std::cell::RefCell;
+use std::rc::Rc;
+#[cfg(test)]
+use {
+    crate::secrets::redact,
+    ic_cdk::api::call::RejectionCode,
+    serde::{Deserialize, Serialize},
+    serde_json::Value,
+    std::collections::BTreeMap,
+};
+
+/// Sends the HTTP outcalls of JSON-RPC requests.
+///
+/// Outcalls go to the management canister, unless tests install another transport
+/// with `set_transport`, e.g. to serve recorded responses.
+pub trait Transport {
+    fn http_request(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> LocalBoxFuture<'static, CallResult<HttpResponse>>;
+}
+
+/// Outcalls through the management canister's `http_request`
+pub struct IcTransport;
+
+impl Transport for IcTransport {
+    fn http_request(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> LocalBoxFuture<'static, CallResult<HttpResponse>> {
+        Box::pin(async move {
+            http_request(request, cycles)
+                .await
+                .map(|(response,)| response)
+        })
+    }
+}
+
+thread_local! {
+    static TRANSPORT: RefCell<Rc<dyn Transport>> = RefCell::new(Rc::new(IcTransport));
+}
+
+/// Send all following outcalls through a transport
+#[cfg(test)]
+pub fn set_transport(transport: Rc<dyn Transport>) {
+    TRANSPORT.with(|current| *current.borrow_mut() = transport);
+}
+
+/// Transport through which outcalls are currently sent
+pub fn transport() -> Rc<dyn Transport> {
+    TRANSPORT.with(|current| current.borrow().clone())
+}
+
+/// Transform of an outcall, applied by this canister's query method of the given name
+#[cfg(not(test))]
+pub fn transform_context(method: &str, context: Vec<u8>) -> TransformContext {
+    TransformContext::from_name(method.to_string(), context)
+}
+
+// Outside a canister there is no canister id to refer to the transform by
+#[cfg(test)]
+pub fn transform_context(method: &str, context: Vec<u8>) -> TransformContext {
+    use ic_cdk::api::management_canister::http_request::TransformFunc;
+    TransformContext {
+        function: TransformFunc(candid::Func {
+            principal: candid::Principal::anonymous(),
+            method: method.to_string(),
+        }),
+        context,
+    }
+}
+
+#[cfg(test)]
+type Handler = dyn Fn(&CanisterHttpRequestArgument) -> CallResult<HttpResponse>;
+
+/// Transport answering each outcall with a handler, and keeping the requests it was sent
+#[cfg(test)]
+pub struct MockTransport {
+    handler: Box<Handler>,
+    requests: RefCell<Vec<CanisterHttpRequestArgument>>,
+}
+
+#[cfg(test)]
+impl MockTransport {
+    pub fn new(
+        handler: impl Fn(&CanisterHttpRequestArgument) -> CallResult<HttpResponse> + 'static,
+    ) -> Self {
+        Self {
+            handler: Box::new(handler),
+            requests: RefCell::default(),
+        }
+    }
+
+    /// Requests sent so far, oldest first
+    pub fn requests(&self) -> Vec<CanisterHttpRequestArgument> {
+        self.requests.borrow().clone()
+    }
+}
+
+#[cfg(test)]
+impl Transport for MockTransport {
+    fn http_request(
+        &self,
+        request: CanisterHttpRequestArgument,
+        _cycles: u128,
+    ) -> LocalBoxFuture<'static, CallResult<HttpResponse>> {
+        let response = (self.handler)(&request);
+        self.requests.borrow_mut().push(request);
+        Box::pin(async move { response })
+    }
+}
+
+/// A recorded outcall and its response. URLs and responses have their secrets redacted.
+#[cfg(test)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Interaction {
+    pub url: String,
+    pub method: String,
+    pub request: Option<Value>,
+    pub status: u64,
+    pub response: String,
+}
+
+// Request body as JSON (or as a string if it isn't JSON)
+#[cfg(test)]
+fn request_body(request: &CanisterHttpRequestArgument) -> Option<Value> {
+    let body = request.body.as_ref()?;
+    Some(
+        serde_json::from_slice(body)
+            .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(body).into_owned())),
+    )
+}
+
+// JSON-RPC ids of a request or response body, in order (a batch has one per entry)
+#[cfg(test)]
+fn ids(body: &Value) -> Vec<Value> {
+    match body {
+        Value::Array(entries) => entries.iter().flat_map(ids).collect(),
+        Value::Object(object) => object.get("id").cloned().into_iter().collect(),
+        _ => vec![],
+    }
+}
+
+// A body without its JSON-RPC ids, which differ from one run to the next
+#[cfg(test)]
+fn without_ids(body: &Value) -> Value {
+    match body {
+        Value::Array(entries) => Value::Array(entries.iter().map(without_ids).collect()),
+        Value::Object(object) => {
+            let mut object = object.clone();
+            object.remove("id");
+            Value::Object(object)
+        }
+        other => other.clone(),
+    }
+}
+
+// Replace the ids of a response body using a map from recorded to actual ids
+#[cfg(test)]
+fn replace_ids(body: &mut Value, map: &BTreeMap<String, Value>) {
+    match body {
+        Value::Array(entries) => entries.iter_mut().for_each(|entry| replace_ids(entry, map)),
+        Value::Object(object) => {
+            if let Some(id) = object.get_mut("id") {
+                if let Some(actual) = map.get(&id.to_string()) {
+                    *id = actual.clone();
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+#[cfg(test)]
+impl Interaction {
+    fn new(request: &CanisterHttpRequestArgument, response: &HttpResponse) -> Self {
+        Self {
+            url: redact(&request.url),
+            method: format!("{:?}", request.method),
+            request: request_body(request),
+            status: u64::try_from(response.status.0.clone()).unwrap_or_default(),
+            response: redact(&String::from_utf8_lossy(&response.body)),
+        }
+    }
+
+    fn matches(&self, request: &CanisterHttpRequestArgument) -> bool {
+        self.url == redact(&request.url)
+            && self.method == format!("{:?}", request.method)
+            && self.request.as_ref().map(without_ids)
+                == request_body(request).as_ref().map(without_ids)
+    }
+
+    // The recorded response, with the JSON-RPC ids of the request it now answers
+    fn response_to(&self, request: &CanisterHttpRequestArgument) -> HttpResponse {
+        let mut body = self.response.clone().into_bytes();
+        if let (Some(recorded), Some(actual)) = (&self.request, request_body(request)) {
+            if let Ok(mut response) = serde_json::from_str::<Value>(&self.response) {
+                let map = ids(recorded)
+                    .iter()
+                    .map(Value::to_string)
+                    .zip(ids(&actual))
+                    .collect();
+                replace_ids(&mut response, &map);
+                body = serde_json::to_vec(&response).expect("Error encoding response");
+            }
+        }
+        HttpResponse {
+            status: self.status.into(),
+            headers: vec![],
+            body,
+        }
+    }
+}
+
+/// Transport which forwards outcalls to another transport and records each
+/// request with its response, e.g. to capture fixtures from a live provider
+#[cfg(test)]
+pub struct Recorder {
+    inner: Rc<dyn Transport>,
+    interactions: Rc<RefCell<Vec<Interaction>>>,
+}
+
+#[cfg(test)]
+impl Recorder {
+    pub fn new(inner: Rc<dyn Transport>) -> Self {
+        Self {
+            inner,
+            interactions: Rc::default(),
+        }
+    }
+
+    /// Interactions recorded so far, oldest first
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.interactions.borrow().clone()
+    }
+
+    /// The recorded interactions as a JSON fixture, which a `Replayer` can load
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(&*self.interactions.borrow())
+            .expect("Error encoding interactions")
+    }
+
+    /// Write the recorded interactions to a JSON fixture file
+    pub fn save(&self, path: impl AsRef<std::path::Path>) -> std::io::Result<()> {
+        std::fs::write(path, self.to_json())
+    }
+}
+
+#[cfg(test)]
+impl Transport for Recorder {
+    fn http_request(
+        &self,
+        request: CanisterHttpRequestArgument,
+        cycles: u128,
+    ) -> LocalBoxFuture<'static, CallResult<HttpResponse>> {
+        let response = self.inner.http_request(request.clone(), cycles);
+        let interactions = self.interactions.clone();
+        Box::pin(async move {
+            let response = response.await?;
+            interactions
+                .borrow_mut()
+                .push(Interaction::new(&request, &response));
+            Ok(response)
+        })
+    }
+}
+
+/// Transport which answers outcalls from recorded interactions.
+///
+/// Requests are matched by URL, method and body, ignoring JSON-RPC ids, and each
+/// recorded interaction answers one request. Responses are given the ids of the
+/// requests they answer.
+#[cfg(test)]
+pub struct Replayer {
+    interactions: RefCell<Vec<Option<Interaction>>>,
+}
+
+#[cfg(test)]
+impl Replayer {
+    pub fn new(interactions: Vec<Interaction>) -> Self {
+        Self {
+            interactions: RefCell::new(interactions.into_iter().map(Some).collect()),
+        }
+    }
+
+    /// Load interactions from a JSON fixture written by a `Recorder`
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        Ok(Self::new(serde_json::from_str(json)?))
+    }
+
+    /// Load interactions from a JSON fixture file
+    pub fn load(path: impl AsRef<std::path::Path>) -> std::io::Result<Self> {
+        let json = std::fs::read_to_string(path)?;
+        Ok(Self::from_json(&json)?)
+    }
+
+    /// Number of recorded interactions which haven't answered a request yet
+    pub fn remaining(&self) -> usize {
+        self.interactions.borrow().iter().flatten().count()
+    }
+}
+
+#[cfg(test)]
+impl Transport for Replayer {
+    fn http_request(
+        &self,
+        request: CanisterHttpRequestArgument,
+        _cycles: u128,
+    ) -> LocalBoxFuture<'static, CallResult<HttpResponse>> {
+        let interaction = self
+            .interactions
+            .borrow_mut()
+            .iter_mut()
+            .find(|interaction| {
+                interaction
+                    .as_ref()
+                    .is_some_and(|interaction| interaction.matches(&request))
+            })
+            .and_then(Option::take);
+        let response = match interaction {
+            Some(interaction) => Ok(interaction.response_to(&request)),
+            None => Err((
+                RejectionCode::SysFatal,
+                format!(
+                    "No recorded interaction for {:?} {}",
+                    request.method,
+                    redact(&request.url)
+                ),
+            )),
+        };
+        Box::pin(async move { response })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock;
+    use crate::eth_rpc;
+    use futures::executor::block_on;
+
+    fn json_rpc_response(
+        result: Value,
+    ) -> impl Fn(&CanisterHttpRequestArgument) -> CallResult<HttpResponse> {
+        move |request| {
+            let body: Value = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            Ok(HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(&serde_json::json!({
+                    "jsonrpc": "2.0",
+                    "id": body["id"],
+                    "result": result,
+                }))
+                .unwrap(),
+            })
+        }
+    }
+
+    #[test]
+    fn replays_recorded_interactions() {
+        clock::set_now(1_000_000_000);
+        let live = Rc::new(MockTransport::new(json_rpc_response("0x1".into())));
+        let recorder = Rc::new(Recorder::new(live.clone()));
+        set_transport(recorder.clone());
+        assert_eq!(block_on(eth_rpc::chain_id("mainnet")), Ok(1));
+        assert_eq!(live.requests().len(), 1);
+
+        let fixture = std::env::temp_dir().join("ic_eth_replay_fixture.json");
+        recorder.save(&fixture).unwrap();
+        let replayer = Rc::new(Replayer::load(&fixture).unwrap());
+        std::fs::remove_file(&fixture).unwrap();
+        set_transport(replayer.clone());
+        // The replayed request has a different JSON-RPC id than the recorded one
+        assert_eq!(block_on(eth_rpc::chain_id("mainnet")), Ok(1));
+        assert_eq!(replayer.remaining(), 0);
+        assert!(block_on(eth_rpc::chain_id("mainnet")).is_err());
+        set_transport(Rc::new(IcTransport));
+    }
+
+    #[test]
+    fn replay_rewrites_batch_ids() {
+        let recorded = serde_json::json!([{"id": 7, "method": "a"}, {"id": 8, "method": "b"}]);
+        let interaction = Interaction {
+            url: "https://example.com/".to_string(),
+            method: "POST".to_string(),
+            request: Some(recorded),
+            status: 200,
+            response: r#"[{"id":8,"result":"b"},{"id":7,"result":"a"}]"#.to_string(),
+        };
+        let request = CanisterHttpRequestArgument {
+            url: "https://example.com/".to_string(),
+            method: ic_cdk::api::management_canister::http_request::HttpMethod::POST,
+            body: Some(br#"[{"id":1,"method":"a"},{"id":2,"method":"b"}]"#.to_vec()),
+            ..Default::default()
+        };
+        assert!(interaction.matches(&request));
+        let response: Value =
+            serde_json::from_slice(&interaction.response_to(&request).body).unwrap();
+        assert_eq!(
+            response,
+            serde_json::json!([{"id": 2, "result": "b"}, {"id": 1, "result": "a"}])
+        );
+    }
+}
@@ -0,0 +1,18 @@
+//! Types of the canister's Candid interface, in one place so that the generated
+//! interface (and client bindings) can be reviewed as a whole.
+
+pub use crate::abis::AbiSource;
+pub use crate::accounts::{AccountSummary, Derivation};
+pub use crate::audit::{AuditConfig, AuditRecord};
+pub use crate::error::{EthRpcError, Overload};
+pub use crate::eth_rpc::{BlockTag, ContractCallOptions, StateFallback, TxStatus};
+pub use crate::governor::{ProposalState, ProposalSubmission, ProposalSummary, VoteType};
+pub use crate::health::{ProviderHealth, ProviderStatus};
+pub use crate::limiter::LimiterConfig;
+pub use crate::provider_check::ProviderCheck;
+pub use crate::providers::{ArchiveSupport, ChainMetadata, NativeCurrency};
+pub use crate::proxy::ProxyInfo;
+pub use crate::quotes::{Quote, QuoteTerms};
+pub use crate::sequences::{SequencePolicy, SequenceStatus, StepState, StepStatus};
+pub use crate::signing::{Eip712Constraints, SignatureRecord, SigningDomain};
+pub use crate::transactions::TxType;
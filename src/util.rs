@@ -1,4 +1,14 @@
+use ethers_core::abi::{ParamType, Token};
+use ethers_core::k256::ecdsa::{RecoveryId, Signature, VerifyingKey};
+use ethers_core::types::{Address, I256, U256};
+use ethers_core::utils::{keccak256, public_key_to_address, to_checksum};
 use hex::FromHexError;
+use serde_json::Value;
+
+use crate::error::EthRpcError;
+
+// Largest hex-encoded input accepted by the decoding endpoints (in decoded bytes)
+pub const MAX_INPUT_BYTES: usize = 256 * 1024;
 
 pub fn to_hex(data: &[u8]) -> String {
     format!("0x{}", hex::encode(data))
@@ -7,3 +17,472 @@ pub fn to_hex(data: &[u8]) -> String {
 pub fn from_hex(data: &str) -> Result<Vec<u8>, FromHexError> {
     hex::decode(&data[2..])
 }
+
+/// Decode caller-provided hex (with or without a `0x` prefix) of at most `max_bytes`
+pub fn parse_hex_input(data: &str, max_bytes: usize) -> Result<Vec<u8>, EthRpcError> {
+    let digits = data.strip_prefix("0x").unwrap_or(data);
+    if digits.len() / 2 > max_bytes {
+        return Err(EthRpcError::InvalidInput(format!(
+            "Input of {} bytes exceeds the limit of {} bytes",
+            digits.len() / 2,
+            max_bytes
+        )));
+    }
+    hex::decode(digits).map_err(|err| EthRpcError::InvalidInput(format!("Invalid hex: {}", err)))
+}
+
+/// Decode a caller-provided 32-byte hex word
+pub fn parse_word_input(data: &str) -> Result<[u8; 32], EthRpcError> {
+    parse_hex_input(data, 32)?
+        .try_into()
+        .map_err(|_| EthRpcError::InvalidInput(format!("Expected 32 bytes: {}", data)))
+}
+
+/// 4-byte selector of a function signature, e.g. `transfer(address,uint256)` (`0xa9059cbb`)
+pub fn function_selector(signature: &str) -> [u8; 4] {
+    let hash = keccak256(signature);
+    [hash[0], hash[1], hash[2], hash[3]]
+}
+
+/// Whether an item (an address, or a topic) may be in a block or receipt's
+/// `logsBloom`. Bloom filters have false positives: `true` means the item may be
+/// present, while `false` means it certainly isn't.
+pub fn bloom_contains(bloom: &[u8; 256], item: &[u8]) -> bool {
+    let hash = keccak256(item);
+    // Each item sets three of the 2048 bits, given by the first three pairs of hash bytes
+    (0..3).all(|i| {
+        let bit = (usize::from(hash[2 * i]) << 8 | usize::from(hash[2 * i + 1])) & 2047;
+        bloom[255 - bit / 8] & (1 << (bit % 8)) != 0
+    })
+}
+
+/// EIP-191 hash of a personal message (as signed by `personal_sign`):
+/// `keccak256("\x19Ethereum Signed Message:\n" || len(message) || message)`
+pub fn hash_personal_message(message: &[u8]) -> [u8; 32] {
+    let mut prefixed = format!("\x19Ethereum Signed Message:\n{}", message.len()).into_bytes();
+    prefixed.extend_from_slice(message);
+    keccak256(prefixed)
+}
+
+/// Recover the address which signed a 32-byte message hash. The signature is
+/// `r || s || v`, with the recovery id `v` as 0 or 1, or as 27 or 28.
+pub fn recover_signer(
+    message_hash: [u8; 32],
+    signature: &[u8; 65],
+) -> Result<Address, EthRpcError> {
+    let recovery_id = match signature[64] {
+        v @ (0 | 1) => v,
+        v @ (27 | 28) => v - 27,
+        v => {
+            return Err(EthRpcError::InvalidInput(format!(
+                "Invalid signature recovery id: {}",
+                v
+            )))
+        }
+    };
+    let invalid = |err| EthRpcError::InvalidInput(format!("Invalid signature: {}", err));
+    let mut signature = Signature::from_slice(&signature[..64]).map_err(invalid)?;
+    // Recovery only accepts low-S signatures, so flip a high-S signature (and with it
+    // the recovery id) to its equivalent low-S form
+    let recovery_id = match signature.normalize_s() {
+        Some(normalized) => {
+            signature = normalized;
+            recovery_id ^ 1
+        }
+        None => recovery_id,
+    };
+    let recovery_id = RecoveryId::from_byte(recovery_id).expect("Invalid recovery id");
+    let key = VerifyingKey::recover_from_prehash(&message_hash, &signature, recovery_id)
+        .map_err(invalid)?;
+    Ok(public_key_to_address(&key))
+}
+
+/// Whether a signature of a 32-byte message hash was made by the expected address
+pub fn verify_signature(message_hash: [u8; 32], signature: &[u8; 65], expected: Address) -> bool {
+    recover_signer(message_hash, signature).is_ok_and(|signer| signer == expected)
+}
+
+/// Rendering of unsigned integers as JSON strings
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HexOrDecimal {
+    /// Decimal strings, e.g. `"255"`
+    #[default]
+    Decimal,
+    /// Hex quantities as in the JSON-RPC wire format, e.g. `"0xff"`
+    Hex,
+}
+
+/// Options for rendering tokens as JSON
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct JsonOutputFormat {
+    pub uint_as: HexOrDecimal,
+}
+
+/// Render a token as JSON, for frontends and off-chain tools. Integers are decimal
+/// strings (so JavaScript doesn't lose precision), addresses are checksummed and
+/// bytes are `0x`-hex. Arrays and tuples are JSON arrays.
+pub fn token_to_json(token: &Token) -> Value {
+    token_to_json_with(token, JsonOutputFormat::default())
+}
+
+/// Render a token as JSON like [`token_to_json`], with unsigned integers rendered
+/// as the format requires. Signed integers are always decimal strings.
+pub fn token_to_json_with(token: &Token, format: JsonOutputFormat) -> Value {
+    match token {
+        Token::Address(address) => Value::String(to_checksum(address, None)),
+        Token::Uint(n) => Value::String(match format.uint_as {
+            HexOrDecimal::Decimal => n.to_string(),
+            HexOrDecimal::Hex => format!("{:#x}", n),
+        }),
+        Token::Int(n) => Value::String(I256::from_raw(*n).to_string()),
+        Token::Bool(b) => Value::Bool(*b),
+        Token::String(s) => Value::String(s.clone()),
+        Token::Bytes(bytes) | Token::FixedBytes(bytes) => Value::String(to_hex(bytes)),
+        Token::Array(tokens) | Token::FixedArray(tokens) | Token::Tuple(tokens) => Value::Array(
+            tokens
+                .iter()
+                .map(|token| token_to_json_with(token, format))
+                .collect(),
+        ),
+    }
+}
+
+/// Parse JSON into a token of an ABI type, accepting the format of [`token_to_json`].
+/// Integers may also be JSON numbers, and unsigned integers `0x`-hex strings.
+pub fn json_to_token(value: &Value, param_type: &ParamType) -> Result<Token, EthRpcError> {
+    let invalid =
+        || EthRpcError::InvalidInput(format!("Expected {} value, found {}", param_type, value));
+    let elements = |len: Option<usize>| match value.as_array() {
+        Some(values) if len.is_none_or(|len| len == values.len()) => Ok(values),
+        _ => Err(invalid()),
+    };
+    match param_type {
+        ParamType::Address => value
+            .as_str()
+            .and_then(|address| address.parse().ok())
+            .map(Token::Address)
+            .ok_or_else(invalid),
+        ParamType::Uint(bits) => {
+            let n = match value {
+                Value::String(n) => match n.strip_prefix("0x") {
+                    Some(digits) => U256::from_str_radix(digits, 16).ok(),
+                    None => U256::from_dec_str(n).ok(),
+                },
+                Value::Number(n) => n.as_u64().map(U256::from),
+                _ => None,
+            }
+            .ok_or_else(invalid)?;
+            if *bits < 256 && n >> *bits != U256::zero() {
+                return Err(invalid());
+            }
+            Ok(Token::Uint(n))
+        }
+        ParamType::Int(bits) => {
+            let n = match value {
+                Value::String(n) => I256::from_dec_str(n).ok(),
+                Value::Number(n) => n.as_i64().map(I256::from),
+                _ => None,
+            }
+            .ok_or_else(invalid)?;
+            // The magnitude of an intN is below 2^(N-1), or equal to it if negative
+            let limit = U256::one() << (bits - 1);
+            let magnitude = n.unsigned_abs();
+            if *bits < 256 && (magnitude > limit || (magnitude == limit && !n.is_negative())) {
+                return Err(invalid());
+            }
+            Ok(Token::Int(n.into_raw()))
+        }
+        ParamType::Bool => value.as_bool().map(Token::Bool).ok_or_else(invalid),
+        ParamType::String => value
+            .as_str()
+            .map(|s| Token::String(s.to_string()))
+            .ok_or_else(invalid),
+        ParamType::Bytes => {
+            let bytes = value.as_str().ok_or_else(invalid)?;
+            Ok(Token::Bytes(parse_hex_input(bytes, MAX_INPUT_BYTES)?))
+        }
+        ParamType::FixedBytes(len) => {
+            let bytes = parse_hex_input(value.as_str().ok_or_else(invalid)?, *len)?;
+            if bytes.len() != *len {
+                return Err(invalid());
+            }
+            Ok(Token::FixedBytes(bytes))
+        }
+        ParamType::Array(kind) => elements(None)?
+            .iter()
+            .map(|value| json_to_token(value, kind))
+            .collect::<Result<_, _>>()
+            .map(Token::Array),
+        ParamType::FixedArray(kind, len) => elements(Some(*len))?
+            .iter()
+            .map(|value| json_to_token(value, kind))
+            .collect::<Result<_, _>>()
+            .map(Token::FixedArray),
+        ParamType::Tuple(kinds) => elements(Some(kinds.len()))?
+            .iter()
+            .zip(kinds)
+            .map(|(value, kind)| json_to_token(value, kind))
+            .collect::<Result<_, _>>()
+            .map(Token::Tuple),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ethers_core::k256::ecdsa::SigningKey;
+    use ethers_core::k256::elliptic_curve::ops::Neg;
+    use serde_json::json;
+
+    // Key of the account used in web3.js's signing examples
+    const PRIVATE_KEY: &str = "4c0883a69102937d6231471b5dbb6204fe5129617082792ae468d01a3f362318";
+    const ADDRESS: &str = "0x2c7536E3605D9C16a7a3D7b1898e529396a65c23";
+
+    fn sign(message_hash: [u8; 32]) -> (Signature, RecoveryId) {
+        let key = SigningKey::from_slice(&hex::decode(PRIVATE_KEY).unwrap()).unwrap();
+        key.sign_prehash_recoverable(&message_hash).unwrap()
+    }
+
+    fn encode(signature: &Signature, recovery_id: u8) -> [u8; 65] {
+        let mut encoded = [0; 65];
+        encoded[..64].copy_from_slice(&signature.to_bytes());
+        encoded[64] = recovery_id;
+        encoded
+    }
+
+    #[test]
+    fn recovers_low_s_signature() {
+        let message_hash = hash_personal_message(b"hello world");
+        let (signature, recovery_id) = sign(message_hash);
+        assert!(signature.normalize_s().is_none());
+        let expected: Address = ADDRESS.parse().unwrap();
+        for v in [recovery_id.to_byte(), recovery_id.to_byte() + 27] {
+            let encoded = encode(&signature, v);
+            assert_eq!(recover_signer(message_hash, &encoded).unwrap(), expected);
+            assert!(verify_signature(message_hash, &encoded, expected));
+        }
+    }
+
+    #[test]
+    fn recovers_high_s_signature() {
+        let message_hash = hash_personal_message(b"hello world");
+        let (signature, recovery_id) = sign(message_hash);
+        let (r, s) = signature.split_scalars();
+        let high_s = Signature::from_scalars(r, (*s).neg()).unwrap();
+        assert!(high_s.normalize_s().is_some());
+        let encoded = encode(&high_s, (recovery_id.to_byte() ^ 1) + 27);
+        let expected: Address = ADDRESS.parse().unwrap();
+        assert_eq!(recover_signer(message_hash, &encoded).unwrap(), expected);
+        assert!(verify_signature(message_hash, &encoded, expected));
+    }
+
+    #[test]
+    fn rejects_signature_of_other_message() {
+        let (signature, recovery_id) = sign(hash_personal_message(b"hello world"));
+        let encoded = encode(&signature, recovery_id.to_byte());
+        let expected: Address = ADDRESS.parse().unwrap();
+        assert!(!verify_signature(
+            hash_personal_message(b"goodbye"),
+            &encoded,
+            expected
+        ));
+    }
+
+    #[test]
+    fn hashes_personal_messages() {
+        let vectors: [(&[u8], &str); 3] = [
+            (
+                b"hello world",
+                "0xd9eba16ed0ecae432b71fe008c98cc872bb4cc214d3220a36f365326cf807d68",
+            ),
+            (
+                b"",
+                "0x5f35dce98ba4fba25530a026ed80b2cecdaa31091ba4958b99b52ea1d068adad",
+            ),
+            (
+                &[
+                    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16, 17, 18, 19, 20, 21,
+                    22, 23, 24, 25, 26, 27, 28, 29, 30, 31,
+                ],
+                "0x04c3a0e6f47dd8889a200887da01ab4fa88d85f15fb01537cd4b7bcc1ef6f991",
+            ),
+        ];
+        for (message, expected) in vectors {
+            assert_eq!(to_hex(&hash_personal_message(message)), expected);
+            assert_eq!(
+                hash_personal_message(message),
+                ethers_core::utils::hash_message(message).0
+            );
+        }
+    }
+
+    #[test]
+    fn enforces_int_bounds() {
+        let int8 = ParamType::Int(8);
+        for (value, expected) in [("-128", -128), ("127", 127)] {
+            assert_eq!(
+                json_to_token(&json!(value), &int8).unwrap(),
+                Token::Int(I256::from(expected).into_raw())
+            );
+        }
+        for value in [json!("128"), json!("-129"), json!(128), json!("0x7f")] {
+            assert!(json_to_token(&value, &int8).is_err(), "accepted {}", value);
+        }
+        // int256 spans the whole of I256
+        let min = I256::MIN.to_string();
+        assert_eq!(
+            json_to_token(&json!(min), &ParamType::Int(256)).unwrap(),
+            Token::Int(I256::MIN.into_raw())
+        );
+        assert_eq!(token_to_json(&Token::Int(I256::MIN.into_raw())), json!(min));
+    }
+
+    #[test]
+    fn enforces_uint_bounds() {
+        let uint8 = ParamType::Uint(8);
+        assert_eq!(
+            json_to_token(&json!("255"), &uint8).unwrap(),
+            Token::Uint(255.into())
+        );
+        assert_eq!(
+            json_to_token(&json!("0xff"), &uint8).unwrap(),
+            Token::Uint(255.into())
+        );
+        for value in [
+            json!("256"),
+            json!("0x100"),
+            json!(256),
+            json!(-1),
+            json!("-1"),
+        ] {
+            assert!(json_to_token(&value, &uint8).is_err(), "accepted {}", value);
+        }
+        let uint256 = ParamType::Uint(256);
+        assert_eq!(
+            json_to_token(&json!(U256::MAX.to_string()), &uint256).unwrap(),
+            Token::Uint(U256::MAX)
+        );
+        // 2^256
+        let overflow =
+            "115792089237316195423570985008687907853269984665640564039457584007913129639936";
+        assert!(json_to_token(&json!(overflow), &uint256).is_err());
+        assert!(json_to_token(&json!(format!("0x1{}", "0".repeat(64))), &uint256).is_err());
+    }
+
+    #[test]
+    fn checksums_addresses() {
+        // Test vectors from EIP-55
+        for checksummed in [
+            "0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeAed",
+            "0xfB6916095ca1df60bB79Ce92cE3Ea74c37c5d359",
+            "0xdbF03B407c01E7cD3CBea99509d93f8DDDC8C6FB",
+        ] {
+            let address =
+                json_to_token(&json!(checksummed.to_lowercase()), &ParamType::Address).unwrap();
+            assert_eq!(token_to_json(&address), json!(checksummed));
+        }
+        for value in [json!("0x5aAeb6053F3E94C9b9A09f33669435E7Ef1BeA"), json!(1)] {
+            assert!(json_to_token(&value, &ParamType::Address).is_err());
+        }
+    }
+
+    #[test]
+    fn round_trips_tokens_through_json() {
+        let kind = ParamType::Tuple(vec![
+            ParamType::Address,
+            ParamType::Uint(256),
+            ParamType::Int(24),
+            ParamType::Bool,
+            ParamType::String,
+            ParamType::Bytes,
+            ParamType::FixedBytes(4),
+            ParamType::Array(Box::new(ParamType::Uint(8))),
+            ParamType::FixedArray(Box::new(ParamType::Tuple(vec![ParamType::Bool])), 2),
+        ]);
+        let token = Token::Tuple(vec![
+            Token::Address(ADDRESS.parse().unwrap()),
+            Token::Uint(U256::MAX),
+            Token::Int(I256::from(-197310).into_raw()),
+            Token::Bool(true),
+            Token::String("Wrapped Ether".to_string()),
+            Token::Bytes(vec![0xde, 0xad, 0xbe, 0xef, 0x00]),
+            Token::FixedBytes(vec![0xa9, 0x05, 0x9c, 0xbb]),
+            Token::Array(vec![Token::Uint(1.into()), Token::Uint(255.into())]),
+            Token::FixedArray(vec![
+                Token::Tuple(vec![Token::Bool(false)]),
+                Token::Tuple(vec![Token::Bool(true)]),
+            ]),
+        ]);
+
+        let value = token_to_json(&token);
+        assert_eq!(
+            value,
+            json!([
+                ADDRESS,
+                U256::MAX.to_string(),
+                "-197310",
+                true,
+                "Wrapped Ether",
+                "0xdeadbeef00",
+                "0xa9059cbb",
+                ["1", "255"],
+                [[false], [true]],
+            ])
+        );
+        assert_eq!(json_to_token(&value, &kind).unwrap(), token);
+
+        // Hex rendering of unsigned integers parses back too
+        let hex = token_to_json_with(
+            &token,
+            JsonOutputFormat {
+                uint_as: HexOrDecimal::Hex,
+            },
+        );
+        assert_eq!(hex[1], json!(format!("0x{}", "f".repeat(64))));
+        assert_eq!(json_to_token(&hex, &kind).unwrap(), token);
+    }
+
+    // logsBloom that a block (or receipt) whose only log is a WETH `Deposit` has: the
+    // WETH address, the `Deposit(address,uint256)` topic and the indexed `dst` topic.
+    // Computed independently of `bloom_contains`, and checked against `ethbloom` below.
+    const DEPOSIT_BLOOM: &str = concat!(
+        "0x",
+        "0000000000000000000000000000000000000000000020000000000000000000",
+        "0000000000000000000000000000000002000000080000000000000000000000",
+        "0000000000000000000002001000000000000000000000000000000080000000",
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000001000000000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "0000000000000000000000000000000000000000000000000000000000000000",
+        "0000200000000000000000000000000000000000000000400000000000000000",
+    );
+    const WETH: &str = "0xC02aaA39b223FE8D0A0e5C4F27eAD9083C756Cc2";
+    const DEPOSIT_TOPIC: &str =
+        "0xe1fffcc4923d04b559f4d29a8bfc6cda04eb5b0d3c460751c2402c5c5cc9109c";
+    const TRANSFER_TOPIC: &str =
+        "0xddf252ad1be2c89b69c2b068fc378daa952ba7f163c4a11628f55a4df523b3ef";
+
+    #[test]
+    fn checks_logs_bloom_membership() {
+        let bloom: [u8; 256] = from_hex(DEPOSIT_BLOOM).unwrap().try_into().unwrap();
+        let weth = from_hex(WETH).unwrap();
+        let dst = [&[0; 12][..], &from_hex(ADDRESS).unwrap()].concat();
+        for item in [&weth, &from_hex(DEPOSIT_TOPIC).unwrap(), &dst] {
+            assert!(bloom_contains(&bloom, item));
+        }
+        // Neither sets all three of its bits in this bloom
+        assert!(!bloom_contains(&bloom, &from_hex(TRANSFER_TOPIC).unwrap()));
+        assert!(!bloom_contains(
+            &bloom,
+            &from_hex("0x6B175474E89094C44Da98b954EedeAC495271d0F").unwrap()
+        ));
+        assert!(!bloom_contains(&[0; 256], &weth));
+
+        // The same bloom as accrued by the `ethbloom` implementation
+        let mut expected = ethers_core::types::Bloom::zero();
+        for item in [&weth, &from_hex(DEPOSIT_TOPIC).unwrap(), &dst] {
+            expected.accrue(ethers_core::abi::ethereum_types::BloomInput::Raw(item));
+        }
+        assert_eq!(expected.0, bloom);
+    }
+}
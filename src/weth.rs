@@ -0,0 +1,153 @@
+use ethers_core::abi::Token;
+use ethers_core::types::{TransactionRequest, U256};
+
+use crate::accounts::{self, EthAccount};
+use crate::error::EthRpcError;
+use crate::providers::weth_address;
+use crate::sequences;
+use crate::transactions::prepare_contract_tx;
+
+/// Build a transaction wrapping `amount` wei of ether into WETH (`deposit()` with value)
+/// from an account
+pub async fn wrap(account: &EthAccount, amount: U256) -> Result<TransactionRequest, EthRpcError> {
+    let network = &account.network;
+    let address = weth_address(network)
+        .ok_or_else(|| EthRpcError::UnsupportedNetwork(network.to_string()))?;
+    prepare_contract_tx(
+        network,
+        address,
+        "weth",
+        "deposit",
+        &[],
+        amount,
+        Some(account),
+    )
+    .await
+}
+
+/// Build a transaction unwrapping `amount` WETH of an account back into ether
+/// (`withdraw(amount)`)
+pub async fn unwrap(account: &EthAccount, amount: U256) -> Result<TransactionRequest, EthRpcError> {
+    let network = &account.network;
+    let address = weth_address(network)
+        .ok_or_else(|| EthRpcError::UnsupportedNetwork(network.to_string()))?;
+    prepare_contract_tx(
+        network,
+        address,
+        "weth",
+        "withdraw",
+        &[Token::Uint(amount)],
+        U256::zero(),
+        Some(account),
+    )
+    .await
+}
+
+/// Wrap ether of an account into WETH, returning the operation id of the sequence
+/// sending the deposit
+pub async fn submit_wrap(label: &str, amount: U256) -> Result<u64, String> {
+    let account = accounts::account(label)?;
+    let tx = wrap(&account, amount)
+        .await
+        .map_err(|err| err.to_string())?;
+    sequences::submit_transaction(label, tx).await
+}
+
+/// Unwrap WETH of an account into ether, returning the operation id of the sequence
+/// sending the withdrawal
+pub async fn submit_unwrap(label: &str, amount: U256) -> Result<u64, String> {
+    let account = accounts::account(label)?;
+    let tx = unwrap(&account, amount)
+        .await
+        .map_err(|err| err.to_string())?;
+    sequences::submit_transaction(label, tx).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{set_transport, MockTransport};
+    use crate::util::to_hex;
+    use ethers_core::types::Address;
+    use futures::executor::block_on;
+    use ic_cdk::api::management_canister::http_request::HttpResponse;
+    use serde_json::Value;
+    use std::rc::Rc;
+
+    // Answer the simulated `eth_call` successfully, returning its request
+    fn mock_simulation() -> Rc<MockTransport> {
+        let transport = Rc::new(MockTransport::new(|request| {
+            let body: Value = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+            assert_eq!(body["method"], "eth_call");
+            Ok(HttpResponse {
+                status: 200u64.into(),
+                headers: vec![],
+                body: serde_json::to_vec(
+                    &serde_json::json!({"jsonrpc": "2.0", "id": body["id"], "result": "0x"}),
+                )
+                .unwrap(),
+            })
+        }));
+        set_transport(transport.clone());
+        transport
+    }
+
+    fn account(network: &str) -> EthAccount {
+        EthAccount {
+            network: network.to_string(),
+            chain_id: 1,
+            derivation_path: vec![],
+            address: to_hex(Address::repeat_byte(2).as_bytes()),
+            nonce: None,
+            balance_wei: None,
+            balance_updated_at: None,
+            pending_transactions: vec![],
+        }
+    }
+
+    fn simulated_call(transport: &MockTransport) -> Value {
+        let request = transport.requests().pop().unwrap();
+        let body: Value = serde_json::from_slice(request.body.as_ref().unwrap()).unwrap();
+        body["params"][0].clone()
+    }
+
+    #[test]
+    fn wraps_with_deposit() {
+        let transport = mock_simulation();
+        let amount = U256::exp10(18);
+        let tx = block_on(wrap(&account("mainnet"), amount)).unwrap();
+        assert_eq!(tx.to, Some(weth_address("mainnet").unwrap().into()));
+        assert_eq!(tx.data.unwrap().to_vec(), vec![0xd0, 0xe3, 0x0d, 0xb0]);
+        assert_eq!(tx.value, Some(amount));
+        assert_eq!(tx.chain_id, Some(1.into()));
+        // The simulation sends the value as well
+        let call = simulated_call(&transport);
+        assert_eq!(call["data"], "0xd0e30db0");
+        assert_eq!(call["value"], "0xde0b6b3a7640000");
+        // from the account, whose balance pays for it
+        assert_eq!(call["from"], to_hex(Address::repeat_byte(2).as_bytes()));
+    }
+
+    #[test]
+    fn unwraps_with_withdraw() {
+        let transport = mock_simulation();
+        let tx = block_on(unwrap(&account("mainnet"), U256::exp10(18))).unwrap();
+        let expected = concat!(
+            "0x2e1a7d4d",
+            "0000000000000000000000000000000000000000000000000de0b6b3a7640000",
+        );
+        assert_eq!(to_hex(&tx.data.unwrap()), expected);
+        assert_eq!(tx.value, Some(U256::zero()));
+        let call = simulated_call(&transport);
+        assert_eq!(call["data"], expected);
+        assert_eq!(call["from"], to_hex(Address::repeat_byte(2).as_bytes()));
+    }
+
+    #[test]
+    fn refuses_networks_without_weth() {
+        assert_eq!(
+            block_on(wrap(&account("unknown"), U256::one())),
+            Err(EthRpcError::UnsupportedNetwork("unknown".to_string()))
+        );
+    }
+}